@@ -3,4 +3,14 @@
 require "mkmf"
 require "rb_sys/mkmf"
 
-create_rust_makefile("lingua_rs_rb/lingua_rs_rb")
+create_rust_makefile("lingua_rs_rb/lingua_rs_rb") do |r|
+  # LINGUA_RS_RB_LANGUAGES="english,german,french" compiles in only those language models,
+  # using lingua's per-language Cargo features.
+  languages = ENV.fetch("LINGUA_RS_RB_LANGUAGES", "").split(",").map { |l| l.strip.downcase }
+  languages.reject!(&:empty?)
+
+  unless languages.empty?
+    r.extra_cargo_args += ["--no-default-features"]
+    r.features = languages.map { |language| "lingua/#{language}" }
+  end
+end
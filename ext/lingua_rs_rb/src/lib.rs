@@ -1,173 +1,5464 @@
-use magnus::{function, method, prelude::*, Error, Ruby, Value};
-use std::cell::RefCell;
+use magnus::{
+    encoding::{EncodingCapable, RbEncoding},
+    function,
+    gc::Marker,
+    method,
+    prelude::*,
+    r_hash::ForEach,
+    rb_sys::AsRawValue,
+    scan_args::{get_kwargs, scan_args},
+    typed_data::Obj,
+    value::{Lazy, Opaque},
+    DataTypeFunctions, Error, ExceptionClass, Integer, IntoValue, KwArgs, RArray, RClass, RHash,
+    RString, Ruby, Symbol, TryConvert, TypedData, Value,
+};
+use rayon::prelude::*;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use unicode_script::{Script, UnicodeScript};
+use unicode_segmentation::UnicodeSegmentation;
+use std::borrow::Cow;
+use std::collections::{hash_map::DefaultHasher, BTreeMap, BTreeSet, HashMap, HashSet};
+use std::ffi::c_void;
+use std::hash::{Hash, Hasher};
+use std::io::{BufWriter, Write};
+use std::panic::AssertUnwindSafe;
+use std::path::Path;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex, MutexGuard, Once, OnceLock, TryLockError, Weak};
+use std::time::{Duration, Instant};
+
+use lingua::{IsoCode639_1, IsoCode639_3, Language, LanguageDetector, LanguageDetectorBuilder};
+
+static STRICT_LANGUAGE_NAMES: AtomicBool = AtomicBool::new(false);
+static PROCESS_START: OnceLock<Instant> = OnceLock::new();
+static GC_UNLOAD_REGISTRY: Mutex<Vec<(Weak<LanguageDetector>, Weak<ModelResidency>)>> =
+    Mutex::new(Vec::new());
+static GC_UNLOAD_HOOK: Once = Once::new();
+static MAJOR_BY_SYMBOL: AtomicUsize = AtomicUsize::new(0);
+static THREAD_POOL: Mutex<Option<Arc<rayon::ThreadPool>>> = Mutex::new(None);
+static URL_PATTERN: OnceLock<Regex> = OnceLock::new();
+static EMAIL_PATTERN: OnceLock<Regex> = OnceLock::new();
+static SOCIAL_TOKEN_PATTERN: OnceLock<Regex> = OnceLock::new();
+static JUNK_TOKEN_PATTERN: OnceLock<Regex> = OnceLock::new();
+static SENTENCE_PATTERN: OnceLock<Regex> = OnceLock::new();
+static HTML_BLOCK_PATTERN: OnceLock<Regex> = OnceLock::new();
+static HTML_TAG_PATTERN: OnceLock<Regex> = OnceLock::new();
+static HTML_ENTITY_PATTERN: OnceLock<Regex> = OnceLock::new();
+static MARKDOWN_CODE_PATTERN: OnceLock<Regex> = OnceLock::new();
+static MARKDOWN_LINK_PATTERN: OnceLock<Regex> = OnceLock::new();
+static MARKDOWN_SYNTAX_PATTERN: OnceLock<Regex> = OnceLock::new();
+static SERIAL_BATCH_THRESHOLD: AtomicUsize = AtomicUsize::new(DEFAULT_SERIAL_BATCH_THRESHOLD);
+static MODEL_CACHE: Mutex<ModelCache> = Mutex::new(ModelCache {
+    resident: BTreeMap::new(),
+    holders: BTreeMap::new(),
+    hits: 0,
+    misses: 0,
+    evictions: 0,
+});
+
+static DEFAULT_BUILDER_STATE: Mutex<Option<BuilderState>> = Mutex::new(None);
+static DEFAULT_DETECTOR: Mutex<Option<Arc<LanguageDetectorWrapper>>> = Mutex::new(None);
+static PROFILES: Mutex<BTreeMap<String, BuilderState>> = Mutex::new(BTreeMap::new());
+static PROFILE_DETECTORS: Lazy<RHash> = Lazy::new(|ruby| ruby.hash_new());
+static DETECTION_HOOKS: Lazy<RArray> = Lazy::new(|ruby| ruby.ary_new());
+// Holds the tracer set through `LinguaRsRb.tracer=`, if any, as its only element.
+static TRACER: Lazy<RArray> = Lazy::new(|ruby| ruby.ary_new());
+// The custom preprocessors of `PROFILES` by name and of `DEFAULT_BUILDER_STATE` under nil,
+// which no Ruby object owns to mark them.
+static STATE_PREPROCESSORS: Lazy<RHash> = Lazy::new(|ruby| ruby.hash_new());
+
+static UNKNOWN_LANGUAGE_ERROR: Lazy<ExceptionClass> =
+    Lazy::new(|ruby| error_class(ruby, "UnknownLanguageError"));
+static DETECTION_ERROR: Lazy<ExceptionClass> =
+    Lazy::new(|ruby| error_class(ruby, "DetectionError"));
+static INTERNAL_ERROR: Lazy<ExceptionClass> = Lazy::new(|ruby| error_class(ruby, "InternalError"));
+static TIMEOUT_ERROR: Lazy<ExceptionClass> = Lazy::new(|ruby| error_class(ruby, "TimeoutError"));
+static NO_LANGUAGE_DETECTED_ERROR: Lazy<ExceptionClass> =
+    Lazy::new(|ruby| error_class(ruby, "NoLanguageDetectedError"));
+
+// Rough resident size of one language's deserialized n-gram models, derived from
+// lingua's documented totals for all 75 languages (~1 GB high accuracy, ~100 MB low).
+const HIGH_ACCURACY_MODEL_BYTES: usize = 14 * 1024 * 1024;
+const LOW_ACCURACY_MODEL_BYTES: usize = 1536 * 1024;
+
+// IO inputs are read in chunks of this size rather than slurped with a single `read`.
+const IO_READ_CHUNK_BYTES: usize = 64 * 1024;
+
+// Every language lingua supports that is written right to left.
+const RTL_LANGUAGES: &[&str] = &["Arabic", "Hebrew", "Persian", "Urdu"];
+
+// Default groups for `group_confidence_values`; languages outside every group are left out.
+const LANGUAGE_GROUPS: &[(&str, &[&str])] = &[
+    ("Austronesian", &["Indonesian", "Malay", "Maori", "Tagalog"]),
+    ("Baltic", &["Latvian", "Lithuanian"]),
+    ("Bantu", &["Ganda", "Shona", "Sotho", "Swahili", "Tsonga", "Tswana", "Xhosa", "Zulu"]),
+    ("CJK", &["Chinese", "Japanese", "Korean"]),
+    ("Celtic", &["Irish", "Welsh"]),
+    ("Dravidian", &["Tamil", "Telugu"]),
+    ("Germanic", &["Afrikaans", "Dutch", "English", "German"]),
+    ("Indo-Aryan", &["Bengali", "Gujarati", "Hindi", "Marathi", "Punjabi", "Urdu"]),
+    ("Romance", &["Catalan", "French", "Italian", "Latin", "Portuguese", "Romanian", "Spanish"]),
+    ("Scandinavian", &["Bokmal", "Danish", "Icelandic", "Nynorsk", "Swedish"]),
+    ("Semitic", &["Arabic", "Hebrew"]),
+    (
+        "Slavic",
+        &[
+            "Belarusian", "Bosnian", "Bulgarian", "Croatian", "Czech", "Macedonian", "Polish",
+            "Russian", "Serbian", "Slovak", "Slovene", "Ukrainian",
+        ],
+    ),
+    ("Turkic", &["Azerbaijani", "Kazakh", "Turkish"]),
+    ("Uralic", &["Estonian", "Finnish", "Hungarian"]),
+];
+
+// Language names in each display locale after CLDR, keyed by ISO 639-1 code.
+const DISPLAY_LOCALES: &[&str] = &["en", "de", "fr", "es"];
+const DISPLAY_NAMES: &[(&str, [&str; 4])] = &[
+    ("af", ["Afrikaans", "Afrikaans", "afrikaans", "afrikáans"]),
+    ("sq", ["Albanian", "Albanisch", "albanais", "albanés"]),
+    ("ar", ["Arabic", "Arabisch", "arabe", "árabe"]),
+    ("hy", ["Armenian", "Armenisch", "arménien", "armenio"]),
+    ("az", ["Azerbaijani", "Aserbaidschanisch", "azerbaïdjanais", "azerbaiyano"]),
+    ("eu", ["Basque", "Baskisch", "basque", "euskera"]),
+    ("be", ["Belarusian", "Belarussisch", "biélorusse", "bielorruso"]),
+    ("bn", ["Bangla", "Bengalisch", "bengali", "bengalí"]),
+    ("nb", ["Norwegian Bokmål", "Norwegisch (Bokmål)", "norvégien bokmål", "noruego bokmal"]),
+    ("bs", ["Bosnian", "Bosnisch", "bosniaque", "bosnio"]),
+    ("bg", ["Bulgarian", "Bulgarisch", "bulgare", "búlgaro"]),
+    ("ca", ["Catalan", "Katalanisch", "catalan", "catalán"]),
+    ("zh", ["Chinese", "Chinesisch", "chinois", "chino"]),
+    ("hr", ["Croatian", "Kroatisch", "croate", "croata"]),
+    ("cs", ["Czech", "Tschechisch", "tchèque", "checo"]),
+    ("da", ["Danish", "Dänisch", "danois", "danés"]),
+    ("nl", ["Dutch", "Niederländisch", "néerlandais", "neerlandés"]),
+    ("en", ["English", "Englisch", "anglais", "inglés"]),
+    ("eo", ["Esperanto", "Esperanto", "espéranto", "esperanto"]),
+    ("et", ["Estonian", "Estnisch", "estonien", "estonio"]),
+    ("fi", ["Finnish", "Finnisch", "finnois", "finés"]),
+    ("fr", ["French", "Französisch", "français", "francés"]),
+    ("lg", ["Ganda", "Ganda", "ganda", "ganda"]),
+    ("ka", ["Georgian", "Georgisch", "géorgien", "georgiano"]),
+    ("de", ["German", "Deutsch", "allemand", "alemán"]),
+    ("el", ["Greek", "Griechisch", "grec", "griego"]),
+    ("gu", ["Gujarati", "Gujarati", "goudjarati", "guyaratí"]),
+    ("he", ["Hebrew", "Hebräisch", "hébreu", "hebreo"]),
+    ("hi", ["Hindi", "Hindi", "hindi", "hindi"]),
+    ("hu", ["Hungarian", "Ungarisch", "hongrois", "húngaro"]),
+    ("is", ["Icelandic", "Isländisch", "islandais", "islandés"]),
+    ("id", ["Indonesian", "Indonesisch", "indonésien", "indonesio"]),
+    ("ga", ["Irish", "Irisch", "irlandais", "irlandés"]),
+    ("it", ["Italian", "Italienisch", "italien", "italiano"]),
+    ("ja", ["Japanese", "Japanisch", "japonais", "japonés"]),
+    ("kk", ["Kazakh", "Kasachisch", "kazakh", "kazajo"]),
+    ("ko", ["Korean", "Koreanisch", "coréen", "coreano"]),
+    ("la", ["Latin", "Latein", "latin", "latín"]),
+    ("lv", ["Latvian", "Lettisch", "letton", "letón"]),
+    ("lt", ["Lithuanian", "Litauisch", "lituanien", "lituano"]),
+    ("mk", ["Macedonian", "Mazedonisch", "macédonien", "macedonio"]),
+    ("ms", ["Malay", "Malaiisch", "malais", "malayo"]),
+    ("mi", ["Māori", "Māori", "maori", "maorí"]),
+    ("mr", ["Marathi", "Marathi", "marathi", "maratí"]),
+    ("mn", ["Mongolian", "Mongolisch", "mongol", "mongol"]),
+    ("nn", ["Norwegian Nynorsk", "Norwegisch (Nynorsk)", "norvégien nynorsk", "noruego nynorsk"]),
+    ("fa", ["Persian", "Persisch", "persan", "persa"]),
+    ("pl", ["Polish", "Polnisch", "polonais", "polaco"]),
+    ("pt", ["Portuguese", "Portugiesisch", "portugais", "portugués"]),
+    ("pa", ["Punjabi", "Punjabi", "pendjabi", "punyabí"]),
+    ("ro", ["Romanian", "Rumänisch", "roumain", "rumano"]),
+    ("ru", ["Russian", "Russisch", "russe", "ruso"]),
+    ("sr", ["Serbian", "Serbisch", "serbe", "serbio"]),
+    ("sn", ["Shona", "Shona", "shona", "shona"]),
+    ("sk", ["Slovak", "Slowakisch", "slovaque", "eslovaco"]),
+    ("sl", ["Slovenian", "Slowenisch", "slovène", "esloveno"]),
+    ("so", ["Somali", "Somali", "somali", "somalí"]),
+    ("st", ["Southern Sotho", "Süd-Sotho", "sotho du Sud", "sotho meridional"]),
+    ("es", ["Spanish", "Spanisch", "espagnol", "español"]),
+    ("sw", ["Swahili", "Suaheli", "swahili", "suajili"]),
+    ("sv", ["Swedish", "Schwedisch", "suédois", "sueco"]),
+    ("tl", ["Tagalog", "Tagalog", "tagalog", "tagalo"]),
+    ("ta", ["Tamil", "Tamil", "tamoul", "tamil"]),
+    ("te", ["Telugu", "Telugu", "télougou", "telugu"]),
+    ("th", ["Thai", "Thailändisch", "thaï", "tailandés"]),
+    ("ts", ["Tsonga", "Tsonga", "tsonga", "tsonga"]),
+    ("tn", ["Tswana", "Tswana", "tswana", "setsuana"]),
+    ("tr", ["Turkish", "Türkisch", "turc", "turco"]),
+    ("uk", ["Ukrainian", "Ukrainisch", "ukrainien", "ucraniano"]),
+    ("ur", ["Urdu", "Urdu", "ourdou", "urdu"]),
+    ("vi", ["Vietnamese", "Vietnamesisch", "vietnamien", "vietnamita"]),
+    ("cy", ["Welsh", "Walisisch", "gallois", "galés"]),
+    ("xh", ["Xhosa", "Xhosa", "xhosa", "xhosa"]),
+    ("yo", ["Yoruba", "Yoruba", "yoruba", "yoruba"]),
+    ("zu", ["Zulu", "Zulu", "zoulou", "zulú"]),
+];
+
+const LANGUAGE_ALIASES: &[(&str, &str)] = &[
+    ("mandarin", "Chinese"),
+    ("cantonese", "Chinese"),
+    ("farsi", "Persian"),
+    ("norwegian", "Bokmal"),
+    ("bokmål", "Bokmal"),
+    ("norwegian bokmal", "Bokmal"),
+    ("norwegian nynorsk", "Nynorsk"),
+    ("filipino", "Tagalog"),
+    ("castilian", "Spanish"),
+    ("flemish", "Dutch"),
+    ("moldovan", "Romanian"),
+    ("slovenian", "Slovene"),
+    ("panjabi", "Punjabi"),
+    ("malaysian", "Malay"),
+    ("luganda", "Ganda"),
+    ("sesotho", "Sotho"),
+    ("setswana", "Tswana"),
+    ("isixhosa", "Xhosa"),
+    ("isizulu", "Zulu"),
+];
+
+#[derive(TypedData)]
+#[magnus(class = "LinguaRsRb::LanguageDetector", mark, frozen_shareable)]
+struct LanguageDetectorWrapper {
+    detector: Arc<LanguageDetector>,
+    languages: HashSet<Language>,
+    options: DetectorOptions,
+    residency: Arc<ModelResidency>,
+    result_cache: Option<Mutex<ResultCache>>,
+    stats: DetectionStats,
+    hybrid: Option<HybridRefiner>,
+    fallback: Mutex<Option<Fallback>>,
+}
+
+// Registered with `on_fallback`: replaces `detect_language` results that are nil or, with a
+// threshold, less confident than it. The detector marks the callback.
+#[derive(Clone, Copy)]
+struct Fallback {
+    callback: Opaque<Value>,
+    threshold: Option<f64>,
+}
+
+impl DataTypeFunctions for LanguageDetectorWrapper {
+    // The fallback lock is only ever held briefly and without allocating, so a GC can't
+    // find it taken; trying it just keeps marking from ever blocking.
+    fn mark(&self, marker: &Marker) {
+        self.options.preprocessing.mark(marker);
+        let fallback = try_lock_ignoring_poison(&self.fallback).and_then(|fallback| *fallback);
+        if let Some(fallback) = fallback {
+            marker.mark(Ruby::get().unwrap().get_inner(fallback.callback));
+        }
+    }
+}
+
+// The second pass of the hybrid accuracy mode: a high-accuracy detector consulted only
+// when the low-accuracy pass cannot separate its top two languages by `threshold`. Both
+// detectors draw on lingua's process-wide model cache.
+#[derive(Clone)]
+struct HybridRefiner {
+    detector: Arc<LanguageDetector>,
+    threshold: f64,
+    minimum_relative_distance: f64,
+}
+
+impl HybridRefiner {
+    fn detect(&self, lingua: &LanguageDetector, text: &str) -> Option<Language> {
+        self.select(&lingua.compute_language_confidence_values(text), text)
+    }
+
+    // `detect` over the confidence values `text` has already been scored with.
+    fn select(&self, values: &[(Language, f64)], text: &str) -> Option<Language> {
+        let gap = match values {
+            [(_, first), (_, second), ..] => first - second,
+            _ => f64::INFINITY,
+        };
+        if gap < self.threshold {
+            return self.detector.detect_language_of(text);
+        }
+        most_likely_language(values, self.minimum_relative_distance).map(|(language, _)| language)
+    }
+}
+
+fn detect_with(
+    hybrid: Option<&HybridRefiner>,
+    lingua: &LanguageDetector,
+    text: &str,
+) -> Option<Language> {
+    match hybrid {
+        Some(hybrid) => hybrid.detect(lingua, text),
+        None => lingua.detect_language_of(text),
+    }
+}
+
+// Counters behind `LanguageDetector#stats`; relaxed atomics, since they are only ever
+// summed and read for reporting.
+#[derive(Default)]
+struct DetectionStats {
+    detections: AtomicU64,
+    cache_hits: AtomicU64,
+    native_calls: AtomicU64,
+    native_nanos: AtomicU64,
+    undetermined: AtomicU64,
+    languages: Vec<Language>,
+    per_language: Vec<AtomicU64>,
+}
+
+impl DetectionStats {
+    fn new(languages: &HashSet<Language>) -> Self {
+        let mut languages: Vec<Language> = languages.iter().copied().collect();
+        languages.sort();
+        let per_language = languages.iter().map(|_| AtomicU64::new(0)).collect();
+        Self {
+            languages,
+            per_language,
+            ..Self::default()
+        }
+    }
+
+    fn record_native_time(&self, started: Instant) {
+        let nanos = u64::try_from(started.elapsed().as_nanos()).unwrap_or(u64::MAX);
+        self.native_calls.fetch_add(1, Ordering::Relaxed);
+        self.native_nanos.fetch_add(nanos, Ordering::Relaxed);
+    }
+
+    fn record_result(&self, language: Option<Language>) {
+        let counter = match language {
+            Some(language) => match self.languages.binary_search(&language) {
+                Ok(index) => &self.per_language[index],
+                Err(_) => return,
+            },
+            None => &self.undetermined,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn reset(&self) {
+        let counters = [
+            &self.detections,
+            &self.cache_hits,
+            &self.native_calls,
+            &self.native_nanos,
+            &self.undetermined,
+        ];
+        for counter in counters.into_iter().chain(&self.per_language) {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+// Least-recently-used `detect_language` results, keyed by a hash of the text.
+struct ResultCache {
+    capacity: usize,
+    entries: HashMap<u64, (Option<Language>, u64)>,
+    recency: BTreeMap<u64, u64>,
+    tick: u64,
+}
+
+impl ResultCache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: HashMap::with_capacity(capacity),
+            recency: BTreeMap::new(),
+            tick: 0,
+        }
+    }
+
+    fn get(&mut self, key: u64) -> Option<Option<Language>> {
+        let (language, used) = self.entries.get_mut(&key)?;
+        self.recency.remove(used);
+        self.tick += 1;
+        *used = self.tick;
+        self.recency.insert(self.tick, key);
+        Some(*language)
+    }
+
+    fn insert(&mut self, key: u64, language: Option<Language>) {
+        self.tick += 1;
+        if let Some((_, used)) = self.entries.insert(key, (language, self.tick)) {
+            self.recency.remove(&used);
+        } else if self.entries.len() > self.capacity {
+            if let Some((_, oldest)) = self.recency.pop_first() {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.recency.insert(self.tick, key);
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.recency.clear();
+    }
+}
+
+fn text_hash(text: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    text.hash(&mut hasher);
+    hasher.finish()
+}
+
+struct ModelResidency {
+    languages: HashSet<Language>,
+    low_accuracy: bool,
+    loaded_languages: Mutex<HashSet<Language>>,
+    last_used_millis: AtomicU64,
+    unloaded_since_use: AtomicBool,
+    idle_policy_generation: AtomicU64,
+}
+
+impl ModelResidency {
+    fn record_loaded(&self, languages: &HashSet<Language>) {
+        let mut loaded = lock_ignoring_poison(&self.loaded_languages);
+        let newly_held: Vec<Language> = languages.difference(&loaded).copied().collect();
+        lock_ignoring_poison(&MODEL_CACHE).acquire(languages, &newly_held, self.low_accuracy);
+        loaded.extend(newly_held);
+    }
+
+    fn unload(&self, detector: &LanguageDetector) {
+        let mut loaded = lock_ignoring_poison(&self.loaded_languages);
+        let mut cache = lock_ignoring_poison(&MODEL_CACHE);
+        self.unload_locked(detector, &mut loaded, &mut cache);
+    }
+
+    // Callers lock `loaded_languages` before `MODEL_CACHE`, always in that order.
+    fn unload_locked(
+        &self,
+        detector: &LanguageDetector,
+        loaded: &mut HashSet<Language>,
+        cache: &mut ModelCache,
+    ) {
+        cache.release(loaded);
+        loaded.clear();
+        // lingua drops every model of the detector's languages at once, so skip it while
+        // another detector still holds any of them; they stay resident for that holder.
+        if !cache.is_held(&self.languages) {
+            detector.unload_language_models();
+            cache.evict(&self.languages);
+        }
+        self.unloaded_since_use.store(true, Ordering::Relaxed);
+    }
+}
+
+impl Drop for ModelResidency {
+    fn drop(&mut self) {
+        let loaded = self
+            .loaded_languages
+            .get_mut()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        lock_ignoring_poison(&MODEL_CACHE).release(loaded);
+    }
+}
+
+// Bookkeeping for lingua's process-wide model storage: which languages are resident,
+// and how many detectors currently hold each of them loaded.
+struct ModelCache {
+    resident: BTreeMap<Language, bool>,
+    holders: BTreeMap<Language, usize>,
+    hits: u64,
+    misses: u64,
+    evictions: u64,
+}
+
+impl ModelCache {
+    fn acquire(&mut self, requested: &HashSet<Language>, newly_held: &[Language], low: bool) {
+        for language in requested {
+            match self.resident.get_mut(language) {
+                Some(high_accuracy) => {
+                    self.hits += 1;
+                    *high_accuracy |= !low;
+                }
+                None => {
+                    self.misses += 1;
+                    self.resident.insert(*language, !low);
+                }
+            }
+        }
+        for language in newly_held {
+            *self.holders.entry(*language).or_insert(0) += 1;
+        }
+    }
+
+    fn release(&mut self, languages: &HashSet<Language>) {
+        for language in languages {
+            if let Some(count) = self.holders.get_mut(language) {
+                *count -= 1;
+                if *count == 0 {
+                    self.holders.remove(language);
+                }
+            }
+        }
+    }
+
+    fn is_held(&self, languages: &HashSet<Language>) -> bool {
+        languages
+            .iter()
+            .any(|language| self.holders.contains_key(language))
+    }
+
+    fn evict(&mut self, languages: &HashSet<Language>) {
+        for language in languages {
+            if self.resident.remove(language).is_some() {
+                self.evictions += 1;
+            }
+        }
+    }
+
+    fn estimated_bytes(&self) -> usize {
+        self.resident
+            .values()
+            .map(|&high_accuracy| model_bytes_per_language(!high_accuracy))
+            .sum()
+    }
+}
+
+#[derive(TypedData)]
+#[magnus(class = "LinguaRsRb::LanguageDetectorBuilder", mark)]
+struct LanguageDetectorBuilderWrapper(Mutex<BuilderState>);
+
+impl DataTypeFunctions for LanguageDetectorBuilderWrapper {
+    // Marks the preprocessor of the state being configured, like the detector does. Builder
+    // methods don't allocate while holding the lock either.
+    fn mark(&self, marker: &Marker) {
+        if let Some(state) = try_lock_ignoring_poison(&self.0) {
+            state.options.preprocessing.mark(marker);
+        }
+    }
+}
+
+#[derive(Clone)]
+struct BuilderState {
+    languages: HashSet<Language>,
+    options: DetectorOptions,
+}
+
+#[derive(Clone)]
+struct DetectorOptions {
+    minimum_relative_distance: f64,
+    low_accuracy_mode: bool,
+    preloaded_language_models: bool,
+    high_confidence_threshold: f64,
+    medium_confidence_threshold: f64,
+    invalid_utf8: InvalidUtf8Policy,
+    result_cache_size: usize,
+    preprocessing: Preprocessing,
+    hybrid_threshold: Option<f64>,
+    language_format: LanguageFormat,
+    display_locale: DisplayLocale,
+    language_merges: Vec<(Language, String)>,
+    calibration: Calibration,
+}
+
+// Native cleanup applied to every text after decoding and before detection. A custom Ruby
+// preprocessor runs first, with the GVL held, and is left out of `to_config_h`.
+#[derive(Clone, Default)]
+struct Preprocessing {
+    custom: Option<Opaque<Value>>,
+    markup: Markup,
+    strip_urls: bool,
+    strip_social_tokens: bool,
+    strip_junk: bool,
+    minimum_letters: usize,
+    minimum_text_length: usize,
+    max_text_length: Option<usize>,
+    sampling: SamplingStrategy,
+}
+
+impl Preprocessing {
+    // Every owner of a copy marks the custom preprocessor: detectors, builders, and
+    // `STATE_PREPROCESSORS` for the states kept outside any Ruby object.
+    fn mark(&self, marker: &Marker) {
+        if let Some(custom) = self.custom {
+            marker.mark(Ruby::get().unwrap().get_inner(custom));
+        }
+    }
+
+    // Stripped spans become one space per byte rather than disappearing, so the rest of the
+    // text keeps its byte offsets for `detect_multiple_languages` and the other span methods.
+    fn apply<'a>(&self, mut text: Cow<'a, str>) -> Cow<'a, str> {
+        text = self.markup.strip(text);
+        if self.strip_urls {
+            let urls = pattern(&URL_PATTERN, r"(?i)\b(?:[a-z][a-z0-9+.-]*://|www\.)\S+");
+            let emails = pattern(&EMAIL_PATTERN, r"[\w.+-]+@[\w-]+(?:\.[\w-]+)+");
+            text = blank_out(blank_out(text, urls), emails);
+        }
+        if self.strip_social_tokens {
+            text = blank_out(text, pattern(&SOCIAL_TOKEN_PATTERN, r"\B[@#]\w+"));
+        }
+        if self.strip_junk {
+            let junk = pattern(
+                &JUNK_TOKEN_PATTERN,
+                concat!(
+                    r"[\p{Extended_Pictographic}\p{Emoji_Modifier}\p{Regional_Indicator}",
+                    r"\u{200D}\u{FE0F}]+|\p{N}+|[\p{P}\p{S}]{2,}",
+                ),
+            );
+            text = blank_out(text, junk);
+        }
+        match self.max_text_length {
+            Some(max_length) => self.sampling.sample(text, max_length),
+            None => text,
+        }
+    }
+
+    // Stripping blanks characters out in place, so when only the head is kept no more than
+    // `max_text_length` characters (at most four bytes each) of an IO ever need reading.
+    // Markup and custom preprocessors can shrink the text, so they read it all.
+    fn read_budget(&self) -> Option<usize> {
+        let keeps_head = self.sampling == SamplingStrategy::Head && self.markup == Markup::None;
+        match self.max_text_length {
+            Some(max_length) if keeps_head && self.custom.is_none() => Some(max_length * 4),
+            _ => None,
+        }
+    }
+
+    // Texts that are too short, or have too few letters left after stripping, are
+    // reported as undetermined.
+    fn is_useful(&self, text: &str) -> bool {
+        let length = text.trim().chars().count();
+        let letters = text.chars().filter(|c| c.is_alphabetic());
+        length >= self.minimum_text_length && letters.count() >= self.minimum_letters
+    }
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+enum Markup {
+    #[default]
+    None,
+    Html,
+    Markdown,
+}
+
+impl Markup {
+    fn parse(ruby: &Ruby, value: Value) -> Result<Self, Error> {
+        if value.is_nil() {
+            return Ok(Self::None);
+        }
+        let name: String = value.funcall("to_s", ())?;
+        match name.as_str() {
+            "none" => Ok(Self::None),
+            "html" => Ok(Self::Html),
+            "markdown" => Ok(Self::Markdown),
+            _ => Err(Error::new(
+                ruby.exception_arg_error(),
+                format!("invalid preprocess mode: {name} (expected html, markdown or none)"),
+            )),
+        }
+    }
+
+    fn name(self) -> Option<&'static str> {
+        match self {
+            Self::None => None,
+            Self::Html => Some("html"),
+            Self::Markdown => Some("markdown"),
+        }
+    }
+
+    fn strip<'a>(self, text: Cow<'a, str>) -> Cow<'a, str> {
+        match self {
+            Self::None => text,
+            Self::Html => strip_html(text),
+            Self::Markdown => strip_markdown(text),
+        }
+    }
+}
+
+// Script/style bodies and comments go first so their contents never reach the tag pass.
+// Entities decode to their character padded with spaces to the entity's length.
+fn strip_html(text: Cow<'_, str>) -> Cow<'_, str> {
+    let blocks = pattern(
+        &HTML_BLOCK_PATTERN,
+        r"(?is)<script\b.*?</script\s*>|<style\b.*?</style\s*>|<!--.*?-->",
+    );
+    let tags = pattern(&HTML_TAG_PATTERN, r"(?s)</?[a-zA-Z!][^>]*>");
+    let text = blank_out(blank_out(text, blocks), tags);
+    let entities = pattern(
+        &HTML_ENTITY_PATTERN,
+        r"&(?:#[0-9]{1,7}|#[xX][0-9a-fA-F]{1,6}|[a-zA-Z]{2,8});",
+    );
+    let decode = |caps: &regex::Captures| {
+        let entity = &caps[0];
+        // Padded to the entity's byte length like every other stripped span; a character that
+        // would not fit is blanked instead, so later byte offsets still line up.
+        let mut decoded = decode_html_entity(&entity[1..entity.len() - 1])
+            .filter(|decoded| decoded.len_utf8() <= entity.len())
+            .map_or_else(String::new, String::from);
+        decoded.push_str(&" ".repeat(entity.len() - decoded.len()));
+        decoded
+    };
+    let decoded = match entities.replace_all(&text, decode) {
+        Cow::Borrowed(_) => None,
+        Cow::Owned(decoded) => Some(decoded),
+    };
+    decoded.map_or(text, Cow::Owned)
+}
+
+// Code is removed outright; links and images keep only their visible text.
+fn strip_markdown(text: Cow<'_, str>) -> Cow<'_, str> {
+    let code = pattern(
+        &MARKDOWN_CODE_PATTERN,
+        r"(?ms)^[ \t]*(?:```|~~~).*?(?:^[ \t]*(?:```|~~~)[ \t]*$|\z)|`[^`\n]+`",
+    );
+    let syntax = pattern(
+        &MARKDOWN_SYNTAX_PATTERN,
+        r"(?m)^[ \t]*(?:#{1,6}|>+|[-*+]|\d+[.)])[ \t]|[*_~]{1,3}|^[ \t]*(?:-{3,}|\*{3,})[ \t]*$",
+    );
+    let text = blank_out(text, code);
+    let links = pattern(&MARKDOWN_LINK_PATTERN, r"(!?\[)([^\]\n]*)(\]\([^)\n]*\))");
+    let keep_label = |caps: &regex::Captures| {
+        let blank = |part: &str| " ".repeat(part.len());
+        format!("{}{}{}", blank(&caps[1]), &caps[2], blank(&caps[3]))
+    };
+    let relinked = match links.replace_all(&text, keep_label) {
+        Cow::Borrowed(_) => None,
+        Cow::Owned(relinked) => Some(relinked),
+    };
+    blank_out(relinked.map_or(text, Cow::Owned), syntax)
+}
+
+const SAMPLING_STRATA: usize = 8;
+
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+enum SamplingStrategy {
+    #[default]
+    Head,
+    RandomSentences,
+    Stratified,
+}
+
+impl SamplingStrategy {
+    fn parse(ruby: &Ruby, value: Value) -> Result<Self, Error> {
+        let name: String = value.funcall("to_s", ())?;
+        match name.as_str() {
+            "head" => Ok(Self::Head),
+            "random_sentences" => Ok(Self::RandomSentences),
+            "stratified" => Ok(Self::Stratified),
+            _ => Err(Error::new(
+                ruby.exception_arg_error(),
+                format!(
+                    "invalid sampling strategy: {name} \
+                     (expected head, random_sentences or stratified)"
+                ),
+            )),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Head => "head",
+            Self::RandomSentences => "random_sentences",
+            Self::Stratified => "stratified",
+        }
+    }
+
+    // Offsets reported by `detect_multiple_languages` refer to the sample, which only
+    // lines up with the original text under `:head`.
+    fn sample<'a>(self, text: Cow<'a, str>, max_length: usize) -> Cow<'a, str> {
+        if text.chars().nth(max_length).is_none() {
+            return text;
+        }
+        Cow::Owned(match self {
+            Self::Head => char_prefix(&text, max_length).to_owned(),
+            Self::RandomSentences => sample_sentences(&text, max_length),
+            Self::Stratified => sample_strata(&text, max_length),
+        })
+    }
+}
+
+fn char_prefix(text: &str, chars: usize) -> &str {
+    match text.char_indices().nth(chars) {
+        Some((end, _)) => &text[..end],
+        None => text,
+    }
+}
+
+// Sentences are drawn in an order shuffled by a seed taken from the text itself, so a
+// text always yields the same sample, and are rejoined in document order.
+fn sample_sentences(text: &str, max_length: usize) -> String {
+    let sentences: Vec<&str> = pattern(&SENTENCE_PATTERN, r"[^.!?。！？\n]+[.!?。！？]*")
+        .find_iter(text)
+        .map(|sentence| sentence.as_str().trim())
+        .filter(|sentence| !sentence.is_empty())
+        .collect();
+    let mut order: Vec<usize> = (0..sentences.len()).collect();
+    let mut state = text_hash(text) | 1;
+    for i in (1..order.len()).rev() {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        order.swap(i, (state % (i as u64 + 1)) as usize);
+    }
+
+    let mut budget = max_length;
+    let mut picked = Vec::new();
+    for index in order {
+        let length = sentences[index].chars().count();
+        if length <= budget {
+            budget = budget.saturating_sub(length + 1);
+            picked.push(index);
+        }
+    }
+    if picked.is_empty() {
+        return char_prefix(text, max_length).to_owned();
+    }
+    picked.sort_unstable();
+    let picked: Vec<&str> = picked.into_iter().map(|index| sentences[index]).collect();
+    picked.join(" ")
+}
+
+// Equal windows taken from evenly spaced points across the whole text.
+fn sample_strata(text: &str, max_length: usize) -> String {
+    let strata = SAMPLING_STRATA.min(max_length).max(1);
+    let window = max_length / strata;
+    let windows: Vec<&str> = (0..strata)
+        .map(|stratum| {
+            let mut start = stratum * text.len() / strata;
+            while !text.is_char_boundary(start) {
+                start -= 1;
+            }
+            char_prefix(&text[start..], window)
+        })
+        .collect();
+    windows.join(" ")
+}
+
+fn decode_html_entity(entity: &str) -> Option<char> {
+    if let Some(code) = entity.strip_prefix('#') {
+        let code = match code.strip_prefix(['x', 'X']) {
+            Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+            None => code.parse().ok()?,
+        };
+        return char::from_u32(code).filter(|c| !c.is_control());
+    }
+    match entity {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        "nbsp" => Some(' '),
+        "shy" => Some('\u{ad}'),
+        "ndash" => Some('–'),
+        "mdash" => Some('—'),
+        "hellip" => Some('…'),
+        "laquo" => Some('«'),
+        "raquo" => Some('»'),
+        "auml" => Some('ä'),
+        "ouml" => Some('ö'),
+        "uuml" => Some('ü'),
+        "Auml" => Some('Ä'),
+        "Ouml" => Some('Ö'),
+        "Uuml" => Some('Ü'),
+        "szlig" => Some('ß'),
+        "eacute" => Some('é'),
+        "egrave" => Some('è'),
+        "agrave" => Some('à'),
+        "ccedil" => Some('ç'),
+        "ntilde" => Some('ñ'),
+        _ => None,
+    }
+}
+
+fn pattern<'a>(cell: &'a OnceLock<Regex>, source: &str) -> &'a Regex {
+    cell.get_or_init(|| Regex::new(source).expect("preprocessing patterns are valid"))
+}
+
+fn blank_out<'a>(text: Cow<'a, str>, pattern: &Regex) -> Cow<'a, str> {
+    let blank = |caps: &regex::Captures| " ".repeat(caps[0].len());
+    let blanked = match pattern.replace_all(&text, blank) {
+        Cow::Borrowed(_) => None,
+        Cow::Owned(blanked) => Some(blanked),
+    };
+    blanked.map_or(text, Cow::Owned)
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+enum LanguageFormat {
+    #[default]
+    Name,
+    Bcp47,
+    DisplayName,
+}
+
+impl LanguageFormat {
+    fn parse(ruby: &Ruby, value: Value) -> Result<Self, Error> {
+        let name: String = value.funcall("to_s", ())?;
+        match name.as_str() {
+            "name" => Ok(Self::Name),
+            "bcp47" => Ok(Self::Bcp47),
+            "display_name" => Ok(Self::DisplayName),
+            _ => Err(Error::new(
+                ruby.exception_arg_error(),
+                format!("invalid language format: {name} (expected name, bcp47 or display_name)"),
+            )),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Name => "name",
+            Self::Bcp47 => "bcp47",
+            Self::DisplayName => "display_name",
+        }
+    }
+}
+
+// Texts with at least this many letters are left alone by the length-aware calibration.
+const CALIBRATION_REFERENCE_LETTERS: f64 = 40.0;
+
+// Temperature scaling raises each confidence to 1/T and renormalizes, so T > 1 tempers
+// overconfident values. The length-aware correction picks T from the text's letter count.
+#[derive(Clone, Copy, Default, PartialEq)]
+enum Calibration {
+    #[default]
+    None,
+    Temperature(f64),
+    LengthAware,
+}
+
+impl Calibration {
+    fn parse(ruby: &Ruby, value: Value) -> Result<Self, Error> {
+        if value.is_kind_of(ruby.class_numeric()) {
+            let temperature = f64::try_convert(value)?;
+            if !temperature.is_finite() || temperature <= 0.0 {
+                return Err(Error::new(
+                    ruby.exception_arg_error(),
+                    "calibration temperature must be a positive number",
+                ));
+            }
+            return Ok(Self::Temperature(temperature));
+        }
+        let name: String = value.funcall("to_s", ())?;
+        match name.as_str() {
+            "none" => Ok(Self::None),
+            "length_aware" => Ok(Self::LengthAware),
+            _ => Err(Error::new(
+                ruby.exception_arg_error(),
+                format!(
+                    "invalid calibration: {name} (expected none, length_aware or a temperature)"
+                ),
+            )),
+        }
+    }
+
+    fn to_value(self, ruby: &Ruby) -> Value {
+        match self {
+            Self::None => ruby.to_symbol("none").as_value(),
+            Self::Temperature(temperature) => temperature.into_value_with(ruby),
+            Self::LengthAware => ruby.to_symbol("length_aware").as_value(),
+        }
+    }
+
+    fn temperature(self, text: &str) -> Option<f64> {
+        match self {
+            Self::None => None,
+            Self::Temperature(temperature) => Some(temperature),
+            Self::LengthAware => {
+                let letters = text.chars().filter(|c| c.is_alphabetic()).count().max(1);
+                Some((CALIBRATION_REFERENCE_LETTERS / letters as f64).sqrt().max(1.0))
+            }
+        }
+    }
+}
+
+fn calibrate(mut values: Vec<(Language, f64)>, temperature: Option<f64>) -> Vec<(Language, f64)> {
+    let Some(temperature) = temperature else {
+        return values;
+    };
+    let mut total = 0.0;
+    for (_, confidence) in &mut values {
+        *confidence = confidence.powf(1.0 / temperature);
+        total += *confidence;
+    }
+    if total > 0.0 {
+        for (_, confidence) in &mut values {
+            *confidence /= total;
+        }
+    }
+    values
+}
+
+// An index into the locale columns of `DISPLAY_NAMES`.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+struct DisplayLocale(usize);
+
+impl DisplayLocale {
+    // Regional variants fall back to their language: "de-AT" and "de_CH" both mean "de".
+    fn parse(ruby: &Ruby, value: Value) -> Result<Self, Error> {
+        let locale: String = value.funcall("to_s", ())?;
+        let language = locale.split(['-', '_']).next().unwrap_or_default();
+        DISPLAY_LOCALES
+            .iter()
+            .position(|supported| supported.eq_ignore_ascii_case(language))
+            .map(Self)
+            .ok_or_else(|| {
+                Error::new(
+                    ruby.exception_arg_error(),
+                    format!(
+                        "unsupported display locale: {locale} (expected one of {})",
+                        DISPLAY_LOCALES.join(", ")
+                    ),
+                )
+            })
+    }
+
+    fn name(self) -> &'static str {
+        DISPLAY_LOCALES[self.0]
+    }
+}
+
+// Turns detected languages into the configured output form. BCP-47 tags take their
+// script subtag from the text the language was detected in, and merged languages are
+// reported under their target label verbatim.
+#[derive(Clone, Copy)]
+struct Labeler<'a> {
+    format: LanguageFormat,
+    locale: DisplayLocale,
+    script: Option<&'static str>,
+    merges: &'a [(Language, String)],
+}
+
+impl<'a> Labeler<'a> {
+    fn new(options: &'a DetectorOptions) -> Self {
+        Self {
+            format: options.language_format,
+            locale: options.display_locale,
+            script: None,
+            merges: &options.language_merges,
+        }
+    }
+
+    fn for_text(self, text: &str) -> Self {
+        let script = match self.format {
+            LanguageFormat::Bcp47 => bcp47_script_subtag(text),
+            LanguageFormat::Name | LanguageFormat::DisplayName => None,
+        };
+        Self { script, ..self }
+    }
+
+    fn label(&self, language: Language) -> String {
+        if let Some((_, target)) = self.merges.iter().find(|(source, _)| *source == language) {
+            return target.clone();
+        }
+        match self.format {
+            LanguageFormat::Name => language.to_string(),
+            LanguageFormat::Bcp47 => bcp47_tag(language, self.script),
+            LanguageFormat::DisplayName => display_name_in(language, self.locale),
+        }
+    }
+
+    // Confidences of languages sharing a label are summed, keeping the largest first.
+    fn merge(&self, values: Vec<(Language, f64)>) -> Vec<(String, f64)> {
+        let mut merged: Vec<(String, f64)> = Vec::with_capacity(values.len());
+        for (language, confidence) in values {
+            let label = self.label(language);
+            match merged.iter_mut().find(|(existing, _)| *existing == label) {
+                Some((_, total)) => *total += confidence,
+                None => merged.push((label, confidence)),
+            }
+        }
+        if !self.merges.is_empty() {
+            merged.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+        }
+        merged
+    }
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+enum InvalidUtf8Policy {
+    #[default]
+    Raise,
+    Scrub,
+    Skip,
+}
+
+impl InvalidUtf8Policy {
+    fn parse(ruby: &Ruby, value: Value) -> Result<Self, Error> {
+        let name: String = value.funcall("to_s", ())?;
+        match name.as_str() {
+            "raise" => Ok(Self::Raise),
+            "scrub" => Ok(Self::Scrub),
+            "skip" => Ok(Self::Skip),
+            _ => Err(Error::new(
+                ruby.exception_arg_error(),
+                format!("invalid invalid_utf8 policy: {name} (expected raise, scrub or skip)"),
+            )),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Raise => "raise",
+            Self::Scrub => "scrub",
+            Self::Skip => "skip",
+        }
+    }
+
+    // Returns `None` when the text should be skipped rather than detected. Valid UTF-8
+    // (and ASCII-only text in ASCII-compatible encodings) is borrowed straight from the
+    // Ruby string, so it is validated without an intermediate copy.
+    //
+    // SAFETY of the borrow: callers hold the GVL and run no Ruby code (and so no GC that
+    // could compact or free the string) until they have copied the returned text out.
+    fn decode<'a>(self, ruby: &Ruby, text: &'a RString) -> Result<Option<Cow<'a, str>>, Error> {
+        let encoding = RbEncoding::from(text.enc_get());
+        let name = encoding.name();
+        let bytes = unsafe { text.as_slice() };
+        if matches!(name, "UTF-8" | "US-ASCII" | "ASCII-8BIT") {
+            return self.decode_utf8(ruby, bytes);
+        }
+
+        match encoding_rs::Encoding::for_label(name.as_bytes()) {
+            Some(source) => self.transcode(ruby, source, bytes),
+            None => Err(Error::new(
+                ruby.exception_encoding_error(),
+                format!("unsupported text encoding {name}; encode the text as UTF-8 first"),
+            )),
+        }
+    }
+
+    fn transcode<'a>(
+        self,
+        ruby: &Ruby,
+        source: &'static encoding_rs::Encoding,
+        bytes: &'a [u8],
+    ) -> Result<Option<Cow<'a, str>>, Error> {
+        if let Some(text) = source.decode_without_bom_handling_and_without_replacement(bytes) {
+            return Ok(Some(text));
+        }
+        match self {
+            Self::Raise => Err(Error::new(
+                ruby.exception_encoding_error(),
+                format!(
+                    "text contains bytes that cannot be converted from {} to UTF-8",
+                    source.name()
+                ),
+            )),
+            Self::Scrub => Ok(Some(source.decode_without_bom_handling(bytes).0)),
+            Self::Skip => Ok(None),
+        }
+    }
+
+    fn decode_utf8<'a>(self, ruby: &Ruby, bytes: &'a [u8]) -> Result<Option<Cow<'a, str>>, Error> {
+        match std::str::from_utf8(bytes) {
+            Ok(text) => Ok(Some(Cow::Borrowed(text))),
+            Err(err) => match self {
+                Self::Raise => Err(Error::new(
+                    ruby.exception_encoding_error(),
+                    format!(
+                        "text is not valid UTF-8 (invalid byte sequence at offset {})",
+                        err.valid_up_to()
+                    ),
+                )),
+                Self::Scrub => Ok(Some(String::from_utf8_lossy(bytes))),
+                Self::Skip => Ok(None),
+            },
+        }
+    }
+}
+
+// Texts that survived decoding, plus which input positions they came from, so results
+// can be spread back out with a placeholder for every skipped input.
+struct DecodedBatch {
+    texts: Vec<String>,
+    kept: Vec<bool>,
+}
+
+impl DecodedBatch {
+    fn scatter<T: Clone>(&self, results: Vec<T>, skipped: T) -> Vec<T> {
+        let mut results = results.into_iter();
+        self.kept
+            .iter()
+            .map(|&kept| {
+                if kept {
+                    results.next().unwrap_or_else(|| skipped.clone())
+                } else {
+                    skipped.clone()
+                }
+            })
+            .collect()
+    }
+}
+
+impl Default for DetectorOptions {
+    fn default() -> Self {
+        Self {
+            minimum_relative_distance: 0.0,
+            low_accuracy_mode: false,
+            preloaded_language_models: false,
+            high_confidence_threshold: 0.75,
+            medium_confidence_threshold: 0.4,
+            invalid_utf8: InvalidUtf8Policy::Raise,
+            result_cache_size: 0,
+            preprocessing: Preprocessing::default(),
+            hybrid_threshold: None,
+            language_format: LanguageFormat::Name,
+            display_locale: DisplayLocale::default(),
+            language_merges: Vec::new(),
+            calibration: Calibration::None,
+        }
+    }
+}
+
+impl DetectorOptions {
+    // The `#inspect` summary shared by builders and detectors.
+    fn summary(&self, languages: usize) -> String {
+        let accuracy = match (self.hybrid_threshold, self.low_accuracy_mode) {
+            (Some(_), _) => "hybrid",
+            (None, true) => "low",
+            (None, false) => "high",
+        };
+        format!(
+            "languages={languages} accuracy={accuracy} minimum_relative_distance={:?} preload={}",
+            self.minimum_relative_distance, self.preloaded_language_models
+        )
+    }
+}
+
+impl Default for LanguageDetectorBuilderWrapper {
+    fn default() -> Self {
+        Self::new(HashSet::new())
+    }
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    languages: Option<Vec<String>>,
+    exclude: Vec<String>,
+    minimum_relative_distance: Option<f64>,
+    accuracy_mode: Option<String>,
+    preload: bool,
+}
+
+// lingua keeps its deserialized models private, so a snapshot records the detector's
+// configuration and resident languages, and restoring replays those model loads.
+#[derive(Deserialize, Serialize)]
+struct DetectorSnapshot {
+    format_version: u32,
+    loaded_languages: Vec<String>,
+    // Everything `to_config_h` returns, under the same names. Version 1 snapshots only
+    // carried the languages, distance, accuracy mode and confidence thresholds.
+    #[serde(flatten)]
+    config: serde_json::Map<String, serde_json::Value>,
+}
+
+const SNAPSHOT_FORMAT_VERSION: u32 = 2;
+
+const DEFAULT_CHUNK_SIZE: usize = 1000;
+const TIMEOUT_CHUNKS_PER_THREAD: usize = 4;
+const DEFAULT_SERIAL_BATCH_THRESHOLD: usize = 4;
+const DEFAULT_HYBRID_THRESHOLD: f64 = 0.1;
+const DEFAULT_EXPLAINED_NGRAMS: usize = 10;
+// lingua's models cover unigrams through fivegrams.
+const MAX_NGRAM_LENGTH: usize = 5;
+
+#[derive(TypedData)]
+#[magnus(class = "LinguaRsRb::DetectorPool", mark)]
+struct DetectorPool {
+    detectors: Vec<Opaque<Value>>,
+    available: Mutex<Vec<Opaque<Value>>>,
+    timeout: Option<Duration>,
+}
+
+impl DataTypeFunctions for DetectorPool {
+    // The pooled detectors are marked with `rb_gc_mark`, which pins them, so GC
+    // compaction never moves a VALUE this struct holds. The builder and detector
+    // wrappers hold no Ruby objects apart from custom preprocessors and fallback blocks,
+    // which they mark the same way, and need no compact callbacks.
+    fn mark(&self, marker: &Marker) {
+        let ruby = Ruby::get().unwrap();
+        for detector in &self.detectors {
+            marker.mark(ruby.get_inner(*detector));
+        }
+    }
+}
+
+#[magnus::wrap(class = "LinguaRsRb::ModelLoadHandle", free_immediately)]
+struct ModelLoadHandle(Arc<Mutex<ModelLoadState>>);
+
+enum ModelLoadState {
+    Pending,
+    Ready,
+    Failed(String),
+}
+
+const WAIT_POLL_INTERVAL: f64 = 0.005;
+
+impl LanguageDetectorBuilderWrapper {
+    fn new(languages: HashSet<Language>) -> Self {
+        Self(Mutex::new(BuilderState {
+            languages,
+            options: DetectorOptions::default(),
+        }))
+    }
+
+    fn initialize_copy(ruby: &Ruby, rb_self: &Self, other: &Self) -> Result<(), Error> {
+        if std::ptr::eq(rb_self, other) {
+            return Ok(());
+        }
+        let state = lock_builder(ruby, other)?.clone();
+        *lock_builder(ruby, rb_self)? = state;
+        Ok(())
+    }
+
+    fn with_minimum_relative_distance(
+        ruby: &Ruby,
+        rb_self: &Self,
+        distance: f64,
+    ) -> Result<Value, Error> {
+        validate_minimum_relative_distance(ruby, distance)?;
+        lock_builder(ruby, rb_self)?.options.minimum_relative_distance = distance;
+        magnus::current_receiver::<Value>()
+    }
+
+    fn with_invalid_utf8_policy(
+        ruby: &Ruby,
+        rb_self: &Self,
+        policy: Value,
+    ) -> Result<Value, Error> {
+        let policy = InvalidUtf8Policy::parse(ruby, policy)?;
+        lock_builder(ruby, rb_self)?.options.invalid_utf8 = policy;
+        magnus::current_receiver::<Value>()
+    }
+
+    fn with_language_format(ruby: &Ruby, rb_self: &Self, format: Value) -> Result<Value, Error> {
+        let format = LanguageFormat::parse(ruby, format)?;
+        lock_builder(ruby, rb_self)?.options.language_format = format;
+        magnus::current_receiver::<Value>()
+    }
+
+    fn with_display_names(ruby: &Ruby, rb_self: &Self, args: &[Value]) -> Result<Value, Error> {
+        let args = scan_args::<(), (Option<Value>,), (), (), (), ()>(args)?;
+        let locale = match args.optional.0 {
+            Some(locale) => DisplayLocale::parse(ruby, locale)?,
+            None => DisplayLocale::default(),
+        };
+        let mut state = lock_builder(ruby, rb_self)?;
+        state.options.language_format = LanguageFormat::DisplayName;
+        state.options.display_locale = locale;
+        magnus::current_receiver::<Value>()
+    }
+
+    fn with_language_merges(ruby: &Ruby, rb_self: &Self, mapping: RHash) -> Result<Value, Error> {
+        let merges = parse_language_merges(ruby, mapping)?;
+        lock_builder(ruby, rb_self)?.options.language_merges = merges;
+        magnus::current_receiver::<Value>()
+    }
+
+    fn with_confidence_calibration(
+        ruby: &Ruby,
+        rb_self: &Self,
+        calibration: Value,
+    ) -> Result<Value, Error> {
+        let calibration = Calibration::parse(ruby, calibration)?;
+        lock_builder(ruby, rb_self)?.options.calibration = calibration;
+        magnus::current_receiver::<Value>()
+    }
+
+    fn with_result_cache(ruby: &Ruby, rb_self: &Self, max_entries: usize) -> Result<Value, Error> {
+        lock_builder(ruby, rb_self)?.options.result_cache_size = max_entries;
+        magnus::current_receiver::<Value>()
+    }
+
+    fn with_confidence_thresholds(
+        ruby: &Ruby,
+        rb_self: &Self,
+        high: f64,
+        medium: f64,
+    ) -> Result<Value, Error> {
+        validate_confidence_thresholds(ruby, high, medium)?;
+        let mut state = lock_builder(ruby, rb_self)?;
+        state.options.high_confidence_threshold = high;
+        state.options.medium_confidence_threshold = medium;
+        magnus::current_receiver::<Value>()
+    }
+
+    fn with_preloaded_language_models(
+        ruby: &Ruby,
+        rb_self: &Self,
+        args: &[Value],
+    ) -> Result<Value, Error> {
+        let enabled = parse_optional_flag(args)?;
+        lock_builder(ruby, rb_self)?.options.preloaded_language_models = enabled;
+        magnus::current_receiver::<Value>()
+    }
+
+    fn with_preprocessing(ruby: &Ruby, rb_self: &Self, mode: Value) -> Result<Value, Error> {
+        let markup = Markup::parse(ruby, mode)?;
+        lock_builder(ruby, rb_self)?.options.preprocessing.markup = markup;
+        magnus::current_receiver::<Value>()
+    }
+
+    fn with_preprocessor(ruby: &Ruby, rb_self: &Self, args: &[Value]) -> Result<Value, Error> {
+        let args = scan_args::<(), (Option<Value>,), (), (), (), ()>(args)?;
+        let preprocessor = match args.optional.0 {
+            Some(preprocessor) => preprocessor,
+            None => ruby.block_proc()?.as_value(),
+        };
+        let preprocessor = parse_preprocessor(ruby, preprocessor)?;
+        lock_builder(ruby, rb_self)?.options.preprocessing.custom = Some(preprocessor);
+        magnus::current_receiver::<Value>()
+    }
+
+    fn with_minimum_text_length(
+        ruby: &Ruby,
+        rb_self: &Self,
+        min_length: usize,
+    ) -> Result<Value, Error> {
+        lock_builder(ruby, rb_self)?.options.preprocessing.minimum_text_length = min_length;
+        magnus::current_receiver::<Value>()
+    }
+
+    fn with_max_text_length(
+        ruby: &Ruby,
+        rb_self: &Self,
+        max_length: Option<usize>,
+    ) -> Result<Value, Error> {
+        validate_max_text_length(ruby, max_length)?;
+        lock_builder(ruby, rb_self)?.options.preprocessing.max_text_length = max_length;
+        magnus::current_receiver::<Value>()
+    }
+
+    fn with_sampling_strategy(
+        ruby: &Ruby,
+        rb_self: &Self,
+        strategy: Value,
+    ) -> Result<Value, Error> {
+        let strategy = SamplingStrategy::parse(ruby, strategy)?;
+        lock_builder(ruby, rb_self)?.options.preprocessing.sampling = strategy;
+        magnus::current_receiver::<Value>()
+    }
+
+    fn with_url_stripping(ruby: &Ruby, rb_self: &Self, args: &[Value]) -> Result<Value, Error> {
+        let enabled = parse_optional_flag(args)?;
+        lock_builder(ruby, rb_self)?.options.preprocessing.strip_urls = enabled;
+        magnus::current_receiver::<Value>()
+    }
+
+    fn with_social_token_stripping(
+        ruby: &Ruby,
+        rb_self: &Self,
+        args: &[Value],
+    ) -> Result<Value, Error> {
+        let enabled = parse_optional_flag(args)?;
+        lock_builder(ruby, rb_self)?.options.preprocessing.strip_social_tokens = enabled;
+        magnus::current_receiver::<Value>()
+    }
+
+    fn with_junk_stripping(ruby: &Ruby, rb_self: &Self, args: &[Value]) -> Result<Value, Error> {
+        let enabled = parse_optional_flag(args)?;
+        lock_builder(ruby, rb_self)?.options.preprocessing.strip_junk = enabled;
+        magnus::current_receiver::<Value>()
+    }
+
+    fn with_minimum_letters(ruby: &Ruby, rb_self: &Self, letters: usize) -> Result<Value, Error> {
+        lock_builder(ruby, rb_self)?.options.preprocessing.minimum_letters = letters;
+        magnus::current_receiver::<Value>()
+    }
+
+    fn with_hybrid_accuracy_mode(
+        ruby: &Ruby,
+        rb_self: &Self,
+        args: &[Value],
+    ) -> Result<Value, Error> {
+        let args = scan_args::<(), (Option<f64>,), (), (), (), ()>(args)?;
+        let threshold = args.optional.0.unwrap_or(DEFAULT_HYBRID_THRESHOLD);
+        validate_hybrid_threshold(ruby, threshold)?;
+        lock_builder(ruby, rb_self)?.options.hybrid_threshold = Some(threshold);
+        magnus::current_receiver::<Value>()
+    }
+
+    fn with_low_accuracy_mode(ruby: &Ruby, rb_self: &Self, args: &[Value]) -> Result<Value, Error> {
+        let enabled = parse_optional_flag(args)?;
+        lock_builder(ruby, rb_self)?.options.low_accuracy_mode = enabled;
+        magnus::current_receiver::<Value>()
+    }
+
+    fn languages(ruby: &Ruby, rb_self: &Self) -> Result<Vec<String>, Error> {
+        Ok(sorted_language_names(&lock_builder(ruby, rb_self)?.languages))
+    }
+
+    fn minimum_relative_distance(ruby: &Ruby, rb_self: &Self) -> Result<f64, Error> {
+        Ok(lock_builder(ruby, rb_self)?.options.minimum_relative_distance)
+    }
+
+    fn is_low_accuracy_mode(ruby: &Ruby, rb_self: &Self) -> Result<bool, Error> {
+        Ok(lock_builder(ruby, rb_self)?.options.low_accuracy_mode)
+    }
+
+    fn is_preloaded_language_models(ruby: &Ruby, rb_self: &Self) -> Result<bool, Error> {
+        Ok(lock_builder(ruby, rb_self)?.options.preloaded_language_models)
+    }
+
+    fn inspect(ruby: &Ruby, rb_self: &Self) -> Result<String, Error> {
+        let state = lock_builder(ruby, rb_self)?;
+        let summary = state.options.summary(state.languages.len());
+        Ok(format!("#<LinguaRsRb::LanguageDetectorBuilder {summary}>"))
+    }
+
+    fn build(ruby: &Ruby, rb_self: &Self) -> Result<LanguageDetectorWrapper, Error> {
+        let state = lock_builder(ruby, rb_self)?.clone();
+        LanguageDetectorWrapper::new(ruby, state.languages, state.options)
+    }
+}
+
+impl LanguageDetectorWrapper {
+    fn new(
+        ruby: &Ruby,
+        languages: HashSet<Language>,
+        options: DetectorOptions,
+    ) -> Result<Self, Error> {
+        // Lingua panics on an empty set, which would otherwise surface as an InternalError.
+        if languages.is_empty() {
+            return Err(Error::new(
+                ruby.exception_arg_error(),
+                "a detector needs at least one language",
+            ));
+        }
+        let residency = Arc::new(ModelResidency {
+            languages: languages.clone(),
+            low_accuracy: options.low_accuracy_mode,
+            loaded_languages: Mutex::new(HashSet::new()),
+            last_used_millis: AtomicU64::new(millis_since_start()),
+            unloaded_since_use: AtomicBool::new(false),
+            idle_policy_generation: AtomicU64::new(0),
+        });
+        let detector = catch_lingua_panic(ruby, || build_detector(&languages, &options))?;
+        let detector = Arc::new(detector);
+        if options.preloaded_language_models {
+            residency.record_loaded(&languages);
+        }
+        let result_cache = (options.result_cache_size > 0)
+            .then(|| Mutex::new(ResultCache::new(options.result_cache_size)));
+        let stats = DetectionStats::new(&languages);
+        let hybrid = match options.hybrid_threshold {
+            Some(threshold) => {
+                let mut refining = options.clone();
+                refining.low_accuracy_mode = false;
+                refining.hybrid_threshold = None;
+                let refiner = catch_lingua_panic(ruby, || build_detector(&languages, &refining))?;
+                Some(HybridRefiner {
+                    detector: Arc::new(refiner),
+                    threshold,
+                    minimum_relative_distance: options.minimum_relative_distance,
+                })
+            }
+            None => None,
+        };
+        Ok(Self {
+            detector,
+            languages,
+            options,
+            residency,
+            result_cache,
+            stats,
+            hybrid,
+            fallback: Mutex::new(None),
+        })
+    }
+
+    // Cache hits return before the GVL is released or any model is touched.
+    fn detect_language_of(
+        &self,
+        ruby: &Ruby,
+        text: String,
+        timeout: Option<Duration>,
+    ) -> Result<Option<Language>, Error> {
+        let key = self.result_cache.as_ref().map(|cache| (cache, text_hash(&text)));
+        if let Some((cache, key)) = key {
+            if let Some(language) = lock_ignoring_poison(cache).get(key) {
+                self.stats.cache_hits.fetch_add(1, Ordering::Relaxed);
+                self.stats.record_result(language);
+                return Ok(language);
+            }
+        }
+        let hybrid = self.hybrid.clone();
+        let language = self.with_lingua_within(ruby, text, timeout, move |lingua, text| {
+            detect_with(hybrid.as_ref(), lingua, text)
+        })?;
+        if let Some((cache, key)) = key {
+            lock_ignoring_poison(cache).insert(key, language);
+        }
+        self.stats.record_result(language);
+        Ok(language)
+    }
+
+    // The batch counterpart of `detect_language_of`, called from the rayon workers.
+    fn detect_cached(&self, lingua: &LanguageDetector, text: &str) -> Option<Language> {
+        let language = match &self.result_cache {
+            None => detect_with(self.hybrid.as_ref(), lingua, text),
+            Some(cache) => {
+                let key = text_hash(text);
+                let cached = lock_ignoring_poison(cache).get(key);
+                if cached.is_some() {
+                    self.stats.cache_hits.fetch_add(1, Ordering::Relaxed);
+                }
+                cached.unwrap_or_else(|| {
+                    let language = detect_with(self.hybrid.as_ref(), lingua, text);
+                    lock_ignoring_poison(cache).insert(key, language);
+                    language
+                })
+            }
+        };
+        self.stats.record_result(language);
+        language
+    }
+
+    fn touch(&self) {
+        self.residency
+            .last_used_millis
+            .store(millis_since_start(), Ordering::Relaxed);
+        self.residency.unloaded_since_use.store(false, Ordering::Relaxed);
+    }
+
+    // Detection runs without the GVL, so `f` must own its texts rather than borrow them
+    // from Ruby strings; `decode_text` and `decode_texts` hand out owned copies.
+    fn with_lingua<T: Send>(
+        &self,
+        ruby: &Ruby,
+        f: impl FnOnce(&LanguageDetector) -> T + Send,
+    ) -> Result<T, Error> {
+        self.touch();
+        let detector = &*self.detector;
+        let started = Instant::now();
+        let result = run_without_gvl(ruby, || f(detector));
+        self.stats.record_native_time(started);
+        result
+    }
+
+    // With a timeout the work runs on the rayon pool while this thread waits for it. lingua
+    // cannot be cancelled mid-text, so a job that overruns finishes in the background and
+    // its result is discarded.
+    fn with_lingua_within<T: Send + 'static>(
+        &self,
+        ruby: &Ruby,
+        text: String,
+        timeout: Option<Duration>,
+        f: impl FnOnce(&LanguageDetector, &str) -> T + Send + 'static,
+    ) -> Result<T, Error> {
+        let Some(timeout) = timeout else {
+            return self.with_lingua(ruby, |lingua| f(lingua, &text));
+        };
+
+        self.touch();
+        let detector = Arc::clone(&self.detector);
+        let (sender, receiver) = mpsc::channel();
+        let job = move || {
+            let work = || in_thread_pool(|| f(&detector, &text));
+            let _ = sender.send(std::panic::catch_unwind(AssertUnwindSafe(work)));
+        };
+        match thread_pool() {
+            Some(pool) => pool.spawn(job),
+            None => rayon::spawn(job),
+        }
+        let started = Instant::now();
+        match receive_within(ruby, receiver, timeout)? {
+            Some(result) => {
+                self.stats.record_native_time(started);
+                result.map_err(|panic| lingua_panic_error(ruby, panic.as_ref()))
+            }
+            None => Err(timeout_error(ruby, timeout)),
+        }
+    }
+
+    fn confidence_values(&self, ruby: &Ruby, text: RString) -> Result<Vec<(Language, f64)>, Error> {
+        self.confidence_values_within(ruby, text, None)
+    }
+
+    fn confidence_values_within(
+        &self,
+        ruby: &Ruby,
+        text: RString,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<(Language, f64)>, Error> {
+        Ok(self.labeled_confidence_values(ruby, text, timeout)?.0)
+    }
+
+    fn labeled_confidence_values(
+        &self,
+        ruby: &Ruby,
+        text: RString,
+        timeout: Option<Duration>,
+    ) -> Result<(Vec<(Language, f64)>, Labeler<'_>), Error> {
+        let Some(text) = self.decode_text(ruby, &text)? else {
+            return Ok((Vec::new(), self.labeler("")));
+        };
+        let labeler = self.labeler(&text);
+        let temperature = self.options.calibration.temperature(&text);
+        let values = self.with_lingua_within(ruby, text, timeout, |lingua, text| {
+            lingua.compute_language_confidence_values(text)
+        })?;
+        Ok((calibrate(values, temperature), labeler))
+    }
+
+    // The confidence `labeled_confidence_values` would report for `language`'s label,
+    // without the decoding and preprocessing `text` has already been through.
+    fn labeled_confidence(
+        &self,
+        ruby: &Ruby,
+        text: &str,
+        language: Language,
+    ) -> Result<f64, Error> {
+        self.with_lingua(ruby, |lingua| self.labeled_confidence_with(lingua, text, language))
+    }
+
+    fn calibrated_confidence_with(
+        &self,
+        lingua: &LanguageDetector,
+        text: &str,
+        language: Language,
+    ) -> f64 {
+        let temperature = self.options.calibration.temperature(text);
+        if temperature.is_none() {
+            return lingua.compute_language_confidence(text, language);
+        }
+        let values = lingua.compute_language_confidence_values(text);
+        calibrate(values, temperature)
+            .into_iter()
+            .find(|(candidate, _)| *candidate == language)
+            .map_or(0.0, |(_, confidence)| confidence)
+    }
+
+    // The calibrated confidence of the label `language` is reported under: with language
+    // merges, the sum over every language sharing that label, as the values methods report.
+    fn labeled_confidence_with(
+        &self,
+        lingua: &LanguageDetector,
+        text: &str,
+        language: Language,
+    ) -> f64 {
+        if self.options.language_merges.is_empty() {
+            return self.calibrated_confidence_with(lingua, text, language);
+        }
+        let labeler = self.labeler(text);
+        let label = labeler.label(language);
+        let values = lingua.compute_language_confidence_values(text);
+        calibrate(values, self.options.calibration.temperature(text))
+            .into_iter()
+            .filter(|(candidate, _)| labeler.label(*candidate) == label)
+            .map(|(_, confidence)| confidence)
+            .sum()
+    }
+
+    // `labeled_confidence_with` over confidence values `text` has already been scored with.
+    fn labeled_confidence_in(
+        &self,
+        values: Vec<(Language, f64)>,
+        text: &str,
+        language: Language,
+    ) -> f64 {
+        let labeler = self.labeler(text);
+        let label = labeler.label(language);
+        let values = calibrate(values, self.options.calibration.temperature(text));
+        labeler
+            .merge(values)
+            .into_iter()
+            .find(|(merged, _)| *merged == label)
+            .map_or(0.0, |(_, confidence)| confidence)
+    }
+
+    // Like `parse_language_value`, but also accepts the target of a language merge.
+    fn parse_labeled_language(&self, ruby: &Ruby, value: Value) -> Result<Language, Error> {
+        let name: String = value.funcall("to_s", ())?;
+        let merged = self.options.language_merges.iter().find(|(_, target)| *target == name);
+        match merged {
+            Some((source, _)) => Ok(*source),
+            None => parse_language_value(ruby, value),
+        }
+    }
+
+    fn labeler(&self, text: &str) -> Labeler<'_> {
+        Labeler::new(&self.options).for_text(text)
+    }
+
+    fn decode_text(&self, ruby: &Ruby, text: &RString) -> Result<Option<String>, Error> {
+        self.stats.detections.fetch_add(1, Ordering::Relaxed);
+        let preprocessing = &self.options.preprocessing;
+        let text = match preprocessing.custom {
+            Some(custom) => {
+                let custom = ractor_callable(ruby, custom, "preprocessor")?;
+                custom.funcall::<_, _, RString>("call", (*text,))?
+            }
+            None => *text,
+        };
+        let text = self.options.invalid_utf8.decode(ruby, &text)?;
+        Ok(text
+            .map(|text| preprocessing.apply(text))
+            .filter(|text| preprocessing.is_useful(text))
+            .map(Cow::into_owned))
+    }
+
+    // Single-text methods accept an IO as well as a String. The IO is read in chunks,
+    // stopping once the read budget is met, with any partial character cut off the end.
+    // The text carries the IO's external encoding, so `decode_text` transcodes it.
+    fn read_text(&self, ruby: &Ruby, input: Value) -> Result<RString, Error> {
+        if let Some(text) = RString::from_value(input) {
+            return Ok(text);
+        }
+        if !input.respond_to("read", false)? {
+            return Err(Error::new(
+                ruby.exception_type_error(),
+                "text must be a String or respond to read",
+            ));
+        }
+        let encoding = if input.respond_to("external_encoding", false)? {
+            input.funcall::<_, _, Option<RbEncoding>>("external_encoding", ())?
+        } else {
+            None
+        };
+        let encoding = encoding.unwrap_or_else(RbEncoding::utf8);
+        let utf8 = matches!(encoding.name(), "UTF-8" | "US-ASCII" | "ASCII-8BIT");
+        let budget = self.options.preprocessing.read_budget();
+        let mut bytes = Vec::new();
+        let read_chunk =
+            || input.funcall::<_, _, Option<RString>>("read", (IO_READ_CHUNK_BYTES,));
+        while let Some(chunk) = read_chunk()? {
+            bytes.extend_from_slice(unsafe { chunk.as_slice() });
+            if budget.is_some_and(|budget| bytes.len() >= budget) {
+                if let Err(err) = std::str::from_utf8(&bytes) {
+                    if utf8 && err.error_len().is_none() {
+                        bytes.truncate(err.valid_up_to());
+                    }
+                }
+                break;
+            }
+        }
+        Ok(RString::enc_new(bytes, encoding))
+    }
+
+    // Text read from files rather than Ruby strings goes through the same decoding and
+    // preprocessing; only a custom preprocessor needs it as a Ruby string.
+    fn decode_native_text(&self, ruby: &Ruby, bytes: &[u8]) -> Result<Option<String>, Error> {
+        if self.options.preprocessing.custom.is_some() {
+            return self.decode_text(ruby, &RString::enc_new(bytes, RbEncoding::utf8()));
+        }
+        self.stats.detections.fetch_add(1, Ordering::Relaxed);
+        let preprocessing = &self.options.preprocessing;
+        Ok(self
+            .options
+            .invalid_utf8
+            .decode_utf8(ruby, bytes)?
+            .map(|text| preprocessing.apply(text))
+            .filter(|text| preprocessing.is_useful(text))
+            .map(Cow::into_owned))
+    }
+
+    // The file-reading counterpart of `detect_languages_in_parallel`: one chunk of raw
+    // texts in, labels (and confidences when asked for) out.
+    fn detect_native_texts(
+        &self,
+        ruby: &Ruby,
+        texts: &[impl AsRef<[u8]>],
+        with_confidence: bool,
+    ) -> Result<Vec<Option<(String, Option<f64>)>>, Error> {
+        let mut batch = DecodedBatch {
+            texts: Vec::with_capacity(texts.len()),
+            kept: Vec::with_capacity(texts.len()),
+        };
+        for text in texts {
+            let text = self.decode_native_text(ruby, text.as_ref())?;
+            batch.kept.push(text.is_some());
+            batch.texts.extend(text);
+        }
+        let detected = self.with_lingua(ruby, |lingua| {
+            map_batch(&batch.texts, |text| {
+                let language = self.detect_cached(lingua, text)?;
+                let confidence = with_confidence
+                    .then(|| self.labeled_confidence_with(lingua, text, language));
+                Some((self.labeler(text).label(language), confidence))
+            })
+        })?;
+        Ok(batch.scatter(detected, None))
+    }
+
+    fn decode_texts(&self, ruby: &Ruby, texts: &[RString]) -> Result<DecodedBatch, Error> {
+        let mut batch = DecodedBatch {
+            texts: Vec::with_capacity(texts.len()),
+            kept: Vec::with_capacity(texts.len()),
+        };
+        for text in texts {
+            let text = self.decode_text(ruby, text)?;
+            batch.kept.push(text.is_some());
+            batch.texts.extend(text);
+        }
+        Ok(batch)
+    }
+
+    // Runs `detect` over `texts` (an Array or any Enumerable) one chunk at a time, so lazy
+    // enumerators are never materialized in full. Skipped inputs yield `skipped`.
+    fn map_text_chunks<T: Clone + Send>(
+        &self,
+        ruby: &Ruby,
+        texts: Value,
+        batch: &BatchOptions,
+        skipped: T,
+        detect: impl Fn(&LanguageDetector, &str) -> T + Send + Sync,
+    ) -> Result<Vec<T>, Error> {
+        let mut results = Vec::new();
+        let outcome = self.for_each_detected_chunk(ruby, texts, batch, skipped, detect, |detected| {
+            results.extend(detected);
+            Ok(())
+        });
+        match outcome {
+            Err(err) if batch.returns_partial_on(ruby, &err) => Ok(results),
+            outcome => outcome.map(|()| results),
+        }
+    }
+
+    // `emit` receives each chunk's results once the chunk is done, and is free to call
+    // back into Ruby.
+    fn for_each_detected_chunk<T: Clone + Send>(
+        &self,
+        ruby: &Ruby,
+        texts: Value,
+        batch: &BatchOptions,
+        skipped: T,
+        detect: impl Fn(&LanguageDetector, &str) -> T + Send + Sync,
+        mut emit: impl FnMut(Vec<T>) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let total = match batch.progress {
+            Some(_) => batch_size_hint(texts)?,
+            None => None,
+        };
+        let mut done = 0;
+        let mut reported = 0;
+        let report = |done: usize| match batch.progress {
+            Some((progress, _)) => progress
+                .funcall::<_, _, Value>("call", (done, total))
+                .map(drop),
+            None => Ok(()),
+        };
+
+        for_each_text_chunk(ruby, texts, batch.chunk_size, |chunk| {
+            let detected = {
+                let batch = self.decode_texts(ruby, chunk)?;
+                let detected = self.with_lingua(ruby, |lingua| {
+                    let (unique, positions) = dedupe_texts(&batch.texts);
+                    let detected = map_batch(&unique, |text| detect(lingua, text));
+                    positions.into_iter().map(|index| detected[index].clone()).collect()
+                })?;
+                batch.scatter(detected, skipped.clone())
+            };
+            emit(detected)?;
+            done += chunk.len();
+            if batch.progress.is_some_and(|(_, every)| done - reported >= every) {
+                report(done)?;
+                reported = done;
+            }
+            check_interrupts()?;
+            match batch.timeout {
+                Some((timeout, deadline)) if Instant::now() >= deadline => {
+                    Err(timeout_error(ruby, timeout))
+                }
+                _ => Ok(()),
+            }
+        })?;
+        if done != reported {
+            report(done)?;
+        }
+        Ok(())
+    }
+
+    fn load(ruby: &Ruby, config: RHash) -> Result<Obj<Self>, Error> {
+        let (languages, options) = config_from_hash(ruby, config)?;
+        wrap_as_receiver(Self::new(ruby, languages, options)?)
+    }
+
+    fn to_config_h(ruby: &Ruby, rb_self: &Self) -> Result<RHash, Error> {
+        config_to_hash(ruby, &rb_self.languages, &rb_self.options)
+    }
+
+    // Detectors are equal when they are configured alike: the same `to_config_h`, the same
+    // custom preprocessor object and the same fallback block and threshold, if any. Loaded
+    // models and statistics don't count.
+    fn equals(ruby: &Ruby, rb_self: &Self, other: Value) -> Result<bool, Error> {
+        let Ok(other) = Obj::<LanguageDetectorWrapper>::try_convert(other) else {
+            return Ok(false);
+        };
+        let preprocessor = |detector: &Self| {
+            let custom = detector.options.preprocessing.custom;
+            custom.map(|custom| ruby.get_inner(custom).as_raw())
+        };
+        let fallback = |detector: &Self| {
+            let fallback = *lock_ignoring_poison(&detector.fallback);
+            fallback.map(|fallback| {
+                let callback = ruby.get_inner(fallback.callback).as_raw();
+                (callback, fallback.threshold.map(f64::to_bits))
+            })
+        };
+        if rb_self.languages != other.languages
+            || preprocessor(rb_self) != preprocessor(&other)
+            || fallback(rb_self) != fallback(&other)
+        {
+            return Ok(false);
+        }
+        let config = Self::to_config_h(ruby, rb_self)?;
+        config.eql(Self::to_config_h(ruby, &other)?)
+    }
+
+    fn hash(ruby: &Ruby, rb_self: &Self) -> Result<Value, Error> {
+        let config = Self::to_config_h(ruby, rb_self)?;
+        let Some(fallback) = *lock_ignoring_poison(&rb_self.fallback) else {
+            return config.funcall("hash", ());
+        };
+        let callback = ruby.get_inner(fallback.callback);
+        let key = ruby.ary_new_from_values(&[
+            config.as_value(),
+            callback.funcall::<_, _, Value>("object_id", ())?,
+            fallback.threshold.into_value_with(ruby),
+        ]);
+        key.funcall("hash", ())
+    }
+
+    // Marshal carries the configuration `to_config_h` reports, and `_load` builds a fresh
+    // detector from it whose models load again as they are used. Ruby callbacks cannot be
+    // marshaled, so detectors with a custom preprocessor or a fallback refuse.
+    fn marshal_dump(ruby: &Ruby, rb_self: &Self, _level: Value) -> Result<Value, Error> {
+        rb_self.ensure_without_callbacks(ruby, "no _dump_data is defined")?;
+        let config = Self::to_config_h(ruby, rb_self)?;
+        marshal_module(ruby)?.funcall("dump", (config,))
+    }
+
+    // Callbacks are Ruby objects, so detectors using them can't be written out.
+    fn ensure_without_callbacks(&self, ruby: &Ruby, message: &str) -> Result<(), Error> {
+        let has_callbacks = self.options.preprocessing.custom.is_some()
+            || lock_ignoring_poison(&self.fallback).is_some();
+        if !has_callbacks {
+            return Ok(());
+        }
+        Err(Error::new(
+            ruby.exception_type_error(),
+            format!("{message} for detectors with a custom preprocessor or fallback"),
+        ))
+    }
+
+    fn marshal_load(ruby: &Ruby, data: RString) -> Result<Obj<Self>, Error> {
+        let config: RHash = marshal_module(ruby)?.funcall("load", (data,))?;
+        Self::load(ruby, config)
+    }
+
+    fn languages(&self) -> Vec<String> {
+        sorted_language_names(&self.languages)
+    }
+
+    fn minimum_relative_distance(&self) -> f64 {
+        self.options.minimum_relative_distance
+    }
+
+    fn is_low_accuracy_mode(&self) -> bool {
+        self.options.low_accuracy_mode
+    }
+
+    fn hybrid_threshold(&self) -> Option<f64> {
+        self.options.hybrid_threshold
+    }
+
+    fn is_preloaded(&self) -> bool {
+        self.options.preloaded_language_models
+    }
+
+    fn inspect(&self) -> String {
+        let summary = self.options.summary(self.languages.len());
+        format!("#<LinguaRsRb::LanguageDetector {summary}>")
+    }
+
+    fn invalid_utf8_policy(ruby: &Ruby, rb_self: &Self) -> Symbol {
+        ruby.to_symbol(rb_self.options.invalid_utf8.name())
+    }
+
+    fn result_cache_size(&self) -> usize {
+        self.options.result_cache_size
+    }
+
+    // `detections` counts every text handed to a detection method, cached or skipped ones
+    // included; `languages` and `undetermined` count `detect_language`-style results only.
+    fn stats(ruby: &Ruby, rb_self: &Self) -> Result<RHash, Error> {
+        let stats = &rb_self.stats;
+        let native_calls = stats.native_calls.load(Ordering::Relaxed);
+        let native_time = stats.native_nanos.load(Ordering::Relaxed) as f64 / 1e9;
+        let average_native_time = if native_calls == 0 {
+            0.0
+        } else {
+            native_time / native_calls as f64
+        };
+        let languages = ruby.hash_new();
+        for (language, count) in stats.languages.iter().zip(&stats.per_language) {
+            let count = count.load(Ordering::Relaxed);
+            if count > 0 {
+                languages.aset(language.to_string(), count)?;
+            }
+        }
+
+        let hash = ruby.hash_new();
+        hash.aset(
+            ruby.to_symbol("detections"),
+            stats.detections.load(Ordering::Relaxed),
+        )?;
+        hash.aset(
+            ruby.to_symbol("cache_hits"),
+            stats.cache_hits.load(Ordering::Relaxed),
+        )?;
+        hash.aset(ruby.to_symbol("native_calls"), native_calls)?;
+        hash.aset(ruby.to_symbol("native_time"), native_time)?;
+        hash.aset(ruby.to_symbol("average_native_time"), average_native_time)?;
+        hash.aset(ruby.to_symbol("languages"), languages)?;
+        hash.aset(
+            ruby.to_symbol("undetermined"),
+            stats.undetermined.load(Ordering::Relaxed),
+        )?;
+        Ok(hash)
+    }
+
+    fn reset_stats(rb_self: &Self) -> Result<Value, Error> {
+        rb_self.stats.reset();
+        magnus::current_receiver::<Value>()
+    }
+
+    // Runs a detection method, then hands its span to `LinguaRsRb.tracer` and reports it to
+    // the `LinguaRsRb.on_detection` hooks, all with the GVL held. `text` is the single
+    // input, or `None` for batches, which report their size instead.
+    fn instrument<T: IntoValue>(
+        &self,
+        ruby: &Ruby,
+        method: &'static str,
+        text: Option<RString>,
+        f: impl FnOnce() -> Result<T, Error>,
+    ) -> Result<Value, Error> {
+        // The hook registry and the tracer belong to the main Ractor; other Ractors skip both.
+        if !on_main_ractor() {
+            return f().map(|result| result.into_value_with(ruby));
+        }
+        let hooks = ruby.get_inner(&DETECTION_HOOKS);
+        let tracer = ruby.get_inner(&TRACER).entry::<Value>(0)?;
+        if hooks.is_empty() && tracer.is_nil() {
+            return f().map(|result| result.into_value_with(ruby));
+        }
+
+        let text_length = text
+            .map(|text| text.funcall::<_, _, usize>("length", ()))
+            .transpose()?;
+        let start_timestamp = epoch_nanos();
+        let started = Instant::now();
+        let outcome = f().map(|result| result.into_value_with(ruby));
+        let duration = started.elapsed();
+        let batch_size = match (text, &outcome) {
+            (None, Ok(result)) => RArray::from_value(*result).map(|results| results.len()),
+            _ => None,
+        };
+
+        if !tracer.is_nil() {
+            let attributes = ruby.hash_new();
+            attributes.aset("lingua_rs_rb.method", method)?;
+            attributes.aset("lingua_rs_rb.languages", self.languages())?;
+            if let Some(text_length) = text_length {
+                attributes.aset("lingua_rs_rb.text_length", text_length)?;
+            }
+            if let Some(batch_size) = batch_size {
+                attributes.aset("lingua_rs_rb.batch_size", batch_size)?;
+            }
+            if let Err(err) = &outcome {
+                attributes.aset("error", true)?;
+                attributes.aset("error.message", err.to_string())?;
+            }
+            record_span(ruby, tracer, method, attributes, start_timestamp, duration)?;
+        }
+
+        let result = outcome?;
+        if !hooks.is_empty() {
+            let event = ruby.hash_new();
+            event.aset(ruby.to_symbol("method"), ruby.to_symbol(method))?;
+            event.aset(ruby.to_symbol("text_length"), text_length)?;
+            event.aset(ruby.to_symbol("batch_size"), batch_size)?;
+            event.aset(ruby.to_symbol("duration"), duration.as_secs_f64())?;
+            event.aset(ruby.to_symbol("result"), result)?;
+            for hook in hooks.to_vec::<Value>()? {
+                hook.funcall::<_, _, Value>("call", (event,))?;
+            }
+        }
+        Ok(result)
+    }
+
+    fn clear_result_cache(rb_self: &Self) -> Result<Value, Error> {
+        if let Some(cache) = &rb_self.result_cache {
+            lock_ignoring_poison(cache).clear();
+        }
+        magnus::current_receiver::<Value>()
+    }
+
+    fn unload_language_models(ruby: &Ruby, rb_self: &Self) -> Result<(), Error> {
+        ensure_not_frozen(ruby)?;
+        rb_self.residency.unload(&rb_self.detector);
+        Ok(())
+    }
+
+    fn unload_models_when_idle(
+        ruby: &Ruby,
+        rb_self: &Self,
+        seconds: Option<f64>,
+    ) -> Result<(), Error> {
+        ensure_not_frozen(ruby)?;
+        let generation = rb_self
+            .residency
+            .idle_policy_generation
+            .fetch_add(1, Ordering::Relaxed)
+            + 1;
+        let Some(seconds) = seconds else {
+            return Ok(());
+        };
+        if !seconds.is_finite() || seconds <= 0.0 {
+            return Err(Error::new(
+                ruby.exception_arg_error(),
+                "idle timeout must be a positive number of seconds",
+            ));
+        }
+
+        let idle_millis = (seconds * 1000.0) as u64;
+        let detector = Arc::downgrade(&rb_self.detector);
+        let residency = Arc::downgrade(&rb_self.residency);
+        let poll = Duration::from_millis(idle_millis.clamp(10, 1000));
+        std::thread::spawn(move || loop {
+            std::thread::sleep(poll);
+            let (Some(detector), Some(residency)) = (detector.upgrade(), residency.upgrade()) else {
+                return;
+            };
+            if residency.idle_policy_generation.load(Ordering::Relaxed) != generation {
+                return;
+            }
+            let idle_for = millis_since_start()
+                .saturating_sub(residency.last_used_millis.load(Ordering::Relaxed));
+            if idle_for >= idle_millis && !residency.unloaded_since_use.load(Ordering::Relaxed) {
+                residency.unload(&detector);
+            }
+        });
+        Ok(())
+    }
+
+    fn unload_models_on_gc(ruby: &Ruby, rb_self: &Self, enabled: bool) -> Result<(), Error> {
+        ensure_not_frozen(ruby)?;
+        let mut registry = lock_ignoring_poison(&GC_UNLOAD_REGISTRY);
+        registry.retain(|(_, residency)| {
+            residency.strong_count() > 0 && !residency.ptr_eq(&Arc::downgrade(&rb_self.residency))
+        });
+        if enabled {
+            registry.push((Arc::downgrade(&rb_self.detector), Arc::downgrade(&rb_self.residency)));
+            drop(registry);
+            install_gc_unload_hook();
+        }
+        Ok(())
+    }
+
+    fn loaded_languages(&self) -> Vec<String> {
+        sorted_language_names(&lock_ignoring_poison(&self.residency.loaded_languages))
+    }
+
+    fn loaded_language_count(&self) -> usize {
+        lock_ignoring_poison(&self.residency.loaded_languages).len()
+    }
+
+    fn model_memory_bytes(&self) -> usize {
+        self.loaded_language_count() * model_bytes_per_language(self.options.low_accuracy_mode)
+    }
+
+    fn prefork_warmup(ruby: &Ruby, rb_self: &Self) -> Result<Value, Error> {
+        rb_self.load_models(ruby, &rb_self.languages)?;
+        // Detecting once forces lingua's remaining lazy statics (alphabets, regexes).
+        rb_self.with_lingua(ruby, |lingua| lingua.detect_language_of("warmup"))?;
+        magnus::current_receiver::<Value>()
+    }
+
+    fn preload_models(ruby: &Ruby, rb_self: &Self, languages: Vec<String>) -> Result<Value, Error> {
+        let languages = rb_self.parse_configured_languages(ruby, languages)?;
+        rb_self.load_models(ruby, &languages)?;
+        magnus::current_receiver::<Value>()
+    }
+
+    fn preload_models_async(
+        ruby: &Ruby,
+        rb_self: &Self,
+        args: &[Value],
+    ) -> Result<ModelLoadHandle, Error> {
+        let args = scan_args::<(), (Option<Vec<String>>,), (), (), (), ()>(args)?;
+        let (languages,) = args.optional;
+        let languages = match languages {
+            Some(languages) => rb_self.parse_configured_languages(ruby, languages)?,
+            None => rb_self.languages.clone(),
+        };
+
+        let state = Arc::new(Mutex::new(ModelLoadState::Pending));
+        let thread_state = Arc::clone(&state);
+        let residency = Arc::clone(&rb_self.residency);
+        let mut options = rb_self.options.clone();
+        options.preloaded_language_models = true;
+
+        std::thread::spawn(move || {
+            let result = std::panic::catch_unwind(|| drop(build_detector(&languages, &options)));
+            *lock_ignoring_poison(&thread_state) = match result {
+                Ok(()) => {
+                    residency.record_loaded(&languages);
+                    ModelLoadState::Ready
+                }
+                Err(panic) => ModelLoadState::Failed(panic_message(panic.as_ref())),
+            };
+        });
+
+        Ok(ModelLoadHandle(state))
+    }
+
+    fn dump(ruby: &Ruby, rb_self: &Self, path: String) -> Result<(), Error> {
+        rb_self.ensure_without_callbacks(ruby, "snapshots can't be written")?;
+        let config = config_to_json(ruby, Self::to_config_h(ruby, rb_self)?.as_value())?;
+        let serde_json::Value::Object(config) = config else {
+            unreachable!("to_config_h returns a Hash")
+        };
+        let snapshot = DetectorSnapshot {
+            format_version: SNAPSHOT_FORMAT_VERSION,
+            loaded_languages: rb_self.loaded_languages(),
+            config,
+        };
+        let contents = serde_json::to_vec(&snapshot).map_err(|err| {
+            Error::new(
+                ruby.exception_runtime_error(),
+                format!("could not encode snapshot: {err}"),
+            )
+        })?;
+        std::fs::write(&path, contents).map_err(|err| {
+            Error::new(
+                ruby.exception_io_error(),
+                format!("could not write snapshot {path}: {err}"),
+            )
+        })
+    }
+
+    fn restore(ruby: &Ruby, path: String) -> Result<Obj<Self>, Error> {
+        let contents = std::fs::read(&path).map_err(|err| {
+            Error::new(
+                ruby.exception_io_error(),
+                format!("could not read snapshot {path}: {err}"),
+            )
+        })?;
+        let snapshot: DetectorSnapshot = serde_json::from_slice(&contents).map_err(|err| {
+            Error::new(
+                ruby.exception_arg_error(),
+                format!("invalid snapshot {path}: {err}"),
+            )
+        })?;
+        if !(1..=SNAPSHOT_FORMAT_VERSION).contains(&snapshot.format_version) {
+            return Err(Error::new(
+                ruby.exception_arg_error(),
+                format!(
+                    "unsupported snapshot format version {} in {path}",
+                    snapshot.format_version
+                ),
+            ));
+        }
+
+        let config = config_from_json(ruby, &serde_json::Value::Object(snapshot.config))?;
+        let (languages, options) = config_from_hash(ruby, RHash::try_convert(config)?)?;
+        let detector = Self::new(ruby, languages, options)?;
+        let loaded = detector.parse_configured_languages(ruby, snapshot.loaded_languages)?;
+        if !loaded.is_empty() {
+            detector.load_models(ruby, &loaded)?;
+        }
+        wrap_as_receiver(detector)
+    }
+
+    fn parse_configured_languages(
+        &self,
+        ruby: &Ruby,
+        languages: Vec<String>,
+    ) -> Result<HashSet<Language>, Error> {
+        let languages: HashSet<Language> = parse_languages(ruby, languages)?.into_iter().collect();
+        let missing = languages
+            .iter()
+            .find(|language| !self.languages.contains(language));
+        if let Some(missing) = missing {
+            return Err(unknown_language_error(
+                ruby,
+                format!("language is not configured for this detector: {missing}"),
+            ));
+        }
+        Ok(languages)
+    }
+
+    fn load_models(&self, ruby: &Ruby, languages: &HashSet<Language>) -> Result<(), Error> {
+        // Models live in lingua's process-wide cache, so building a preloaded detector
+        // for the same languages makes them resident for this detector as well.
+        let mut options = self.options.clone();
+        options.preloaded_language_models = true;
+        catch_lingua_panic(ruby, || drop(build_detector(languages, &options)))?;
+        self.residency.record_loaded(languages);
+        Ok(())
+    }
+
+    fn parse_only(&self, ruby: &Ruby, only: Vec<String>) -> Result<Vec<Language>, Error> {
+        let only = parse_languages(ruby, only)?;
+        let missing = only.iter().find(|language| !self.languages.contains(language));
+        if let Some(missing) = missing {
+            return Err(Error::new(
+                ruby.exception_arg_error(),
+                format!("only: names a language this detector was not built with: {missing}"),
+            ));
+        }
+        Ok(only)
+    }
+
+    fn detect_language(ruby: &Ruby, rb_self: &Self, args: &[Value]) -> Result<Value, Error> {
+        let args = scan_args::<(Value,), (), (), (), RHash, ()>(args)?;
+        let (text,) = args.required;
+        let text = rb_self.read_text(ruby, text)?;
+        rb_self.instrument(ruby, "detect_language", Some(text), || {
+            type Optional = (Option<Vec<String>>, Option<f64>, Option<f64>);
+            let kwargs = get_kwargs::<_, (), Optional, ()>(
+                args.keywords,
+                &[],
+                &["only", "timeout", "min_confidence"],
+            )?;
+            let (only, timeout, min_confidence) = kwargs.optional;
+            let timeout = parse_timeout(ruby, timeout)?;
+            if let Some(min_confidence) = min_confidence {
+                validate_min_confidence(ruby, min_confidence)?;
+            }
+
+            let Some(only) = only else {
+                let Some(decoded) = rb_self.decode_text(ruby, &text)? else {
+                    return rb_self.apply_fallback(ruby, text, None);
+                };
+                let labeler = rb_self.labeler(&decoded);
+                let needs_confidence =
+                    min_confidence.is_some() || rb_self.fallback_threshold().is_some();
+                let scored = needs_confidence.then(|| decoded.clone());
+                let language = rb_self.detect_language_of(ruby, decoded, timeout)?;
+                let confidence = match (language, scored) {
+                    (Some(language), Some(scored)) => {
+                        Some(rb_self.labeled_confidence(ruby, &scored, language)?)
+                    }
+                    _ => None,
+                };
+                let detected = language
+                    .map(|language| (labeler.label(language), confidence))
+                    .filter(|(_, confidence)| meets_min_confidence(*confidence, min_confidence));
+                return rb_self.apply_fallback(ruby, text, detected);
+            };
+
+            let only = rb_self.parse_only(ruby, only)?;
+            let (values, labeler) = rb_self.labeled_confidence_values(ruby, text, timeout)?;
+            let values = restrict_confidence_values(values, &only);
+            let detected =
+                most_likely_language(&values, rb_self.options.minimum_relative_distance);
+            rb_self.stats.record_result(detected.map(|(language, _)| language));
+            let detected = detected
+                .map(|(language, confidence)| (labeler.label(language), Some(confidence)))
+                .filter(|(_, confidence)| meets_min_confidence(*confidence, min_confidence));
+            rb_self.apply_fallback(ruby, text, detected)
+        })
+    }
+
+    // Detection and the candidates for the error message come from one set of confidence
+    // values, so the text is decoded, preprocessed and scored once.
+    fn detect_language_or_raise(
+        ruby: &Ruby,
+        rb_self: &Self,
+        args: &[Value],
+    ) -> Result<Value, Error> {
+        let args = scan_args::<(Value,), (), (), (), RHash, ()>(args)?;
+        let (text,) = args.required;
+        let text = rb_self.read_text(ruby, text)?;
+        let mut candidates = Vec::new();
+        let detected = rb_self.instrument(ruby, "detect_language", Some(text), || {
+            type Optional = (Option<Vec<String>>, Option<f64>, Option<f64>);
+            let kwargs = get_kwargs::<_, (), Optional, ()>(
+                args.keywords,
+                &[],
+                &["only", "timeout", "min_confidence"],
+            )?;
+            let (only, timeout, min_confidence) = kwargs.optional;
+            let timeout = parse_timeout(ruby, timeout)?;
+            if let Some(min_confidence) = min_confidence {
+                validate_min_confidence(ruby, min_confidence)?;
+            }
+            let only = only.map(|only| rb_self.parse_only(ruby, only)).transpose()?;
+
+            let Some(decoded) = rb_self.decode_text(ruby, &text)? else {
+                return rb_self.apply_fallback(ruby, text, None);
+            };
+            let labeler = rb_self.labeler(&decoded);
+            let temperature = rb_self.options.calibration.temperature(&decoded);
+            let scored = decoded.clone();
+            let hybrid = rb_self.hybrid.clone();
+            let minimum_relative_distance = rb_self.options.minimum_relative_distance;
+            let (values, language) =
+                rb_self.with_lingua_within(ruby, decoded, timeout, move |lingua, text| {
+                    let values = lingua.compute_language_confidence_values(text);
+                    let language = match &hybrid {
+                        Some(hybrid) => hybrid.select(&values, text),
+                        None => most_likely_language(&values, minimum_relative_distance)
+                            .map(|(language, _)| language),
+                    };
+                    (values, language)
+                })?;
+
+            let detected = match &only {
+                Some(only) => {
+                    let values = calibrate(values.clone(), temperature);
+                    let values = restrict_confidence_values(values, only);
+                    most_likely_language(&values, minimum_relative_distance)
+                }
+                None => language.map(|language| {
+                    let values = values.clone();
+                    (language, rb_self.labeled_confidence_in(values, &scored, language))
+                }),
+            };
+            rb_self.stats.record_result(detected.map(|(language, _)| language));
+            let options = ConfidenceOptions {
+                top_k: Some(3),
+                min_confidence: Some(f64::MIN_POSITIVE),
+            };
+            candidates =
+                confidence_values_to_tuples(calibrate(values, temperature), &options, &labeler);
+            let detected = detected
+                .map(|(language, confidence)| (labeler.label(language), Some(confidence)))
+                .filter(|(_, confidence)| meets_min_confidence(*confidence, min_confidence));
+            rb_self.apply_fallback(ruby, text, detected)
+        })?;
+        if !detected.is_nil() {
+            return Ok(detected);
+        }
+
+        let length: usize = text.funcall("length", ())?;
+        let candidates: Vec<String> = candidates
+            .into_iter()
+            .map(|(label, confidence)| format!("{label} {confidence:.2}"))
+            .collect();
+        let candidates = if candidates.is_empty() {
+            "none".to_owned()
+        } else {
+            candidates.join(", ")
+        };
+        Err(Error::new(
+            ruby.get_inner(&NO_LANGUAGE_DETECTED_ERROR),
+            format!(
+                "no language detected in text of {length} characters \
+                 (top candidates: {candidates})"
+            ),
+        ))
+    }
+
+    fn on_fallback(ruby: &Ruby, rb_self: &Self, args: &[Value]) -> Result<Value, Error> {
+        ensure_not_frozen(ruby)?;
+        let args = scan_args::<(), (Option<f64>,), (), (), (), ()>(args)?;
+        let (threshold,) = args.optional;
+        if let Some(threshold) = threshold {
+            validate_fallback_threshold(ruby, threshold)?;
+        }
+        let callback = ruby.block_proc()?.as_value();
+        *lock_ignoring_poison(&rb_self.fallback) = Some(Fallback {
+            callback: Opaque::from(callback),
+            threshold,
+        });
+        magnus::current_receiver::<Value>()
+    }
+
+    fn fallback_threshold(&self) -> Option<f64> {
+        lock_ignoring_poison(&self.fallback).and_then(|fallback| fallback.threshold)
+    }
+
+    // `detected` carries the label and, when a threshold is registered, its confidence.
+    fn apply_fallback(
+        &self,
+        ruby: &Ruby,
+        text: RString,
+        detected: Option<(String, Option<f64>)>,
+    ) -> Result<Value, Error> {
+        let fallback = *lock_ignoring_poison(&self.fallback);
+        let confident = match (&detected, fallback.and_then(|fallback| fallback.threshold)) {
+            (Some((_, Some(confidence))), Some(threshold)) => *confidence >= threshold,
+            (detected, _) => detected.is_some(),
+        };
+        match fallback {
+            Some(fallback) if !confident => {
+                let callback = ractor_callable(ruby, fallback.callback, "on_fallback block")?;
+                callback.funcall("call", (text,))
+            }
+            _ => Ok(detected.map(|(label, _)| label).into_value_with(ruby)),
+        }
+    }
+
+    fn detect_language_with_confidence(
+        ruby: &Ruby,
+        rb_self: &Self,
+        args: &[Value],
+    ) -> Result<Value, Error> {
+        let args = scan_args::<(Value,), (), (), (), RHash, ()>(args)?;
+        let (text,) = args.required;
+        let text = rb_self.read_text(ruby, text)?;
+        let kwargs = get_kwargs::<_, (), (Option<bool>, Option<bool>, Option<bool>), ()>(
+            args.keywords,
+            &[],
+            &["script", "direction", "gap"],
+        )?;
+        let (with_script, with_direction, with_gap) = kwargs.optional;
+        let with_script = with_script.unwrap_or(false);
+        let with_direction = with_direction.unwrap_or(false);
+        let with_gap = with_gap.unwrap_or(false);
+
+        rb_self.instrument(ruby, "detect_language_with_confidence", Some(text), || {
+            let text = rb_self.decode_text(ruby, &text)?;
+            let values = match &text {
+                Some(text) => rb_self
+                    .with_lingua(ruby, |lingua| lingua.compute_language_confidence_values(text))?,
+                None => Vec::new(),
+            };
+            let detected = most_likely_language(&values, rb_self.options.minimum_relative_distance);
+            rb_self.stats.record_result(detected.map(|(language, _)| language));
+            let Some((language, confidence)) = detected else {
+                return Ok(ruby.qnil().as_value());
+            };
+            let text = text.as_deref().unwrap_or("");
+            let labeler = rb_self.labeler(text);
+            let label = labeler.label(language);
+            let values = calibrate(values, rb_self.options.calibration.temperature(text));
+            let gap = top_two_gap(&values);
+            let confidence = labeler
+                .merge(values)
+                .into_iter()
+                .find(|(merged, _)| *merged == label)
+                .map_or(confidence, |(_, merged)| merged);
+            let result = LanguageConfidence {
+                script: with_script.then(|| script_code(text)),
+                direction: with_direction.then(|| text_direction(language)),
+                gap: with_gap.then_some(gap),
+                ..LanguageConfidence::new(label, confidence)
+            };
+            Ok(result.into_value_with(ruby))
+        })
+    }
+
+    fn detect_top_languages(
+        ruby: &Ruby,
+        rb_self: &Self,
+        text: Value,
+        n: usize,
+    ) -> Result<Vec<LanguageConfidence>, Error> {
+        let text = rb_self.read_text(ruby, text)?;
+        let options = ConfidenceOptions {
+            top_k: Some(n),
+            min_confidence: None,
+        };
+        let (values, labeler) = rb_self.labeled_confidence_values(ruby, text, None)?;
+        let values = confidence_values_to_tuples(values, &options, &labeler);
+        Ok(LanguageConfidence::from_pairs(values))
+    }
+
+    fn group_confidence_values(
+        ruby: &Ruby,
+        rb_self: &Self,
+        args: &[Value],
+    ) -> Result<Vec<(String, f64)>, Error> {
+        let args = scan_args::<(Value,), (), (), (), RHash, ()>(args)?;
+        let (text,) = args.required;
+        let text = rb_self.read_text(ruby, text)?;
+        let kwargs = get_kwargs::<_, (), (Option<RHash>,), ()>(args.keywords, &[], &["groups"])?;
+        let groups = match kwargs.optional.0 {
+            Some(groups) => parse_language_groups(ruby, groups)?,
+            None => default_language_groups(),
+        };
+        let values = rb_self.confidence_values(ruby, text)?;
+        let mut totals: Vec<(String, f64)> = groups
+            .into_iter()
+            .filter(|(_, members)| values.iter().any(|(language, _)| members.contains(language)))
+            .map(|(group, members)| {
+                let confidence = values
+                    .iter()
+                    .filter(|(language, _)| members.contains(language))
+                    .map(|(_, confidence)| confidence)
+                    .sum();
+                (group, confidence)
+            })
+            .collect();
+        totals.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+        Ok(totals)
+    }
+
+    fn confidence_gap(ruby: &Ruby, rb_self: &Self, text: Value) -> Result<f64, Error> {
+        let text = rb_self.read_text(ruby, text)?;
+        Ok(top_two_gap(&rb_self.confidence_values(ruby, text)?))
+    }
+
+    // One decode and one confidence pass cover every field, where separate calls to
+    // `detect_language_with_confidence`, `reliable?` and `script_of` each rescan the text.
+    fn analyze(ruby: &Ruby, rb_self: &Self, text: Value) -> Result<DetectionAnalysis, Error> {
+        let text = rb_self.read_text(ruby, text)?;
+        let Some(text) = rb_self.decode_text(ruby, &text)? else {
+            rb_self.stats.record_result(None);
+            return Ok(DetectionAnalysis::default());
+        };
+        let values =
+            rb_self.with_lingua(ruby, |lingua| lingua.compute_language_confidence_values(&text))?;
+        let detected = most_likely_language(&values, rb_self.options.minimum_relative_distance);
+        rb_self.stats.record_result(detected.map(|(language, _)| language));
+
+        let labeler = rb_self.labeler(&text);
+        let values = calibrate(values, rb_self.options.calibration.temperature(&text));
+        // The same rule as `reliable?`: the calibrated top-two gap against the distance.
+        let reliable =
+            most_likely_language(&values, rb_self.options.minimum_relative_distance).is_some();
+        let merged = labeler.merge(values);
+        let language = detected.map(|(language, _)| labeler.label(language));
+        let confidence = language
+            .as_ref()
+            .and_then(|label| merged.iter().find(|(merged, _)| merged == label))
+            .map_or(0.0, |(_, confidence)| *confidence);
+        let runner_up = language
+            .as_ref()
+            .and_then(|label| merged.into_iter().find(|(merged, _)| merged != label));
+        Ok(DetectionAnalysis {
+            reliable,
+            language,
+            confidence,
+            script: script_code(&text),
+            runner_up,
+        })
+    }
+
+    // The threshold defaults to the minimum relative distance, which is 0.0 unless the
+    // detector was built with one, so any unambiguous top language counts as reliable.
+    fn is_reliable(ruby: &Ruby, rb_self: &Self, args: &[Value]) -> Result<bool, Error> {
+        let args = scan_args::<(Value,), (), (), (), RHash, ()>(args)?;
+        let (text,) = args.required;
+        let text = rb_self.read_text(ruby, text)?;
+        let kwargs = get_kwargs::<_, (), (Option<f64>,), ()>(args.keywords, &[], &["threshold"])?;
+        let (threshold,) = kwargs.optional;
+        let threshold = threshold.unwrap_or(rb_self.options.minimum_relative_distance);
+
+        let values = rb_self.confidence_values(ruby, text)?;
+        Ok(most_likely_language(&values, threshold).is_some())
+    }
+
+    fn confidence_bucket(ruby: &Ruby, rb_self: &Self, text: Value) -> Result<Symbol, Error> {
+        let text = rb_self.read_text(ruby, text)?;
+        let values = rb_self.confidence_values(ruby, text)?;
+        let options = &rb_self.options;
+        let confidence = most_likely_language(&values, options.minimum_relative_distance)
+            .map_or(0.0, |(_, confidence)| confidence);
+
+        let bucket = if confidence >= options.high_confidence_threshold {
+            "high"
+        } else if confidence >= options.medium_confidence_threshold {
+            "medium"
+        } else {
+            "low"
+        };
+        Ok(ruby.to_symbol(bucket))
+    }
+
+    fn detect_languages_in_parallel(
+        ruby: &Ruby,
+        rb_self: &Self,
+        args: &[Value],
+    ) -> Result<Value, Error> {
+        let args = scan_args::<(Value,), (), (), (), RHash, ()>(args)?;
+        let (texts,) = args.required;
+        let batch = parse_only_batch_options(ruby, args.keywords)?;
+
+        rb_self.instrument(ruby, "detect_languages_in_parallel", None, || {
+            rb_self.map_text_chunks(ruby, texts, &batch, None, |lingua, text| {
+                let language = rb_self.detect_cached(lingua, text);
+                language.map(|language| rb_self.labeler(text).label(language))
+            })
+        })
+    }
+
+    // Tallies languages while the corpus streams through in chunks, so no per-text results
+    // are kept. Texts with no detected language count under nil; the most common come first.
+    fn language_distribution(ruby: &Ruby, rb_self: &Self, args: &[Value]) -> Result<Value, Error> {
+        let args = scan_args::<(Value,), (), (), (), RHash, ()>(args)?;
+        let (texts,) = args.required;
+        let batch = parse_only_batch_options(ruby, args.keywords)?;
+
+        rb_self.instrument(ruby, "language_distribution", None, || {
+            let mut counts: HashMap<Option<String>, usize> = HashMap::new();
+            let mut total = 0;
+            let detect = |lingua: &LanguageDetector, text: &str| {
+                let language = rb_self.detect_cached(lingua, text);
+                language.map(|language| rb_self.labeler(text).label(language))
+            };
+            let tally = |detected: Vec<Option<String>>| {
+                total += detected.len();
+                for label in detected {
+                    *counts.entry(label).or_insert(0) += 1;
+                }
+                Ok(())
+            };
+            let outcome = rb_self.for_each_detected_chunk(ruby, texts, &batch, None, detect, tally);
+            if let Err(err) = outcome {
+                if !batch.returns_partial_on(ruby, &err) {
+                    return Err(err);
+                }
+            }
+
+            let mut counts: Vec<_> = counts.into_iter().collect();
+            counts.sort_by(|(a, a_count), (b, b_count)| {
+                b_count.cmp(a_count).then_with(|| a.cmp(b))
+            });
+            let distribution = ruby.hash_new();
+            for (label, count) in counts {
+                let entry = ruby.hash_new();
+                entry.aset(ruby.to_symbol("count"), count)?;
+                entry.aset(ruby.to_symbol("percentage"), ratio(count, total) * 100.0)?;
+                distribution.aset(label, entry)?;
+            }
+            Ok(distribution)
+        })
+    }
+
+    // Keys come back in the hash's own order and may be of any type; the texts go through
+    // the same chunked batch as `detect_languages_in_parallel`.
+    fn detect_languages_keyed(ruby: &Ruby, rb_self: &Self, args: &[Value]) -> Result<Value, Error> {
+        let args = scan_args::<(RHash,), (), (), (), RHash, ()>(args)?;
+        let (texts,) = args.required;
+        let (batch, rest) = parse_batch_options(ruby, args.keywords)?;
+        let kwargs = get_kwargs::<_, (), (Option<bool>,), ()>(rest, &[], &["confidence"])?;
+        let with_confidence = kwargs.optional.0.unwrap_or(false);
+
+        let keys = ruby.ary_new_capa(texts.len());
+        let values = ruby.ary_new_capa(texts.len());
+        texts.foreach(|key: Value, text: Value| {
+            keys.push(key)?;
+            values.push(text)?;
+            Ok(ForEach::Continue)
+        })?;
+
+        rb_self.instrument(ruby, "detect_languages_keyed", None, || {
+            let results =
+                rb_self.map_text_chunks(ruby, values.as_value(), &batch, None, |lingua, text| {
+                    let language = rb_self.detect_cached(lingua, text)?;
+                    let confidence = with_confidence
+                        .then(|| rb_self.labeled_confidence_with(lingua, text, language));
+                    Some((rb_self.labeler(text).label(language), confidence))
+                })?;
+            let detected = ruby.hash_new();
+            for (key, result) in keys.each().zip(results) {
+                detected.aset(key?, detection_to_value(ruby, result))?;
+            }
+            Ok(detected)
+        })
+    }
+
+    // Streams a CSV or TSV file through the detector `chunk_size` rows at a time without
+    // turning its rows into Ruby strings. With `output:` every row is written back with the
+    // language appended and the number of rows is returned; otherwise the languages are.
+    fn detect_file(ruby: &Ruby, rb_self: &Self, args: &[Value]) -> Result<Value, Error> {
+        let args = scan_args::<(String,), (), (), (), RHash, ()>(args)?;
+        let (path,) = args.required;
+        type Optional = (
+            Option<String>,
+            Option<bool>,
+            Option<String>,
+            Option<String>,
+            Option<bool>,
+            Option<usize>,
+        );
+        let kwargs = get_kwargs::<_, (Value,), Optional, ()>(
+            args.keywords,
+            &["text_column"],
+            &["output", "headers", "col_sep", "output_column", "confidence", "chunk_size"],
+        )?;
+        let (text_column,) = kwargs.required;
+        let (output, headers, col_sep, output_column, with_confidence, chunk_size) =
+            kwargs.optional;
+        let with_confidence = with_confidence.unwrap_or(false);
+        let chunk_size = chunk_size.unwrap_or(DEFAULT_CHUNK_SIZE);
+        if chunk_size == 0 {
+            return Err(Error::new(
+                ruby.exception_arg_error(),
+                "chunk_size must be at least 1",
+            ));
+        }
+        let delimiter = csv_delimiter(ruby, &path, col_sep)?;
+        let mut column = match Integer::from_value(text_column) {
+            Some(index) => Some(index.to_usize()?),
+            None => None,
+        };
+
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(delimiter)
+            .has_headers(false)
+            .flexible(true)
+            .from_path(&path)
+            .map_err(|err| file_error(ruby, "read", &path, err))?;
+        let mut writer = match &output {
+            Some(output) => Some(
+                csv::WriterBuilder::new()
+                    .delimiter(delimiter)
+                    .flexible(true)
+                    .from_path(output)
+                    .map_err(|err| file_error(ruby, "write", output, err))?,
+            ),
+            None => None,
+        };
+        let csv_write_error = |err| file_error(ruby, "write", output.as_deref().unwrap_or(""), err);
+
+        let mut records = reader.byte_records();
+        if headers.unwrap_or(false) {
+            if let Some(header) = records.next() {
+                let mut header = header.map_err(|err| file_error(ruby, "read", &path, err))?;
+                if column.is_none() {
+                    let name: String = text_column.funcall("to_s", ())?;
+                    column = header.iter().position(|field| field == name.as_bytes());
+                }
+                if let Some(writer) = &mut writer {
+                    header.push_field(output_column.as_deref().unwrap_or("language").as_bytes());
+                    if with_confidence {
+                        header.push_field(b"confidence");
+                    }
+                    writer.write_byte_record(&header).map_err(csv_write_error)?;
+                }
+            }
+        }
+        let Some(column) = column else {
+            return Err(Error::new(
+                ruby.exception_arg_error(),
+                format!("text_column {text_column} not found in the header of {path}"),
+            ));
+        };
+
+        let languages = ruby.ary_new();
+        let mut rows = 0usize;
+        loop {
+            let chunk = records
+                .by_ref()
+                .take(chunk_size)
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|err| file_error(ruby, "read", &path, err))?;
+            if chunk.is_empty() {
+                break;
+            }
+            let texts: Vec<Vec<u8>> = chunk
+                .iter()
+                .map(|record| record.get(column).unwrap_or_default().to_vec())
+                .collect();
+            let detected = rb_self.detect_native_texts(ruby, &texts, with_confidence)?;
+            rows += chunk.len();
+            for (mut record, detected) in chunk.into_iter().zip(detected) {
+                let Some(writer) = &mut writer else {
+                    languages.push(detection_to_value(ruby, detected))?;
+                    continue;
+                };
+                let (label, confidence) = detected.unzip();
+                record.push_field(label.as_deref().unwrap_or_default().as_bytes());
+                if with_confidence {
+                    let confidence = confidence.flatten().map(|value| value.to_string());
+                    record.push_field(confidence.as_deref().unwrap_or_default().as_bytes());
+                }
+                writer.write_byte_record(&record).map_err(csv_write_error)?;
+            }
+        }
+        match &mut writer {
+            Some(writer) => {
+                writer.flush().map_err(|err| csv_write_error(err.into()))?;
+                Ok(rows.into_value_with(ruby))
+            }
+            None => Ok(languages.as_value()),
+        }
+    }
+
+    // Results are serialized natively and written a chunk at a time to a path or to any
+    // object responding to `write`. Ids are positions, or the keys when `texts` is a Hash.
+    // Returns the number of lines written.
+    fn write_json_lines(ruby: &Ruby, rb_self: &Self, args: &[Value]) -> Result<usize, Error> {
+        let args = scan_args::<(Value,), (), (), (), RHash, ()>(args)?;
+        let (texts,) = args.required;
+        let (batch, rest) = parse_batch_options(ruby, args.keywords)?;
+        let kwargs = get_kwargs::<_, (Value,), (), ()>(rest, &["to"], &[])?;
+        let (to,) = kwargs.required;
+
+        let (keys, texts) = match RHash::from_value(texts) {
+            Some(hash) => {
+                let keys = ruby.ary_new_capa(hash.len());
+                let values = ruby.ary_new_capa(hash.len());
+                hash.foreach(|key: Value, text: Value| {
+                    keys.push(key)?;
+                    values.push(text)?;
+                    Ok(ForEach::Continue)
+                })?;
+                (Some(keys), values.as_value())
+            }
+            None => (None, texts),
+        };
+        let mut sink = JsonLinesSink::open(ruby, to)?;
+        let mut written = 0usize;
+        let outcome = rb_self.for_each_detected_chunk(
+            ruby,
+            texts,
+            &batch,
+            None,
+            |lingua, text| {
+                let language = rb_self.detect_cached(lingua, text)?;
+                let confidence = rb_self.labeled_confidence_with(lingua, text, language);
+                Some((rb_self.labeler(text).label(language), confidence))
+            },
+            |detected| {
+                let mut lines = String::new();
+                for detected in detected {
+                    let id = match keys {
+                        Some(keys) => json_id(keys.entry(written as isize)?)?,
+                        None => written.into(),
+                    };
+                    let (language, confidence) = detected.unzip();
+                    let line = JsonLine {
+                        id,
+                        language,
+                        confidence,
+                    };
+                    lines.push_str(&serde_json::to_string(&line).map_err(|err| {
+                        Error::new(ruby.exception_runtime_error(), err.to_string())
+                    })?);
+                    lines.push('\n');
+                    written += 1;
+                }
+                sink.write(ruby, &lines)
+            },
+        );
+        match outcome {
+            Err(err) if batch.returns_partial_on(ruby, &err) => {}
+            outcome => outcome?,
+        }
+        sink.finish(ruby)?;
+        Ok(written)
+    }
+
+    // Files matching the glob are read and detected `chunk_size` at a time, both in parallel
+    // with the GVL released. Returns a Hash from each file's path to its language.
+    fn detect_directory(ruby: &Ruby, rb_self: &Self, args: &[Value]) -> Result<RHash, Error> {
+        let args = scan_args::<(String,), (), (), (), RHash, ()>(args)?;
+        let (pattern,) = args.required;
+        let kwargs = get_kwargs::<_, (), (Option<bool>, Option<usize>), ()>(
+            args.keywords,
+            &[],
+            &["confidence", "chunk_size"],
+        )?;
+        let (with_confidence, chunk_size) = kwargs.optional;
+        let with_confidence = with_confidence.unwrap_or(false);
+        let chunk_size = chunk_size.unwrap_or(DEFAULT_CHUNK_SIZE);
+        if chunk_size == 0 {
+            return Err(Error::new(
+                ruby.exception_arg_error(),
+                "chunk_size must be at least 1",
+            ));
+        }
+
+        let paths = glob::glob(&pattern).map_err(|err| {
+            Error::new(
+                ruby.exception_arg_error(),
+                format!("invalid glob pattern {pattern}: {err}"),
+            )
+        })?;
+        let paths = paths
+            .filter_map(Result::ok)
+            .filter(|path| path.is_file())
+            .collect::<Vec<_>>();
+        let results = ruby.hash_new();
+        for chunk in paths.chunks(chunk_size) {
+            let contents = run_without_gvl(ruby, || map_batch(chunk, |path| std::fs::read(path)))?;
+            let texts = chunk
+                .iter()
+                .zip(contents)
+                .map(|(path, contents)| {
+                    contents.map_err(|err| {
+                        Error::new(
+                            ruby.exception_io_error(),
+                            format!("could not read {}: {err}", path.display()),
+                        )
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            let detected = rb_self.detect_native_texts(ruby, &texts, with_confidence)?;
+            for (path, detected) in chunk.iter().zip(detected) {
+                let path = path.to_string_lossy().into_owned();
+                results.aset(path, detection_to_value(ruby, detected))?;
+            }
+        }
+        Ok(results)
+    }
+
+    // Takes a red-arrow `Arrow::StringArray` and reads its offsets and data buffers
+    // directly, then builds the results as Arrow buffers, so no Ruby string is created per
+    // text. Null entries and undetected texts come back as nulls.
+    fn detect_arrow(ruby: &Ruby, rb_self: &Self, args: &[Value]) -> Result<Value, Error> {
+        let args = scan_args::<(Value,), (), (), (), RHash, ()>(args)?;
+        let (array,) = args.required;
+        let kwargs = get_kwargs::<_, (), (Option<bool>, Option<usize>), ()>(
+            args.keywords,
+            &[],
+            &["confidence", "chunk_size"],
+        )?;
+        let (with_confidence, chunk_size) = kwargs.optional;
+        let with_confidence = with_confidence.unwrap_or(false);
+        let chunk_size = chunk_size.unwrap_or(DEFAULT_CHUNK_SIZE);
+        if chunk_size == 0 {
+            return Err(Error::new(
+                ruby.exception_arg_error(),
+                "chunk_size must be at least 1",
+            ));
+        }
+
+        let arrow = ArrowStrings::read(ruby, array)?;
+        let mut languages = ArrowStringBuilder::new();
+        let mut confidences = Vec::new();
+        for start in (0..arrow.length).step_by(chunk_size) {
+            let chunk = start..arrow.length.min(start + chunk_size);
+            let texts: Vec<&[u8]> = chunk.clone().filter_map(|index| arrow.value(index)).collect();
+            let detected = rb_self.detect_native_texts(ruby, &texts, with_confidence)?;
+            let mut detected = detected.into_iter();
+            for index in chunk {
+                let result = match arrow.value(index) {
+                    Some(_) => detected.next().flatten(),
+                    None => None,
+                };
+                if with_confidence {
+                    confidences.push(result.as_ref().and_then(|(_, confidence)| *confidence));
+                }
+                languages.push(result.map(|(label, _)| label));
+            }
+        }
+
+        let languages = languages.finish(ruby)?;
+        if !with_confidence {
+            return Ok(languages);
+        }
+        let confidences = arrow_double_array(ruby, &confidences)?;
+        Ok(ruby.ary_new_from_values(&[languages, confidences]).as_value())
+    }
+
+    fn detect_each(ruby: &Ruby, rb_self: &Self, args: &[Value]) -> Result<Value, Error> {
+        let args = scan_args::<(Value,), (), (), (), RHash, ()>(args)?;
+        let (texts,) = args.required;
+        let receiver = magnus::current_receiver::<Value>()?;
+        if !ruby.block_given() {
+            let args = (texts, KwArgs(args.keywords));
+            return Ok(receiver.enumeratorize("detect_each", args).as_value());
+        }
+        let batch = parse_only_batch_options(ruby, args.keywords)?;
+
+        let mut index = 0usize;
+        let outcome = rb_self.for_each_detected_chunk(
+            ruby,
+            texts,
+            &batch,
+            None,
+            |lingua, text| {
+                let language = rb_self.detect_cached(lingua, text);
+                language.map(|language| rb_self.labeler(text).label(language))
+            },
+            |languages| {
+                for language in languages {
+                    ruby.yield_values::<_, Value>((index, language))?;
+                    index += 1;
+                }
+                Ok(())
+            },
+        );
+        match outcome {
+            Err(err) if batch.returns_partial_on(ruby, &err) => Ok(receiver),
+            outcome => outcome.map(|()| receiver),
+        }
+    }
+
+    fn detect_multiple_languages(
+        ruby: &Ruby,
+        rb_self: &Self,
+        args: &[Value],
+    ) -> Result<Value, Error> {
+        let args = scan_args::<(Value,), (), (), (), RHash, ()>(args)?;
+        let (text,) = args.required;
+        let text = rb_self.read_text(ruby, text)?;
+        let fields = parse_span_fields(args.keywords)?;
+
+        rb_self.instrument(ruby, "detect_multiple_languages", Some(text), || {
+            let Some(text) = rb_self.decode_text(ruby, &text)? else {
+                return Ok(ruby.ary_new());
+            };
+            let spans =
+                rb_self.with_lingua(ruby, |lingua| detect_spans(rb_self, lingua, &text, fields))?;
+            spans_to_array(ruby, spans, fields)
+        })
+    }
+
+    // Shares are by characters of the detected spans, largest first, and sum to 1.0.
+    fn language_composition(ruby: &Ruby, rb_self: &Self, text: Value) -> Result<Value, Error> {
+        let text = rb_self.read_text(ruby, text)?;
+        rb_self.instrument(ruby, "language_composition", Some(text), || {
+            let composition = ruby.hash_new();
+            let Some(text) = rb_self.decode_text(ruby, &text)? else {
+                return Ok(composition);
+            };
+            let labeler = rb_self.labeler(&text);
+            let mut spans = rb_self.with_lingua(ruby, |lingua| {
+                let mut lengths: Vec<(String, usize)> = Vec::new();
+                for result in lingua.detect_multiple_languages_of(&text) {
+                    let span = &text[result.start_index()..result.end_index()];
+                    let length = span.chars().count();
+                    let label = labeler.label(result.language());
+                    match lengths.iter_mut().find(|(existing, _)| *existing == label) {
+                        Some((_, total)) => *total += length,
+                        None => lengths.push((label, length)),
+                    }
+                }
+                lengths
+            })?;
+            spans.sort_by(|(a, a_length), (b, b_length)| b_length.cmp(a_length).then(a.cmp(b)));
+            let total: usize = spans.iter().map(|(_, length)| length).sum();
+            for (label, length) in spans {
+                composition.aset(label, length as f64 / total as f64)?;
+            }
+            Ok(composition)
+        })
+    }
+
+    // Each UAX #29 sentence is detected on its own; ranges are byte offsets into the
+    // text, like `detect_multiple_languages`, with trailing whitespace left out.
+    fn detect_sentences(ruby: &Ruby, rb_self: &Self, text: Value) -> Result<Value, Error> {
+        let text = rb_self.read_text(ruby, text)?;
+        rb_self.instrument(ruby, "detect_sentences", Some(text), || {
+            let Some(text) = rb_self.decode_text(ruby, &text)? else {
+                return Ok(Vec::new());
+            };
+            let sentences = sentence_spans(&text);
+            rb_self.with_lingua(ruby, |lingua| {
+                map_batch(&sentences, |&(start, end)| {
+                    let sentence = &text[start..end];
+                    let language = rb_self.detect_cached(lingua, sentence);
+                    let labeler = rb_self.labeler(sentence);
+                    (language.map(|language| labeler.label(language)), start, end)
+                })
+            })
+        })
+    }
+
+    // Line numbers are 1-based and count blank lines, which are left out of the result.
+    fn detect_lines(ruby: &Ruby, rb_self: &Self, text: Value) -> Result<Value, Error> {
+        let text = rb_self.read_text(ruby, text)?;
+        rb_self.instrument(ruby, "detect_lines", Some(text), || {
+            let Some(text) = rb_self.decode_text(ruby, &text)? else {
+                return Ok(Vec::new());
+            };
+            let lines: Vec<(usize, &str)> = text
+                .lines()
+                .enumerate()
+                .filter(|(_, line)| !line.trim().is_empty())
+                .map(|(index, line)| (index + 1, line))
+                .collect();
+            let distance = rb_self.options.minimum_relative_distance;
+            rb_self.with_lingua(ruby, |lingua| {
+                map_batch(&lines, |&(number, line)| {
+                    let values = lingua.compute_language_confidence_values(line);
+                    let detected = most_likely_language(&values, distance);
+                    rb_self.stats.record_result(detected.map(|(language, _)| language));
+                    match detected {
+                        Some((language, _)) => {
+                            let label = rb_self.labeler(line).label(language);
+                            let confidence = rb_self.labeled_confidence_in(values, line, language);
+                            (number, Some(label), confidence)
+                        }
+                        None => (number, None, 0.0),
+                    }
+                })
+            })
+        })
+    }
+
+    // With `window: n`, each word is detected together with up to `n` neighbours on either
+    // side, which smooths out short words that look like several languages at once.
+    fn detect_words(ruby: &Ruby, rb_self: &Self, args: &[Value]) -> Result<Value, Error> {
+        let args = scan_args::<(Value,), (), (), (), RHash, ()>(args)?;
+        let (text,) = args.required;
+        let text = rb_self.read_text(ruby, text)?;
+        let kwargs = get_kwargs::<_, (), (Option<usize>,), ()>(args.keywords, &[], &["window"])?;
+        let window = kwargs.optional.0.unwrap_or(0);
+
+        rb_self.instrument(ruby, "detect_words", Some(text), || {
+            let Some(text) = rb_self.decode_text(ruby, &text)? else {
+                return Ok(Vec::new());
+            };
+            let words: Vec<(usize, &str)> = text.unicode_word_indices().collect();
+            let indices: Vec<usize> = (0..words.len()).collect();
+            rb_self.with_lingua(ruby, |lingua| {
+                map_batch(&indices, |&index| {
+                    let (start, word) = words[index];
+                    let (first, _) = words[index.saturating_sub(window)];
+                    let (last, last_word) = words[(index + window).min(words.len() - 1)];
+                    let context = &text[first..last + last_word.len()];
+                    let language = rb_self.detect_cached(lingua, context);
+                    let labeler = rb_self.labeler(context);
+                    let language = language.map(|language| labeler.label(language));
+                    (word.to_owned(), language, start, start + word.len())
+                })
+            })
+        })
+    }
+
+    fn detect_multiple_languages_in_parallel(
+        ruby: &Ruby,
+        rb_self: &Self,
+        args: &[Value],
+    ) -> Result<Value, Error> {
+        let args = scan_args::<(Value,), (), (), (), RHash, ()>(args)?;
+        let (texts,) = args.required;
+        let (batch, rest) = parse_batch_options(ruby, args.keywords)?;
+        let fields = parse_span_fields(rest)?;
+
+        rb_self.instrument(ruby, "detect_multiple_languages_in_parallel", None, || {
+            let results = rb_self.map_text_chunks(ruby, texts, &batch, Vec::new(), |lingua, text| {
+                detect_spans(rb_self, lingua, text, fields)
+            })?;
+            let rows = ruby.ary_new_capa(results.len());
+            for spans in results {
+                rows.push(spans_to_array(ruby, spans, fields)?)?;
+            }
+            Ok(rows)
+        })
+    }
+
+    fn compute_language_confidence_values(
+        ruby: &Ruby,
+        rb_self: &Self,
+        args: &[Value],
+    ) -> Result<Value, Error> {
+        let args = scan_args::<(Value,), (), (), (), RHash, ()>(args)?;
+        let (text,) = args.required;
+        let text = rb_self.read_text(ruby, text)?;
+        rb_self.instrument(ruby, "compute_language_confidence_values", Some(text), || {
+            let kwargs =
+                get_kwargs::<_, (), (Option<f64>,), RHash>(args.keywords, &[], &["timeout"])?;
+            let (timeout,) = kwargs.optional;
+            let timeout = parse_timeout(ruby, timeout)?;
+            let options = parse_confidence_options(kwargs.splat)?;
+
+            let (values, labeler) = rb_self.labeled_confidence_values(ruby, text, timeout)?;
+            let values = confidence_values_to_tuples(values, &options, &labeler);
+            Ok(LanguageConfidence::from_pairs(values))
+        })
+    }
+
+    fn compute_language_confidence_values_in_parallel(
+        ruby: &Ruby,
+        rb_self: &Self,
+        args: &[Value],
+    ) -> Result<Value, Error> {
+        let args = scan_args::<(Value,), (), (), (), RHash, ()>(args)?;
+        let (texts,) = args.required;
+        let (batch, keywords) = parse_batch_options(ruby, args.keywords)?;
+        let options = parse_confidence_options(keywords)?;
+
+        rb_self.instrument(ruby, "compute_language_confidence_values_in_parallel", None, || {
+            rb_self.map_text_chunks(ruby, texts, &batch, Vec::new(), |lingua, text| {
+                let values = lingua.compute_language_confidence_values(text);
+                let temperature = rb_self.options.calibration.temperature(text);
+                let values = calibrate(values, temperature);
+                let values = confidence_values_to_tuples(values, &options, &rb_self.labeler(text));
+                LanguageConfidence::from_pairs(values)
+            })
+        })
+    }
+
+    // lingua keeps its n-gram models private, so each n-gram of the text is scored on its
+    // own: its contribution is the winner's confidence for it minus the best rival's.
+    fn explain_detection(ruby: &Ruby, rb_self: &Self, args: &[Value]) -> Result<Value, Error> {
+        let args = scan_args::<(Value,), (), (), (), RHash, ()>(args)?;
+        let (text,) = args.required;
+        let text = rb_self.read_text(ruby, text)?;
+        let kwargs = get_kwargs::<_, (), (Option<usize>,), ()>(args.keywords, &[], &["top"])?;
+        let top = kwargs.optional.0.unwrap_or(DEFAULT_EXPLAINED_NGRAMS);
+        let Some(text) = rb_self.decode_text(ruby, &text)? else {
+            return Ok(ruby.qnil().as_value());
+        };
+        let hybrid = rb_self.hybrid.as_ref();
+        let explained = rb_self.with_lingua(ruby, |lingua| {
+            let language = detect_with(hybrid, lingua, &text)?;
+            let mut ngrams: Vec<(String, f64)> = text_ngrams(&text)
+                .into_iter()
+                .map(|ngram| {
+                    let values = lingua.compute_language_confidence_values(&ngram);
+                    let margin = confidence_margin(&values, language);
+                    (ngram, margin)
+                })
+                .filter(|(_, margin)| *margin > 0.0)
+                .collect();
+            ngrams.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+            ngrams.truncate(top);
+            Some((language, ngrams))
+        })?;
+        let Some((language, ngrams)) = explained else {
+            return Ok(ruby.qnil().as_value());
+        };
+        let explanation = ruby.hash_new();
+        explanation.aset(ruby.to_symbol("language"), rb_self.labeler(&text).label(language))?;
+        explanation.aset(ruby.to_symbol("ngrams"), ngrams)?;
+        Ok(explanation.as_value())
+    }
+
+    fn compute_language_confidence(
+        ruby: &Ruby,
+        rb_self: &Self,
+        text: Value,
+        language_value: Value,
+    ) -> Result<Value, Error> {
+        let text = rb_self.read_text(ruby, text)?;
+        rb_self.instrument(ruby, "compute_language_confidence", Some(text), || {
+            let language = rb_self.parse_labeled_language(ruby, language_value)?;
+            let Some(text) = rb_self.decode_text(ruby, &text)? else {
+                return Ok(0.0);
+            };
+            rb_self.with_lingua(ruby, |lingua| {
+                rb_self.labeled_confidence_with(lingua, &text, language)
+            })
+        })
+    }
+
+    fn compute_language_confidence_in_parallel(
+        ruby: &Ruby,
+        rb_self: &Self,
+        args: &[Value],
+    ) -> Result<Value, Error> {
+        let args = scan_args::<(Value, Value), (), (), (), RHash, ()>(args)?;
+        let (texts, language_value) = args.required;
+        let batch = parse_only_batch_options(ruby, args.keywords)?;
+
+        rb_self.instrument(ruby, "compute_language_confidence_in_parallel", None, || {
+            let language = rb_self.parse_labeled_language(ruby, language_value)?;
+            rb_self.map_text_chunks(ruby, texts, &batch, 0.0, |lingua, text| {
+                rb_self.labeled_confidence_with(lingua, text, language)
+            })
+        })
+    }
+
+    fn confidence_gaps_in_parallel(
+        ruby: &Ruby,
+        rb_self: &Self,
+        args: &[Value],
+    ) -> Result<Value, Error> {
+        let args = scan_args::<(Value,), (), (), (), RHash, ()>(args)?;
+        let (texts,) = args.required;
+        let batch = parse_only_batch_options(ruby, args.keywords)?;
+
+        rb_self.instrument(ruby, "confidence_gaps_in_parallel", None, || {
+            rb_self.map_text_chunks(ruby, texts, &batch, 0.0, |lingua, text| {
+                let values = lingua.compute_language_confidence_values(text);
+                top_two_gap(&calibrate(values, rb_self.options.calibration.temperature(text)))
+            })
+        })
+    }
+
+    // Times the same corpus through the single-text and the parallel path, with the GVL
+    // released and no result cache, for comparing detector configurations.
+    fn benchmark(ruby: &Ruby, rb_self: &Self, args: &[Value]) -> Result<RHash, Error> {
+        let args = scan_args::<(Vec<RString>,), (), (), (), RHash, ()>(args)?;
+        let (texts,) = args.required;
+        let kwargs =
+            get_kwargs::<_, (), (Option<usize>,), ()>(args.keywords, &[], &["iterations"])?;
+        let iterations = kwargs.optional.0.unwrap_or(1);
+        if iterations == 0 {
+            return Err(Error::new(
+                ruby.exception_arg_error(),
+                "iterations must be at least 1",
+            ));
+        }
+        let texts = rb_self.decode_texts(ruby, &texts)?.texts;
+
+        let (single, parallel) = rb_self.with_lingua(ruby, |lingua| {
+            let mut single = Vec::with_capacity(texts.len() * iterations);
+            let mut parallel = Vec::with_capacity(iterations);
+            for _ in 0..iterations {
+                for text in &texts {
+                    let started = Instant::now();
+                    std::hint::black_box(lingua.detect_language_of(text));
+                    single.push(started.elapsed());
+                }
+                let started = Instant::now();
+                std::hint::black_box(lingua.detect_languages_in_parallel_of(&texts));
+                parallel.push(started.elapsed());
+            }
+            (single, parallel)
+        })?;
+
+        let report = ruby.hash_new();
+        report.aset(ruby.to_symbol("texts"), texts.len())?;
+        report.aset(ruby.to_symbol("iterations"), iterations)?;
+        report.aset(
+            ruby.to_symbol("single"),
+            benchmark_report(ruby, single, texts.len() * iterations)?,
+        )?;
+        report.aset(
+            ruby.to_symbol("parallel"),
+            benchmark_report(ruby, parallel, texts.len() * iterations)?,
+        )?;
+        Ok(report)
+    }
+
+    fn compute_language_confidence_pairs(
+        ruby: &Ruby,
+        rb_self: &Self,
+        pairs: Vec<(RString, Value)>,
+    ) -> Result<Vec<f64>, Error> {
+        let pairs = pairs
+            .iter()
+            .map(|(text, language_value)| {
+                Ok((
+                    rb_self.decode_text(ruby, text)?,
+                    rb_self.parse_labeled_language(ruby, *language_value)?,
+                ))
+            })
+            .collect::<Result<Vec<(Option<String>, Language)>, Error>>()?;
+
+        rb_self.with_lingua(ruby, |lingua| {
+            map_batch(&pairs, |(text, language)| match text {
+                Some(text) => rb_self.labeled_confidence_with(lingua, text, *language),
+                None => 0.0,
+            })
+        })
+    }
+}
+
+impl ModelLoadHandle {
+    fn is_ready(ruby: &Ruby, rb_self: &Self) -> Result<bool, Error> {
+        match &*lock_ignoring_poison(&rb_self.0) {
+            ModelLoadState::Pending => Ok(false),
+            ModelLoadState::Ready => Ok(true),
+            ModelLoadState::Failed(message) => Err(model_load_error(ruby, message)),
+        }
+    }
+
+    fn wait(ruby: &Ruby, rb_self: &Self, args: &[Value]) -> Result<bool, Error> {
+        let args = scan_args::<(), (Option<f64>,), (), (), (), ()>(args)?;
+        let (timeout,) = args.optional;
+        // Zero polls once; a deadline too far away to represent means waiting forever.
+        let timeout = timeout
+            .map(|seconds| {
+                Duration::try_from_secs_f64(seconds).map_err(|_| {
+                    Error::new(
+                        ruby.exception_arg_error(),
+                        "timeout must be a non-negative number of seconds",
+                    )
+                })
+            })
+            .transpose()?;
+        let deadline = timeout.and_then(|timeout| Instant::now().checked_add(timeout));
+        loop {
+            if Self::is_ready(ruby, rb_self)? {
+                return Ok(true);
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Ok(false);
+            }
+            ruby.module_kernel()
+                .funcall::<_, _, Value>("sleep", (WAIT_POLL_INTERVAL,))?;
+        }
+    }
+}
+
+// One language's confidence, as the confidence methods return them. It converts to and
+// compares equal with the `[language, confidence]` pair it replaced, and orders by
+// confidence. The script, direction and gap are only set when asked for, and then follow
+// the confidence in the array form.
+#[magnus::wrap(class = "LinguaRsRb::LanguageConfidence", free_immediately, frozen_shareable)]
+#[derive(Clone, Serialize)]
+struct LanguageConfidence {
+    language: String,
+    confidence: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    script: Option<Option<&'static str>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    direction: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    gap: Option<f64>,
+}
+
+impl LanguageConfidence {
+    fn new(language: String, confidence: f64) -> Self {
+        Self {
+            language,
+            confidence,
+            script: None,
+            direction: None,
+            gap: None,
+        }
+    }
+
+    fn rb_new(ruby: &Ruby, language: String, confidence: f64) -> Result<Obj<Self>, Error> {
+        let args = [language.as_str().into_value_with(ruby), confidence.into_value_with(ruby)];
+        new_as_receiver(Self::new(language, confidence), &args)
+    }
+
+    fn from_pairs(values: Vec<(String, f64)>) -> Vec<Self> {
+        values
+            .into_iter()
+            .map(|(language, confidence)| Self::new(language, confidence))
+            .collect()
+    }
+
+    fn language(&self) -> String {
+        self.language.clone()
+    }
+
+    fn confidence(&self) -> f64 {
+        self.confidence
+    }
+
+    fn script(&self) -> Option<&'static str> {
+        self.script.flatten()
+    }
+
+    fn direction(ruby: &Ruby, rb_self: &Self) -> Option<Symbol> {
+        rb_self.direction.map(|direction| ruby.to_symbol(direction))
+    }
+
+    fn gap(&self) -> Option<f64> {
+        self.gap
+    }
+
+    fn to_a(ruby: &Ruby, rb_self: &Self) -> Result<RArray, Error> {
+        let array = ruby.ary_new();
+        array.push(rb_self.language())?;
+        array.push(rb_self.confidence)?;
+        if let Some(script) = rb_self.script {
+            array.push(script)?;
+        }
+        if rb_self.direction.is_some() {
+            array.push(Self::direction(ruby, rb_self))?;
+        }
+        if let Some(gap) = rb_self.gap {
+            array.push(gap)?;
+        }
+        Ok(array)
+    }
+
+    fn element(ruby: &Ruby, rb_self: &Self, index: isize) -> Result<Value, Error> {
+        Self::to_a(ruby, rb_self)?.entry(index)
+    }
+
+    fn last(ruby: &Ruby, rb_self: &Self, args: &[Value]) -> Result<Value, Error> {
+        Self::to_a(ruby, rb_self)?.funcall("last", args)
+    }
+
+    fn compare(&self, other: Value) -> Option<i32> {
+        let other = Obj::<LanguageConfidence>::try_convert(other).ok()?;
+        let ordering = self.confidence.partial_cmp(&other.confidence)?;
+        Some(ordering as i32)
+    }
+
+    fn equals(ruby: &Ruby, rb_self: &Self, other: Value) -> Result<bool, Error> {
+        if let Ok(other) = Obj::<LanguageConfidence>::try_convert(other) {
+            return Ok(rb_self.eql(&other));
+        }
+        if RArray::from_value(other).is_none() {
+            return Ok(false);
+        }
+        Self::to_a(ruby, rb_self)?.funcall("==", (other,))
+    }
+
+    fn is_eql(&self, other: Value) -> bool {
+        Obj::<LanguageConfidence>::try_convert(other).is_ok_and(|other| self.eql(&other))
+    }
+
+    fn eql(&self, other: &Self) -> bool {
+        self.language == other.language
+            && self.confidence.to_bits() == other.confidence.to_bits()
+            && self.script == other.script
+            && self.direction == other.direction
+            && self.gap.map(f64::to_bits) == other.gap.map(f64::to_bits)
+    }
+
+    fn hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        (&self.language, self.confidence.to_bits()).hash(&mut hasher);
+        (self.script, self.direction, self.gap.map(f64::to_bits)).hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn to_h(ruby: &Ruby, rb_self: &Self) -> Result<RHash, Error> {
+        let hash = ruby.hash_new();
+        hash.aset(ruby.to_symbol("language"), rb_self.language())?;
+        hash.aset(ruby.to_symbol("confidence"), rb_self.confidence)?;
+        if let Some(script) = rb_self.script {
+            hash.aset(ruby.to_symbol("script"), script)?;
+        }
+        if rb_self.direction.is_some() {
+            hash.aset(ruby.to_symbol("direction"), Self::direction(ruby, rb_self))?;
+        }
+        if let Some(gap) = rb_self.gap {
+            hash.aset(ruby.to_symbol("gap"), gap)?;
+        }
+        Ok(hash)
+    }
+
+    fn deconstruct_keys(ruby: &Ruby, rb_self: &Self, _keys: Value) -> Result<RHash, Error> {
+        Self::to_h(ruby, rb_self)
+    }
+
+    fn to_json(ruby: &Ruby, rb_self: &Self, _args: &[Value]) -> Result<String, Error> {
+        to_json_string(ruby, rb_self)
+    }
+
+    fn inspect(&self) -> String {
+        let mut inspected = format!(
+            "#<LinguaRsRb::LanguageConfidence language={:?} confidence={:?}",
+            self.language, self.confidence
+        );
+        if let Some(script) = self.script {
+            inspected.push_str(&format!(" script={:?}", script));
+        }
+        if let Some(direction) = self.direction {
+            inspected.push_str(&format!(" direction=:{direction}"));
+        }
+        if let Some(gap) = self.gap {
+            inspected.push_str(&format!(" gap={gap:?}"));
+        }
+        inspected.push('>');
+        inspected
+    }
+}
+
+#[magnus::wrap(class = "LinguaRsRb::DetectionAnalysis", free_immediately, frozen_shareable)]
+#[derive(Default, Serialize)]
+struct DetectionAnalysis {
+    language: Option<String>,
+    confidence: f64,
+    reliable: bool,
+    script: Option<&'static str>,
+    runner_up: Option<(String, f64)>,
+}
+
+impl DetectionAnalysis {
+    fn language(&self) -> Option<String> {
+        self.language.clone()
+    }
+
+    fn confidence(&self) -> f64 {
+        self.confidence
+    }
+
+    fn is_reliable(&self) -> bool {
+        self.reliable
+    }
+
+    fn script(&self) -> Option<&'static str> {
+        self.script
+    }
+
+    fn runner_up(&self) -> Option<(String, f64)> {
+        self.runner_up.clone()
+    }
+
+    fn to_h(ruby: &Ruby, rb_self: &Self) -> Result<RHash, Error> {
+        let hash = ruby.hash_new();
+        hash.aset(ruby.to_symbol("language"), rb_self.language())?;
+        hash.aset(ruby.to_symbol("confidence"), rb_self.confidence)?;
+        hash.aset(ruby.to_symbol("reliable"), rb_self.reliable)?;
+        hash.aset(ruby.to_symbol("script"), rb_self.script)?;
+        hash.aset(ruby.to_symbol("runner_up"), rb_self.runner_up())?;
+        Ok(hash)
+    }
+
+    fn to_json(ruby: &Ruby, rb_self: &Self, _args: &[Value]) -> Result<String, Error> {
+        to_json_string(ruby, rb_self)
+    }
+}
+
+fn millis_since_start() -> u64 {
+    PROCESS_START.get_or_init(Instant::now).elapsed().as_millis() as u64
+}
+
+fn install_gc_unload_hook() {
+    GC_UNLOAD_HOOK.call_once(|| unsafe {
+        let major_by = rb_sys::rb_id2sym(rb_sys::rb_intern(b"major_by\0".as_ptr().cast()));
+        MAJOR_BY_SYMBOL.store(major_by as usize, Ordering::Relaxed);
+
+        let tracepoint = rb_sys::rb_tracepoint_new(
+            0 as rb_sys::VALUE,
+            rb_sys::RUBY_INTERNAL_EVENT_GC_END_SWEEP as rb_sys::rb_event_flag_t,
+            Some(unload_models_after_major_gc),
+            std::ptr::null_mut(),
+        );
+        rb_sys::rb_gc_register_mark_object(tracepoint);
+        rb_sys::rb_tracepoint_enable(tracepoint);
+    });
+}
+
+// Runs inside the GC, so it must not allocate Ruby objects or block on a lock that a
+// Ruby thread could be holding; contended locks simply skip this round.
+unsafe extern "C" fn unload_models_after_major_gc(
+    _tracepoint: rb_sys::VALUE,
+    _data: *mut c_void,
+) {
+    let major_by = MAJOR_BY_SYMBOL.load(Ordering::Relaxed) as rb_sys::VALUE;
+    if rb_sys::rb_gc_latest_gc_info(major_by) == rb_sys::Qnil as rb_sys::VALUE {
+        return;
+    }
+
+    let Ok(mut registry) = GC_UNLOAD_REGISTRY.try_lock() else {
+        return;
+    };
+    registry.retain(|(detector, residency)| {
+        let (Some(detector), Some(residency)) = (detector.upgrade(), residency.upgrade()) else {
+            return false;
+        };
+        if let Ok(mut loaded) = residency.loaded_languages.try_lock() {
+            if let Ok(mut cache) = MODEL_CACHE.try_lock() {
+                residency.unload_locked(&detector, &mut loaded, &mut cache);
+            }
+        }
+        true
+    });
+}
+
+// Frozen detectors keep detecting, and keep updating their result caches and statistics,
+// but refuse calls that change how they handle results or load and unload models.
+fn ensure_not_frozen(ruby: &Ruby) -> Result<(), Error> {
+    let receiver = magnus::current_receiver::<Value>()?;
+    if !receiver.is_frozen() {
+        return Ok(());
+    }
+    let inspect: String = receiver.funcall("inspect", ())?;
+    Err(Error::new(
+        ruby.exception_frozen_error(),
+        format!("can't modify frozen {}: {inspect}", receiver.class().inspect()),
+    ))
+}
+
+// Constructors are singleton methods, so calling one through a Ruby subclass makes that
+// subclass the receiver; wrapping in it keeps `MyDetector.load(...)` a `MyDetector`.
+fn wrap_as_receiver<T: TypedData>(data: T) -> Result<Obj<T>, Error> {
+    let class = magnus::current_receiver::<RClass>()?;
+    Ok(Obj::wrap_as(data, class))
+}
+
+// What `new` does for the wrapped classes: wrap in the receiver, then run `initialize`.
+fn new_as_receiver<T: TypedData>(data: T, args: &[Value]) -> Result<Obj<T>, Error> {
+    let object = wrap_as_receiver(data)?;
+    call_initialize(object.as_value(), args)?;
+    Ok(object)
+}
+
+// Accepts `new`'s arguments so subclass initializers can call `super` with or without them.
+fn initialize_wrapped<T: TypedData>(_rb_self: &T, _args: &[Value]) {}
+
+// Runs `initialize` with the arguments, keywords and block the current method received,
+// the way `Class#new` does after allocating.
+fn call_initialize(object: Value, args: &[Value]) -> Result<(), Error> {
+    magnus::rb_sys::protect(|| {
+        unsafe {
+            rb_sys::rb_obj_call_init_kw(
+                object.as_raw(),
+                args.len() as std::os::raw::c_int,
+                args.as_ptr().cast::<rb_sys::VALUE>(),
+                rb_sys::RB_PASS_CALLED_KEYWORDS as std::os::raw::c_int,
+            )
+        };
+        rb_sys::Qnil as rb_sys::VALUE
+    })
+    .map(|_| ())
+}
+
+fn marshal_module(ruby: &Ruby) -> Result<Value, Error> {
+    ruby.class_object().const_get("Marshal")
+}
+
+extern "C" {
+    // Exported by libruby behind the inline `rb_ractor_main_p` of ruby/ractor.h.
+    fn rb_ractor_main_p_() -> bool;
+}
+
+fn on_main_ractor() -> bool {
+    unsafe { rb_ractor_main_p_() }
+}
+
+// Callbacks a detector was configured with belong to the main Ractor. A detector shared
+// with other Ractors may only call them there once they have been made shareable.
+fn ractor_callable(ruby: &Ruby, callback: Opaque<Value>, name: &str) -> Result<Value, Error> {
+    let callback = ruby.get_inner(callback);
+    if on_main_ractor() {
+        return Ok(callback);
+    }
+    let ractor: RClass = ruby.class_object().const_get("Ractor")?;
+    if ractor.funcall::<_, _, bool>("shareable?", (callback,))? {
+        return Ok(callback);
+    }
+    let unsafe_error: ExceptionClass = ractor.const_get("UnsafeError")?;
+    Err(Error::new(
+        unsafe_error,
+        format!("{name} must be made shareable with Ractor.make_shareable to be called here"),
+    ))
+}
+
+fn error_class(ruby: &Ruby, name: &str) -> ExceptionClass {
+    ruby.define_module("LinguaRsRb")
+        .and_then(|module| module.const_get(name))
+        .unwrap()
+}
+
+fn unknown_language_error(ruby: &Ruby, message: String) -> Error {
+    Error::new(ruby.get_inner(&UNKNOWN_LANGUAGE_ERROR), message)
+}
+
+fn for_each_text_chunk(
+    ruby: &Ruby,
+    texts: Value,
+    chunk_size: usize,
+    mut f: impl FnMut(&[RString]) -> Result<(), Error>,
+) -> Result<(), Error> {
+    if let Some(texts) = RArray::from_value(texts) {
+        // Entries are re-read per chunk since `f` may run Ruby code between chunks.
+        let mut offset = 0;
+        while offset < texts.len() {
+            let end = texts.len().min(offset + chunk_size);
+            let chunk = (offset..end)
+                .map(|index| texts.entry::<RString>(index as isize))
+                .collect::<Result<Vec<RString>, Error>>()?;
+            f(&chunk)?;
+            offset = end;
+        }
+        return Ok(());
+    }
+
+    let chunks: Value = texts.funcall("each_slice", (chunk_size,))?;
+    loop {
+        // Keeping the chunk as an RArray on the stack keeps it (and its strings) alive.
+        let chunk: RArray = match chunks.funcall("next", ()) {
+            Ok(chunk) => chunk,
+            Err(err) if err.is_kind_of(ruby.exception_stop_iteration()) => return Ok(()),
+            Err(err) => return Err(err),
+        };
+        f(&chunk.to_vec::<RString>()?)?;
+    }
+}
+
+// The total handed to `progress:` callbacks: known for Arrays and sized Enumerators only.
+fn batch_size_hint(texts: Value) -> Result<Option<usize>, Error> {
+    if let Some(texts) = RArray::from_value(texts) {
+        return Ok(Some(texts.len()));
+    }
+    if !texts.respond_to("size", false)? {
+        return Ok(None);
+    }
+    let size: Value = texts.funcall("size", ())?;
+    Ok(Integer::from_value(size).and_then(|size| size.to_usize().ok()))
+}
+
+// Raises any pending interrupt (Ctrl-C, Thread#raise, ...) as the corresponding Ruby error.
+fn check_interrupts() -> Result<(), Error> {
+    magnus::rb_sys::protect(|| {
+        unsafe { rb_sys::rb_thread_check_ints() };
+        rb_sys::Qnil as rb_sys::VALUE
+    })
+    .map(|_| ())
+}
+
+fn catch_lingua_panic<T>(ruby: &Ruby, f: impl FnOnce() -> T) -> Result<T, Error> {
+    std::panic::catch_unwind(AssertUnwindSafe(f))
+        .map_err(|panic| lingua_panic_error(ruby, panic.as_ref()))
+}
+
+// Runs lingua work with the GVL released so other Ruby threads keep running. Under a
+// fiber scheduler the work moves to the rayon pool instead, while this fiber waits in
+// `Kernel#sleep`, which the scheduler turns into a non-blocking wait.
+fn run_without_gvl<T: Send>(ruby: &Ruby, f: impl FnOnce() -> T + Send) -> Result<T, Error> {
+    let work = || std::panic::catch_unwind(AssertUnwindSafe(|| in_thread_pool(f)));
+    let result = if fiber_scheduler_active(ruby)? {
+        wait_on_fiber_scheduler(ruby, work)?
+    } else {
+        call_without_gvl(work)
+    };
+    result.map_err(|panic| lingua_panic_error(ruby, panic.as_ref()))
+}
+
+// Returns each distinct text once, plus for every input the index of its distinct text,
+// so repeated strings are detected once and their result fanned back out.
+fn dedupe_texts(texts: &[String]) -> (Vec<&str>, Vec<usize>) {
+    let mut indices: HashMap<&str, usize> = HashMap::with_capacity(texts.len());
+    let mut unique = Vec::new();
+    let positions = texts
+        .iter()
+        .map(|text| {
+            *indices.entry(text.as_str()).or_insert_with(|| {
+                unique.push(text.as_str());
+                unique.len() - 1
+            })
+        })
+        .collect();
+    (unique, positions)
+}
+
+// Batches of at most `LinguaRsRb.serial_batch_threshold` items skip rayon entirely: for a
+// handful of short texts the dispatch overhead outweighs the detection itself.
+fn map_batch<I: Sync, T: Send>(items: &[I], f: impl Fn(&I) -> T + Send + Sync) -> Vec<T> {
+    if items.len() <= SERIAL_BATCH_THRESHOLD.load(Ordering::Relaxed) {
+        items.iter().map(f).collect()
+    } else {
+        items.par_iter().map(f).collect()
+    }
+}
+
+fn thread_pool() -> Option<Arc<rayon::ThreadPool>> {
+    lock_ignoring_poison(&THREAD_POOL).clone()
+}
+
+// Runs `f` on the pool set through `LinguaRsRb.max_threads=`, so lingua's parallel
+// iterators stay within it; without one they use rayon's global pool.
+fn in_thread_pool<T: Send>(f: impl FnOnce() -> T + Send) -> T {
+    match thread_pool() {
+        Some(pool) => pool.install(f),
+        None => f(),
+    }
+}
+
+fn worker_thread_count() -> usize {
+    thread_pool().map_or_else(rayon::current_num_threads, |pool| pool.current_num_threads())
+}
+
+fn fiber_scheduler_active(ruby: &Ruby) -> Result<bool, Error> {
+    let fiber: Value = ruby.class_object().const_get("Fiber")?;
+    // `current_scheduler` is nil inside blocking fibers, which must not yield.
+    let scheduler: Value = fiber.funcall("current_scheduler", ())?;
+    Ok(!scheduler.is_nil())
+}
+
+fn wait_on_fiber_scheduler<T: Send>(
+    ruby: &Ruby,
+    f: impl FnOnce() -> T + Send,
+) -> Result<T, Error> {
+    let (sender, receiver) = mpsc::channel();
+    // If the sleep raises, leaving the scope still waits for the job to finish.
+    rayon::in_place_scope(|scope| {
+        scope.spawn(move |_| {
+            let _ = sender.send(f());
+        });
+        loop {
+            match receiver.try_recv() {
+                Err(mpsc::TryRecvError::Empty) => {
+                    ruby.module_kernel()
+                        .funcall::<_, _, Value>("sleep", (WAIT_POLL_INTERVAL,))?;
+                }
+                result => return Ok(result.expect("detection job always reports back")),
+            }
+        }
+    })
+}
+
+// Waits for a background job's result while other threads (or, under a fiber scheduler,
+// other fibers) keep running. Returns `None` once `timeout` has passed.
+fn receive_within<T: Send>(
+    ruby: &Ruby,
+    receiver: mpsc::Receiver<T>,
+    timeout: Duration,
+) -> Result<Option<T>, Error> {
+    if !fiber_scheduler_active(ruby)? {
+        return Ok(call_without_gvl(move || receiver.recv_timeout(timeout).ok()));
+    }
+    let deadline = Instant::now() + timeout;
+    loop {
+        match receiver.try_recv() {
+            Err(mpsc::TryRecvError::Empty) if Instant::now() < deadline => {
+                ruby.module_kernel()
+                    .funcall::<_, _, Value>("sleep", (WAIT_POLL_INTERVAL,))?;
+            }
+            result => return Ok(result.ok()),
+        }
+    }
+}
+
+// `f` must not panic: unwinding out of the callback would cross the C frame.
+fn call_without_gvl<F: FnOnce() -> T, T>(f: F) -> T {
+    unsafe extern "C" fn trampoline<F: FnOnce() -> T, T>(data: *mut c_void) -> *mut c_void {
+        let (f, result) = &mut *data.cast::<(Option<F>, Option<T>)>();
+        *result = f.take().map(|f| f());
+        std::ptr::null_mut()
+    }
+
+    let mut slot: (Option<F>, Option<T>) = (Some(f), None);
+    unsafe {
+        rb_sys::rb_thread_call_without_gvl(
+            Some(trampoline::<F, T>),
+            (&mut slot as *mut (Option<F>, Option<T>)).cast(),
+            None,
+            std::ptr::null_mut(),
+        );
+    }
+    slot.1.expect("rb_thread_call_without_gvl runs its callback")
+}
+
+fn lingua_panic_error(ruby: &Ruby, panic: &(dyn std::any::Any + Send)) -> Error {
+    Error::new(
+        ruby.get_inner(&INTERNAL_ERROR),
+        format!("lingua panicked: {}", panic_message(panic)),
+    )
+}
+
+fn timeout_error(ruby: &Ruby, timeout: Duration) -> Error {
+    Error::new(
+        ruby.get_inner(&TIMEOUT_ERROR),
+        format!("detection exceeded its timeout of {}s", timeout.as_secs_f64()),
+    )
+}
+
+fn parse_timeout(ruby: &Ruby, seconds: Option<f64>) -> Result<Option<Duration>, Error> {
+    let Some(seconds) = seconds else {
+        return Ok(None);
+    };
+    match Duration::try_from_secs_f64(seconds) {
+        Ok(timeout) if !timeout.is_zero() => Ok(Some(timeout)),
+        _ => Err(Error::new(
+            ruby.exception_arg_error(),
+            "timeout must be a positive number of seconds",
+        )),
+    }
+}
+
+fn model_load_error(ruby: &Ruby, message: &str) -> Error {
+    Error::new(
+        ruby.get_inner(&DETECTION_ERROR),
+        format!("background model loading failed: {message}"),
+    )
+}
+
+fn panic_message(panic: &(dyn std::any::Any + Send)) -> String {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+impl DetectorPool {
+    fn new(ruby: &Ruby, args: &[Value]) -> Result<Obj<Self>, Error> {
+        let parsed = scan_args::<(), (), (), (), RHash, ()>(args)?;
+        let kwargs = get_kwargs::<_, (), (Option<usize>, Option<Option<f64>>), RHash>(
+            parsed.keywords,
+            &[],
+            &["size", "timeout"],
+        )?;
+        let (size, timeout) = kwargs.optional;
+        let timeout = match timeout {
+            Some(timeout) => parse_timeout(ruby, timeout)?,
+            None => Some(Duration::from_secs(5)),
+        };
+        let size = size.unwrap_or(5);
+        if size == 0 {
+            return Err(Error::new(
+                ruby.exception_arg_error(),
+                "detector pool size must be at least 1",
+            ));
+        }
+
+        let state = parse_detector_kwargs(ruby, kwargs.splat)?;
+        let detectors = (0..size)
+            .map(|_| {
+                let detector = LanguageDetectorWrapper::new(
+                    ruby,
+                    state.languages.clone(),
+                    state.options.clone(),
+                )?;
+                Ok(Opaque::from(Obj::wrap(detector).as_value()))
+            })
+            .collect::<Result<Vec<Opaque<Value>>, Error>>()?;
+
+        let pool = Self {
+            available: Mutex::new(detectors.clone()),
+            detectors,
+            timeout,
+        };
+        new_as_receiver(pool, args)
+    }
+
+    fn size(&self) -> usize {
+        self.detectors.len()
+    }
+
+    fn available(&self) -> usize {
+        lock_ignoring_poison(&self.available).len()
+    }
+
+    fn checkout(ruby: &Ruby, rb_self: &Self, args: &[Value]) -> Result<Value, Error> {
+        let timeout = parse_pool_timeout(ruby, args)?.unwrap_or(rb_self.timeout);
+        rb_self.checkout_with_timeout(ruby, timeout)
+    }
+
+    fn checkin(ruby: &Ruby, rb_self: &Self, detector: Value) -> Result<(), Error> {
+        let mut available = lock_ignoring_poison(&rb_self.available);
+        let owned = rb_self
+            .detectors
+            .iter()
+            .find(|candidate| ruby.get_inner(**candidate).as_raw() == detector.as_raw())
+            .ok_or_else(|| {
+                Error::new(
+                    ruby.exception_arg_error(),
+                    "detector does not belong to this pool",
+                )
+            })?;
+        if available
+            .iter()
+            .any(|candidate| ruby.get_inner(*candidate).as_raw() == detector.as_raw())
+        {
+            return Err(Error::new(
+                ruby.exception_arg_error(),
+                "detector has already been checked in",
+            ));
+        }
+        available.push(*owned);
+        Ok(())
+    }
+
+    fn with(ruby: &Ruby, rb_self: &Self, args: &[Value]) -> Result<Value, Error> {
+        let timeout = parse_pool_timeout(ruby, args)?.unwrap_or(rb_self.timeout);
+        let detector = rb_self.checkout_with_timeout(ruby, timeout)?;
+        let result = ruby.yield_value::<_, Value>(detector);
+        Self::checkin(ruby, rb_self, detector)?;
+        result
+    }
+
+    // A deadline too far in the future to represent is no deadline at all.
+    fn checkout_with_timeout(
+        &self,
+        ruby: &Ruby,
+        timeout: Option<Duration>,
+    ) -> Result<Value, Error> {
+        let deadline = timeout.and_then(|timeout| Instant::now().checked_add(timeout));
+        loop {
+            if let Some(detector) = lock_ignoring_poison(&self.available).pop() {
+                return Ok(ruby.get_inner(detector));
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Err(Error::new(
+                    ruby.get_inner(&TIMEOUT_ERROR),
+                    format!(
+                        "timed out waiting for a detector from the pool (size {})",
+                        self.detectors.len()
+                    ),
+                ));
+            }
+            // Kernel#sleep releases the GVL so other threads can check detectors back in.
+            ruby.module_kernel()
+                .funcall::<_, _, Value>("sleep", (WAIT_POLL_INTERVAL,))?;
+        }
+    }
+}
+
+// `None` when no timeout was given; `Some(None)` when it was given as nil to wait forever.
+fn parse_pool_timeout(ruby: &Ruby, args: &[Value]) -> Result<Option<Option<Duration>>, Error> {
+    let args = scan_args::<(), (), (), (), RHash, ()>(args)?;
+    let kwargs = get_kwargs::<_, (), (Option<Option<f64>>,), ()>(args.keywords, &[], &["timeout"])?;
+    let (timeout,) = kwargs.optional;
+    timeout.map(|timeout| parse_timeout(ruby, timeout)).transpose()
+}
+
+// Scores a detector against labeled samples. The texts are detected natively and in
+// parallel, and only the tallies ever become Ruby objects.
+#[derive(TypedData)]
+#[magnus(class = "LinguaRsRb::Evaluator", mark)]
+struct Evaluator {
+    detector: Opaque<Value>,
+}
+
+impl DataTypeFunctions for Evaluator {
+    // Marking pins the detector, as it does for `DetectorPool`.
+    fn mark(&self, marker: &Marker) {
+        marker.mark(Ruby::get().unwrap().get_inner(self.detector));
+    }
+}
+
+impl Evaluator {
+    fn new(detector: Obj<LanguageDetectorWrapper>) -> Result<Obj<Self>, Error> {
+        let evaluator = Self {
+            detector: Opaque::from(detector.as_value()),
+        };
+        new_as_receiver(evaluator, &[detector.as_value()])
+    }
+
+    fn detector(ruby: &Ruby, rb_self: &Self) -> Value {
+        ruby.get_inner(rb_self.detector)
+    }
+
+    // Samples are a Hash of text => expected language, or any Enumerable of such pairs.
+    // Expected languages are compared with the detector's labels as given.
+    fn evaluate(ruby: &Ruby, rb_self: &Self, args: &[Value]) -> Result<EvaluationReport, Error> {
+        let args = scan_args::<(Value,), (), (), (), RHash, ()>(args)?;
+        let (samples,) = args.required;
+        let batch = parse_only_batch_options(ruby, args.keywords)?;
+        let detector =
+            Obj::<LanguageDetectorWrapper>::try_convert(Self::detector(ruby, rb_self))?;
+        let detector: &LanguageDetectorWrapper = &detector;
+
+        let samples: RArray = samples.funcall("to_a", ())?;
+        let texts = ruby.ary_new_capa(samples.len());
+        let mut expected = Vec::with_capacity(samples.len());
+        for sample in samples.each() {
+            let (text, language): (RString, Value) = TryConvert::try_convert(sample?)?;
+            texts.push(text)?;
+            expected.push(language.funcall::<_, _, String>("to_s", ())?);
+        }
+
+        let detected =
+            detector.map_text_chunks(ruby, texts.as_value(), &batch, None, |lingua, text| {
+                let language = detector.detect_cached(lingua, text)?;
+                Some(detector.labeler(text).label(language))
+            })?;
+        let mut matrix = ConfusionMatrix::default();
+        for (expected, detected) in expected.into_iter().zip(detected) {
+            *matrix.counts.entry((expected, detected)).or_insert(0) += 1;
+        }
+        Ok(EvaluationReport { matrix })
+    }
+}
+
+#[magnus::wrap(class = "LinguaRsRb::EvaluationReport", free_immediately, frozen_shareable)]
+struct EvaluationReport {
+    matrix: ConfusionMatrix,
+}
+
+impl EvaluationReport {
+    fn size(&self) -> usize {
+        self.matrix.total()
+    }
+
+    fn accuracy(&self) -> f64 {
+        let labels = self.matrix.labels();
+        let correct = labels.iter().map(|label| self.matrix.correct(label)).sum();
+        ratio(correct, self.matrix.total())
+    }
+
+    fn precision(&self, language: Value) -> Result<f64, Error> {
+        let language: String = language.funcall("to_s", ())?;
+        Ok(ratio(self.matrix.correct(&language), self.matrix.detected(&language)))
+    }
+
+    fn recall(&self, language: Value) -> Result<f64, Error> {
+        let language: String = language.funcall("to_s", ())?;
+        Ok(ratio(self.matrix.correct(&language), self.matrix.expected(&language)))
+    }
+
+    fn scores(&self) -> BTreeMap<String, LanguageScores> {
+        let matrix = &self.matrix;
+        let scores = matrix.labels().into_iter().map(|label| {
+            let correct = matrix.correct(&label);
+            let scores = LanguageScores {
+                precision: ratio(correct, matrix.detected(&label)),
+                recall: ratio(correct, matrix.expected(&label)),
+                support: matrix.expected(&label),
+            };
+            (label, scores)
+        });
+        scores.collect()
+    }
+
+    fn per_language(ruby: &Ruby, rb_self: &Self) -> Result<RHash, Error> {
+        let languages = ruby.hash_new();
+        for (label, scores) in rb_self.scores() {
+            languages.aset(label, scores.to_h(ruby)?)?;
+        }
+        Ok(languages)
+    }
+
+    fn confusion_matrix(&self) -> ConfusionMatrix {
+        self.matrix.clone()
+    }
+
+    fn to_h(ruby: &Ruby, rb_self: &Self) -> Result<RHash, Error> {
+        let hash = ruby.hash_new();
+        hash.aset(ruby.to_symbol("size"), rb_self.size())?;
+        hash.aset(ruby.to_symbol("accuracy"), rb_self.accuracy())?;
+        hash.aset(ruby.to_symbol("per_language"), Self::per_language(ruby, rb_self)?)?;
+        Ok(hash)
+    }
+
+    fn to_json(ruby: &Ruby, rb_self: &Self, _args: &[Value]) -> Result<String, Error> {
+        #[derive(Serialize)]
+        struct Report {
+            size: usize,
+            accuracy: f64,
+            per_language: BTreeMap<String, LanguageScores>,
+        }
+        let report = Report {
+            size: rb_self.size(),
+            accuracy: rb_self.accuracy(),
+            per_language: rb_self.scores(),
+        };
+        to_json_string(ruby, &report)
+    }
+}
+
+#[derive(Serialize)]
+struct LanguageScores {
+    precision: f64,
+    recall: f64,
+    support: usize,
+}
+
+impl LanguageScores {
+    fn to_h(&self, ruby: &Ruby) -> Result<RHash, Error> {
+        let hash = ruby.hash_new();
+        hash.aset(ruby.to_symbol("precision"), self.precision)?;
+        hash.aset(ruby.to_symbol("recall"), self.recall)?;
+        hash.aset(ruby.to_symbol("support"), self.support)?;
+        Ok(hash)
+    }
+}
+
+// Counts keyed by expected and detected label; undetected samples have no detected label.
+#[magnus::wrap(class = "LinguaRsRb::ConfusionMatrix", free_immediately, frozen_shareable)]
+#[derive(Clone, Default)]
+struct ConfusionMatrix {
+    counts: BTreeMap<(String, Option<String>), usize>,
+}
+
+impl ConfusionMatrix {
+    fn labels(&self) -> Vec<String> {
+        let expected = self.counts.keys().map(|(expected, _)| expected);
+        let detected = self.counts.keys().filter_map(|(_, detected)| detected.as_ref());
+        let labels: BTreeSet<&String> = expected.chain(detected).collect();
+        labels.into_iter().cloned().collect()
+    }
+
+    fn count(&self, expected: String, detected: Option<String>) -> usize {
+        self.counts.get(&(expected, detected)).copied().unwrap_or(0)
+    }
+
+    fn total(&self) -> usize {
+        self.counts.values().sum()
+    }
+
+    fn correct(&self, label: &str) -> usize {
+        self.count(label.to_owned(), Some(label.to_owned()))
+    }
+
+    fn expected(&self, label: &str) -> usize {
+        let counts = self.counts.iter().filter(|((expected, _), _)| expected == label);
+        counts.map(|(_, count)| count).sum()
+    }
 
-use lingua::{
-    DetectionResult, IsoCode639_1, IsoCode639_3, Language, LanguageDetector,
-    LanguageDetectorBuilder,
-};
+    fn detected(&self, label: &str) -> usize {
+        let counts = self.counts.iter();
+        let counts = counts.filter(|((_, detected), _)| detected.as_deref() == Some(label));
+        counts.map(|(_, count)| count).sum()
+    }
 
-#[magnus::wrap(class = "LinguaRsRb::LanguageDetector")]
-struct LanguageDetectorWrapper(LanguageDetector);
+    // Rows are expected and columns detected languages, both in `labels` order.
+    fn to_a(&self) -> Vec<Vec<usize>> {
+        let labels = self.labels();
+        labels
+            .iter()
+            .map(|expected| {
+                let detected = labels.iter().map(|label| Some(label.clone()));
+                detected.map(|detected| self.count(expected.clone(), detected)).collect()
+            })
+            .collect()
+    }
 
-#[magnus::wrap(class = "LinguaRsRb::LanguageDetectorBuilder")]
-struct LanguageDetectorBuilderWrapper(RefCell<Option<LanguageDetectorBuilder>>);
+    // Undetected samples per expected language, in `labels` order.
+    fn undetected(&self) -> Vec<usize> {
+        let labels = self.labels();
+        labels.into_iter().map(|label| self.count(label, None)).collect()
+    }
 
-impl LanguageDetectorBuilderWrapper {
-    fn new(builder: LanguageDetectorBuilder) -> Self {
-        Self(RefCell::new(Some(builder)))
+    fn to_h(ruby: &Ruby, rb_self: &Self) -> Result<RHash, Error> {
+        let hash = ruby.hash_new();
+        hash.aset(ruby.to_symbol("labels"), rb_self.labels())?;
+        hash.aset(ruby.to_symbol("counts"), rb_self.to_a())?;
+        hash.aset(ruby.to_symbol("undetected"), rb_self.undetected())?;
+        Ok(hash)
     }
 
-    fn with_minimum_relative_distance(
-        ruby: &Ruby,
-        rb_self: &Self,
-        distance: f64,
-    ) -> Result<Value, Error> {
-        if !(0.0..=1.0).contains(&distance) {
+    fn to_json(ruby: &Ruby, rb_self: &Self, _args: &[Value]) -> Result<String, Error> {
+        #[derive(Serialize)]
+        struct Matrix {
+            labels: Vec<String>,
+            counts: Vec<Vec<usize>>,
+            undetected: Vec<usize>,
+        }
+        let matrix = Matrix {
+            labels: rb_self.labels(),
+            counts: rb_self.to_a(),
+            undetected: rb_self.undetected(),
+        };
+        to_json_string(ruby, &matrix)
+    }
+}
+
+// Result objects skip the Ruby `json` library: `to_json` accepts and ignores the generator
+// state `JSON.generate` passes, and NaN confidences become null.
+fn to_json_string(ruby: &Ruby, value: &impl Serialize) -> Result<String, Error> {
+    serde_json::to_string(value).map_err(|err| {
+        Error::new(
+            ruby.exception_runtime_error(),
+            format!("could not serialize result: {err}"),
+        )
+    })
+}
+
+fn ratio(count: usize, total: usize) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        count as f64 / total as f64
+    }
+}
+
+// Mirrors lingua's own selection rule for `detect_language_of`, so results can be derived
+// from confidence values that have already been computed. A lone candidate still needs a
+// non-zero confidence, as it has none for text without letters.
+fn most_likely_language(
+    values: &[(Language, f64)],
+    minimum_relative_distance: f64,
+) -> Option<(Language, f64)> {
+    let (language, confidence) = *values.first()?;
+    let Some(&(_, second_confidence)) = values.get(1) else {
+        return (confidence != 0.0).then_some((language, confidence));
+    };
+
+    if (confidence - second_confidence).abs() < f64::EPSILON
+        || confidence - second_confidence < minimum_relative_distance
+    {
+        return None;
+    }
+
+    Some((language, confidence))
+}
+
+fn restrict_confidence_values(
+    values: Vec<(Language, f64)>,
+    languages: &[Language],
+) -> Vec<(Language, f64)> {
+    let mut values: Vec<(Language, f64)> = values
+        .into_iter()
+        .filter(|(language, _)| languages.contains(language))
+        .collect();
+
+    let total: f64 = values.iter().map(|(_, confidence)| confidence).sum();
+    if total > 0.0 {
+        for (_, confidence) in values.iter_mut() {
+            *confidence /= total;
+        }
+    }
+    values
+}
+
+fn meets_min_confidence(confidence: Option<f64>, min_confidence: Option<f64>) -> bool {
+    match (confidence, min_confidence) {
+        (Some(confidence), Some(min_confidence)) => confidence >= min_confidence,
+        _ => true,
+    }
+}
+
+fn top_two_gap(values: &[(Language, f64)]) -> f64 {
+    match values {
+        [] => 0.0,
+        [(_, only)] => *only,
+        [(_, first), (_, second), ..] => first - second,
+    }
+}
+
+struct ConfidenceOptions {
+    top_k: Option<usize>,
+    min_confidence: Option<f64>,
+}
+
+struct BatchOptions {
+    chunk_size: usize,
+    partial: bool,
+    timeout: Option<(Duration, Instant)>,
+    progress: Option<(Value, usize)>,
+}
+
+impl BatchOptions {
+    // With `partial: true`, an interrupt or timeout keeps the results finished so far.
+    fn returns_partial_on(&self, ruby: &Ruby, err: &Error) -> bool {
+        self.partial
+            && (err.is_kind_of(ruby.exception_interrupt())
+                || err.is_kind_of(ruby.get_inner(&TIMEOUT_ERROR)))
+    }
+}
+
+// Returns the batch options plus the remaining keywords for the caller to parse.
+fn parse_batch_options(ruby: &Ruby, keywords: RHash) -> Result<(BatchOptions, RHash), Error> {
+    type Optional = (
+        Option<usize>,
+        Option<bool>,
+        Option<f64>,
+        Option<Value>,
+        Option<usize>,
+    );
+    let kwargs = get_kwargs::<_, (), Optional, RHash>(
+        keywords,
+        &[],
+        &["chunk_size", "partial", "timeout", "progress", "progress_every"],
+    )?;
+    let (chunk_size, partial, timeout, progress, progress_every) = kwargs.optional;
+    if chunk_size == Some(0) {
+        return Err(Error::new(
+            ruby.exception_arg_error(),
+            "chunk_size must be at least 1",
+        ));
+    }
+    if progress_every == Some(0) {
+        return Err(Error::new(
+            ruby.exception_arg_error(),
+            "progress_every must be at least 1",
+        ));
+    }
+
+    let timeout = parse_timeout(ruby, timeout)?;
+    // The deadline is checked between chunks, so default to small ones under a timeout.
+    let mut default_chunk_size = if timeout.is_some() {
+        worker_thread_count().max(1) * TIMEOUT_CHUNKS_PER_THREAD
+    } else {
+        DEFAULT_CHUNK_SIZE
+    };
+    // Progress is reported between chunks too, so chunks never outgrow the interval.
+    if let Some(every) = progress_every {
+        default_chunk_size = default_chunk_size.min(every);
+    }
+    let chunk_size = chunk_size.unwrap_or(default_chunk_size);
+    let progress = progress
+        .filter(|progress| !progress.is_nil())
+        .map(|progress| (progress, progress_every.unwrap_or(chunk_size)));
+
+    let options = BatchOptions {
+        chunk_size,
+        partial: partial.unwrap_or(false),
+        timeout: timeout.map(|timeout| (timeout, Instant::now() + timeout)),
+        progress,
+    };
+    Ok((options, kwargs.splat))
+}
+
+#[derive(Serialize)]
+struct JsonLine {
+    id: serde_json::Value,
+    language: Option<String>,
+    confidence: Option<f64>,
+}
+
+enum JsonLinesSink {
+    File(BufWriter<std::fs::File>, String),
+    Io(Value),
+}
+
+impl JsonLinesSink {
+    fn open(ruby: &Ruby, to: Value) -> Result<Self, Error> {
+        if let Some(path) = RString::from_value(to) {
+            let path = path.to_string()?;
+            let file =
+                std::fs::File::create(&path).map_err(|err| write_error(ruby, &path, err))?;
+            return Ok(Self::File(BufWriter::new(file), path));
+        }
+        if !to.respond_to("write", false)? {
+            return Err(Error::new(
+                ruby.exception_type_error(),
+                "to: must be a path or respond to write",
+            ));
+        }
+        Ok(Self::Io(to))
+    }
+
+    fn write(&mut self, ruby: &Ruby, lines: &str) -> Result<(), Error> {
+        match self {
+            Self::File(file, path) => file
+                .write_all(lines.as_bytes())
+                .map_err(|err| write_error(ruby, path, err)),
+            Self::Io(io) => io.funcall::<_, _, Value>("write", (lines,)).map(drop),
+        }
+    }
+
+    fn finish(self, ruby: &Ruby) -> Result<(), Error> {
+        match self {
+            Self::File(mut file, path) => file.flush().map_err(|err| write_error(ruby, &path, err)),
+            Self::Io(_) => Ok(()),
+        }
+    }
+}
+
+fn write_error(ruby: &Ruby, path: &str, err: std::io::Error) -> Error {
+    Error::new(
+        ruby.exception_io_error(),
+        format!("could not write {path}: {err}"),
+    )
+}
+
+fn json_id(key: Value) -> Result<serde_json::Value, Error> {
+    if key.is_nil() {
+        return Ok(serde_json::Value::Null);
+    }
+    if let Some(key) = Integer::from_value(key) {
+        return Ok(key.to_i64()?.into());
+    }
+    Ok(key.funcall::<_, _, String>("to_s", ())?.into())
+}
+
+// A label, or `[label, confidence]` when the confidence was asked for.
+fn detection_to_value(ruby: &Ruby, detected: Option<(String, Option<f64>)>) -> Value {
+    match detected {
+        Some((label, Some(confidence))) => (label, confidence).into_value_with(ruby),
+        Some((label, None)) => label.into_value_with(ruby),
+        None => ruby.qnil().as_value(),
+    }
+}
+
+// The buffers of an `Arrow::StringArray`, copied out once. Offsets are 32-bit and, like
+// the validity bitmap, indexed from the array's own offset into them.
+struct ArrowStrings {
+    length: usize,
+    offset: usize,
+    offsets: Vec<u8>,
+    data: Vec<u8>,
+    validity: Option<Vec<u8>>,
+}
+
+impl ArrowStrings {
+    fn read(ruby: &Ruby, array: Value) -> Result<Self, Error> {
+        let string_array = arrow_class(ruby, "StringArray")?;
+        if !array.is_kind_of(string_array) {
+            return Err(Error::new(
+                ruby.exception_type_error(),
+                "expected an Arrow::StringArray",
+            ));
+        }
+        let validity = match array.funcall::<_, _, Option<Value>>("null_bitmap", ())? {
+            Some(bitmap) => Some(arrow_buffer_bytes(bitmap)?),
+            None => None,
+        };
+        let strings = Self {
+            length: array.funcall("length", ())?,
+            offset: array.funcall("offset", ())?,
+            offsets: arrow_buffer_bytes(array.funcall("offsets_buffer", ())?)?,
+            data: arrow_buffer_bytes(array.funcall("data_buffer", ())?)?,
+            validity,
+        };
+        let valid = strings.offsets.len() >= (strings.offset + strings.length + 1) * 4
+            && (0..strings.length).all(|index| {
+                let (start, end) = strings.bounds(index);
+                start <= end && end <= strings.data.len()
+            });
+        if !valid {
             return Err(Error::new(
                 ruby.exception_arg_error(),
-                "minimum relative distance must be between 0.0 and 1.0",
+                "malformed Arrow::StringArray buffers",
             ));
         }
+        Ok(strings)
+    }
 
-        let mut builder = take_builder(ruby, rb_self)?;
-        builder.with_minimum_relative_distance(distance);
-        *rb_self.0.borrow_mut() = Some(builder);
-        magnus::current_receiver::<Value>()
+    fn bounds(&self, index: usize) -> (usize, usize) {
+        let at = |position: usize| {
+            let bytes = &self.offsets[position * 4..position * 4 + 4];
+            i32::from_le_bytes(bytes.try_into().unwrap()).max(0) as usize
+        };
+        (at(self.offset + index), at(self.offset + index + 1))
     }
 
-    fn with_preloaded_language_models(
-        ruby: &Ruby,
-        rb_self: &Self,
-    ) -> Result<Value, Error> {
-        let mut builder = take_builder(ruby, rb_self)?;
+    fn value(&self, index: usize) -> Option<&[u8]> {
+        let bit = self.offset + index;
+        let valid = self.validity.as_ref().map_or(true, |validity| {
+            validity.get(bit / 8).is_some_and(|byte| byte & (1 << (bit % 8)) != 0)
+        });
+        let (start, end) = self.bounds(index);
+        valid.then(|| &self.data[start..end])
+    }
+}
+
+struct ArrowStringBuilder {
+    offsets: Vec<u8>,
+    data: Vec<u8>,
+    validity: ArrowValidity,
+}
+
+impl ArrowStringBuilder {
+    fn new() -> Self {
+        Self {
+            offsets: 0i32.to_le_bytes().to_vec(),
+            data: Vec::new(),
+            validity: ArrowValidity::default(),
+        }
+    }
+
+    fn push(&mut self, value: Option<String>) {
+        self.validity.push(value.is_some());
+        self.data.extend(value.unwrap_or_default().into_bytes());
+        self.offsets.extend((self.data.len() as i32).to_le_bytes());
+    }
+
+    fn finish(self, ruby: &Ruby) -> Result<Value, Error> {
+        let length = self.validity.length;
+        let (bitmap, nulls) = self.validity.finish(ruby)?;
+        let offsets = arrow_buffer(ruby, &self.offsets)?;
+        let data = arrow_buffer(ruby, &self.data)?;
+        arrow_class(ruby, "StringArray")?.funcall("new", (length, offsets, data, bitmap, nulls))
+    }
+}
+
+#[derive(Default)]
+struct ArrowValidity {
+    bitmap: Vec<u8>,
+    length: usize,
+    nulls: usize,
+}
+
+impl ArrowValidity {
+    fn push(&mut self, valid: bool) {
+        if self.length % 8 == 0 {
+            self.bitmap.push(0);
+        }
+        if valid {
+            *self.bitmap.last_mut().unwrap() |= 1 << (self.length % 8);
+        } else {
+            self.nulls += 1;
+        }
+        self.length += 1;
+    }
+
+    // Arrays without nulls are built without a bitmap.
+    fn finish(self, ruby: &Ruby) -> Result<(Option<Value>, usize), Error> {
+        if self.nulls == 0 {
+            return Ok((None, 0));
+        }
+        Ok((Some(arrow_buffer(ruby, &self.bitmap)?), self.nulls))
+    }
+}
+
+fn arrow_double_array(ruby: &Ruby, values: &[Option<f64>]) -> Result<Value, Error> {
+    let mut validity = ArrowValidity::default();
+    let mut data = Vec::with_capacity(values.len() * 8);
+    for value in values {
+        validity.push(value.is_some());
+        data.extend(value.unwrap_or(0.0).to_le_bytes());
+    }
+    let (bitmap, nulls) = validity.finish(ruby)?;
+    let data = arrow_buffer(ruby, &data)?;
+    arrow_class(ruby, "DoubleArray")?.funcall("new", (values.len(), data, bitmap, nulls))
+}
+
+// red-arrow is an optional dependency, loaded by whoever passes Arrow arrays in.
+fn arrow_class(ruby: &Ruby, name: &str) -> Result<RClass, Error> {
+    let loaded: bool = ruby.class_object().funcall("const_defined?", ("Arrow",))?;
+    if !loaded {
+        return Err(Error::new(
+            ruby.exception_type_error(),
+            "expected an Arrow::StringArray (red-arrow is not loaded)",
+        ));
+    }
+    let arrow: Value = ruby.class_object().const_get("Arrow")?;
+    RClass::try_convert(arrow.funcall("const_get", (name,))?)
+}
+
+fn arrow_buffer(ruby: &Ruby, bytes: &[u8]) -> Result<Value, Error> {
+    arrow_class(ruby, "Buffer")?.funcall("new", (ruby.str_from_slice(bytes),))
+}
+
+fn arrow_buffer_bytes(buffer: Value) -> Result<Vec<u8>, Error> {
+    let data: RString = buffer.funcall::<_, _, Value>("data", ())?.funcall("to_s", ())?;
+    Ok(unsafe { data.as_slice() }.to_vec())
+}
+
+// `col_sep:` wins; otherwise `.tsv` and `.tab` files are tab separated and the rest comma.
+fn csv_delimiter(ruby: &Ruby, path: &str, col_sep: Option<String>) -> Result<u8, Error> {
+    let Some(col_sep) = col_sep else {
+        let is_tsv = Path::new(path).extension().is_some_and(|extension| {
+            extension.eq_ignore_ascii_case("tsv") || extension.eq_ignore_ascii_case("tab")
+        });
+        return Ok(if is_tsv { b'\t' } else { b',' });
+    };
+    match col_sep.as_bytes() {
+        [delimiter] => Ok(*delimiter),
+        _ => Err(Error::new(
+            ruby.exception_arg_error(),
+            format!("col_sep must be a single byte, got {col_sep:?}"),
+        )),
+    }
+}
+
+fn file_error(ruby: &Ruby, action: &str, path: &str, err: csv::Error) -> Error {
+    Error::new(
+        ruby.exception_io_error(),
+        format!("could not {action} {path}: {err}"),
+    )
+}
+
+fn parse_only_batch_options(ruby: &Ruby, keywords: RHash) -> Result<BatchOptions, Error> {
+    let (options, rest) = parse_batch_options(ruby, keywords)?;
+    get_kwargs::<_, (), (), ()>(rest, &[], &[])?;
+    Ok(options)
+}
+
+fn parse_confidence_options(keywords: RHash) -> Result<ConfidenceOptions, Error> {
+    let kwargs = get_kwargs::<_, (), (Option<usize>, Option<f64>), ()>(
+        keywords,
+        &[],
+        &["top_k", "min_confidence"],
+    )?;
+    let (top_k, min_confidence) = kwargs.optional;
+    Ok(ConfidenceOptions {
+        top_k,
+        min_confidence,
+    })
+}
+
+fn confidence_values_to_tuples(
+    values: Vec<(Language, f64)>,
+    options: &ConfidenceOptions,
+    labeler: &Labeler<'_>,
+) -> Vec<(String, f64)> {
+    let values = labeler.merge(values);
+    let limit = options.top_k.unwrap_or(values.len());
+    let min_confidence = options.min_confidence.unwrap_or(0.0);
+    values
+        .into_iter()
+        .filter(|(_, confidence)| *confidence >= min_confidence)
+        .take(limit)
+        .collect()
+}
+
+// `latencies` are per text on the single path and per whole batch on the parallel one.
+fn benchmark_report(
+    ruby: &Ruby,
+    mut latencies: Vec<Duration>,
+    texts: usize,
+) -> Result<RHash, Error> {
+    latencies.sort();
+    let total: Duration = latencies.iter().sum();
+    let texts_per_second = if total.is_zero() {
+        0.0
+    } else {
+        texts as f64 / total.as_secs_f64()
+    };
+    let percentile = |p: f64| {
+        let rank = ((latencies.len() as f64 * p).ceil() as usize).max(1);
+        latencies.get(rank - 1).map_or(0.0, Duration::as_secs_f64)
+    };
+
+    let report = ruby.hash_new();
+    report.aset(ruby.to_symbol("total_time"), total.as_secs_f64())?;
+    report.aset(ruby.to_symbol("texts_per_second"), texts_per_second)?;
+    report.aset(ruby.to_symbol("p50"), percentile(0.5))?;
+    report.aset(ruby.to_symbol("p99"), percentile(0.99))?;
+    Ok(report)
+}
+
+fn sentence_spans(text: &str) -> Vec<(usize, usize)> {
+    text.split_sentence_bound_indices()
+        .filter_map(|(start, sentence)| {
+            let trimmed = sentence.trim_end();
+            let leading = trimmed.len() - trimmed.trim_start().len();
+            (!trimmed.is_empty()).then_some((start + leading, start + trimmed.len()))
+        })
+        .collect()
+}
+
+// Optional fields appended to each `[language, start, end]` span, in declaration order.
+#[derive(Clone, Copy)]
+struct SpanFields {
+    confidence: bool,
+    script: bool,
+    direction: bool,
+}
+
+fn parse_span_fields(keywords: RHash) -> Result<SpanFields, Error> {
+    let kwargs = get_kwargs::<_, (), (Option<bool>, Option<bool>, Option<bool>), ()>(
+        keywords,
+        &[],
+        &["confidence", "script", "direction"],
+    )?;
+    let (confidence, script, direction) = kwargs.optional;
+    Ok(SpanFields {
+        confidence: confidence.unwrap_or(false),
+        script: script.unwrap_or(false),
+        direction: direction.unwrap_or(false),
+    })
+}
+
+#[derive(Clone)]
+struct DetectedSpan {
+    language: Language,
+    label: String,
+    start: usize,
+    end: usize,
+    confidence: f64,
+    script: Option<&'static str>,
+}
+
+// Every character n-gram lingua could score, taken within lowercased words and kept in
+// order of first appearance.
+fn text_ngrams(text: &str) -> Vec<String> {
+    let mut seen = HashSet::new();
+    let mut ngrams = Vec::new();
+    for word in text.unicode_words() {
+        let chars: Vec<char> = word
+            .to_lowercase()
+            .chars()
+            .filter(|c| c.is_alphabetic())
+            .collect();
+        for length in 1..=MAX_NGRAM_LENGTH.min(chars.len()) {
+            for window in chars.windows(length) {
+                let ngram: String = window.iter().collect();
+                if seen.insert(ngram.clone()) {
+                    ngrams.push(ngram);
+                }
+            }
+        }
+    }
+    ngrams
+}
+
+fn confidence_margin(values: &[(Language, f64)], language: Language) -> f64 {
+    let mut own = 0.0;
+    let mut rival: f64 = 0.0;
+    for &(candidate, confidence) in values {
+        if candidate == language {
+            own = confidence;
+        } else {
+            rival = rival.max(confidence);
+        }
+    }
+    own - rival
+}
+
+// A span's confidence is that of its language over the span's own text, calibrated like
+// every other confidence the detector reports.
+fn detect_spans(
+    detector: &LanguageDetectorWrapper,
+    lingua: &LanguageDetector,
+    text: &str,
+    fields: SpanFields,
+) -> Vec<DetectedSpan> {
+    lingua
+        .detect_multiple_languages_of(text)
+        .into_iter()
+        .map(|result| {
+            let span = &text[result.start_index()..result.end_index()];
+            let language = result.language();
+            DetectedSpan {
+                language,
+                label: detector.labeler(span).label(language),
+                start: result.start_index(),
+                end: result.end_index(),
+                confidence: if fields.confidence {
+                    detector.labeled_confidence_with(lingua, span, language)
+                } else {
+                    0.0
+                },
+                script: if fields.script { script_code(span) } else { None },
+            }
+        })
+        .collect()
+}
+
+fn spans_to_array(
+    ruby: &Ruby,
+    spans: Vec<DetectedSpan>,
+    fields: SpanFields,
+) -> Result<RArray, Error> {
+    let array = ruby.ary_new_capa(spans.len());
+    for span in spans {
+        let row = ruby.ary_new();
+        row.push(span.label)?;
+        row.push(span.start)?;
+        row.push(span.end)?;
+        if fields.confidence {
+            row.push(span.confidence)?;
+        }
+        if fields.script {
+            row.push(span.script)?;
+        }
+        if fields.direction {
+            row.push(ruby.to_symbol(text_direction(span.language)))?;
+        }
+        array.push(row)?;
+    }
+    Ok(array)
+}
+
+fn build_detector(languages: &HashSet<Language>, options: &DetectorOptions) -> LanguageDetector {
+    let languages: Vec<Language> = languages.iter().copied().collect();
+    let mut builder = LanguageDetectorBuilder::from_languages(&languages);
+    builder.with_minimum_relative_distance(options.minimum_relative_distance);
+    if options.low_accuracy_mode || options.hybrid_threshold.is_some() {
+        builder.with_low_accuracy_mode();
+    }
+    if options.preloaded_language_models {
         builder.with_preloaded_language_models();
-        *rb_self.0.borrow_mut() = Some(builder);
-        magnus::current_receiver::<Value>()
     }
-
-    fn with_low_accuracy_mode(ruby: &Ruby, rb_self: &Self) -> Result<Value, Error> {
-        let mut builder = take_builder(ruby, rb_self)?;
-        builder.with_low_accuracy_mode();
-        *rb_self.0.borrow_mut() = Some(builder);
-        magnus::current_receiver::<Value>()
+    builder.build()
+}
+
+fn sorted_language_names(languages: &HashSet<Language>) -> Vec<String> {
+    let mut names: Vec<String> = languages.iter().map(|l| l.to_string()).collect();
+    names.sort();
+    names
+}
+
+fn parse_optional_flag(args: &[Value]) -> Result<bool, Error> {
+    let args = scan_args::<(), (Option<Value>,), (), (), (), ()>(args)?;
+    let (enabled,) = args.optional;
+    Ok(enabled.map_or(true, |value| value.to_bool()))
+}
+
+fn validate_minimum_relative_distance(ruby: &Ruby, distance: f64) -> Result<(), Error> {
+    if !(0.0..=1.0).contains(&distance) {
+        return Err(Error::new(
+            ruby.exception_arg_error(),
+            "minimum relative distance must be between 0.0 and 1.0",
+        ));
+    }
+    Ok(())
+}
+
+fn validate_confidence_thresholds(ruby: &Ruby, high: f64, medium: f64) -> Result<(), Error> {
+    if !(0.0..=1.0).contains(&medium) || !(medium..=1.0).contains(&high) {
+        return Err(Error::new(
+            ruby.exception_arg_error(),
+            "confidence thresholds must satisfy 0.0 <= medium <= high <= 1.0",
+        ));
+    }
+    Ok(())
+}
+
+fn validate_hybrid_threshold(ruby: &Ruby, threshold: f64) -> Result<(), Error> {
+    if !(0.0..=1.0).contains(&threshold) {
+        return Err(Error::new(
+            ruby.exception_arg_error(),
+            "hybrid threshold must be between 0.0 and 1.0",
+        ));
+    }
+    Ok(())
+}
+
+fn validate_min_confidence(ruby: &Ruby, min_confidence: f64) -> Result<(), Error> {
+    if !(0.0..=1.0).contains(&min_confidence) {
+        return Err(Error::new(
+            ruby.exception_arg_error(),
+            "min_confidence must be between 0.0 and 1.0",
+        ));
+    }
+    Ok(())
+}
+
+fn validate_fallback_threshold(ruby: &Ruby, threshold: f64) -> Result<(), Error> {
+    if !(0.0..=1.0).contains(&threshold) {
+        return Err(Error::new(
+            ruby.exception_arg_error(),
+            "fallback threshold must be between 0.0 and 1.0",
+        ));
+    }
+    Ok(())
+}
+
+fn config_to_hash(
+    ruby: &Ruby,
+    languages: &HashSet<Language>,
+    options: &DetectorOptions,
+) -> Result<RHash, Error> {
+    let hash = ruby.hash_new();
+    hash.aset(ruby.to_symbol("languages"), sorted_language_names(languages))?;
+    hash.aset(
+        ruby.to_symbol("minimum_relative_distance"),
+        options.minimum_relative_distance,
+    )?;
+    hash.aset(ruby.to_symbol("low_accuracy_mode"), options.low_accuracy_mode)?;
+    hash.aset(
+        ruby.to_symbol("preloaded_language_models"),
+        options.preloaded_language_models,
+    )?;
+    hash.aset(
+        ruby.to_symbol("high_confidence_threshold"),
+        options.high_confidence_threshold,
+    )?;
+    hash.aset(
+        ruby.to_symbol("medium_confidence_threshold"),
+        options.medium_confidence_threshold,
+    )?;
+    hash.aset(
+        ruby.to_symbol("invalid_utf8"),
+        ruby.to_symbol(options.invalid_utf8.name()),
+    )?;
+    hash.aset(ruby.to_symbol("result_cache_size"), options.result_cache_size)?;
+    hash.aset(ruby.to_symbol("hybrid_threshold"), options.hybrid_threshold)?;
+    hash.aset(
+        ruby.to_symbol("language_format"),
+        ruby.to_symbol(options.language_format.name()),
+    )?;
+    hash.aset(ruby.to_symbol("display_locale"), options.display_locale.name())?;
+    let merges = ruby.hash_new();
+    for (source, target) in &options.language_merges {
+        merges.aset(source.to_string(), target.as_str())?;
+    }
+    hash.aset(ruby.to_symbol("language_merges"), merges)?;
+    hash.aset(ruby.to_symbol("calibration"), options.calibration.to_value(ruby))?;
+    let preprocessing = &options.preprocessing;
+    hash.aset(
+        ruby.to_symbol("preprocess"),
+        preprocessing.markup.name().map(|name| ruby.to_symbol(name)),
+    )?;
+    hash.aset(ruby.to_symbol("strip_urls"), preprocessing.strip_urls)?;
+    hash.aset(
+        ruby.to_symbol("strip_social_tokens"),
+        preprocessing.strip_social_tokens,
+    )?;
+    hash.aset(ruby.to_symbol("strip_junk"), preprocessing.strip_junk)?;
+    hash.aset(ruby.to_symbol("minimum_letters"), preprocessing.minimum_letters)?;
+    hash.aset(
+        ruby.to_symbol("minimum_text_length"),
+        preprocessing.minimum_text_length,
+    )?;
+    hash.aset(ruby.to_symbol("max_text_length"), preprocessing.max_text_length)?;
+    hash.aset(
+        ruby.to_symbol("sampling"),
+        ruby.to_symbol(preprocessing.sampling.name()),
+    )?;
+    Ok(hash)
+}
+
+// Snapshots store `to_config_h` as JSON. Symbols are written as strings, which every
+// option parser accepts back.
+fn config_to_json(ruby: &Ruby, value: Value) -> Result<serde_json::Value, Error> {
+    if value.is_nil() {
+        return Ok(serde_json::Value::Null);
+    }
+    if value.is_kind_of(ruby.class_true_class()) || value.is_kind_of(ruby.class_false_class()) {
+        return Ok(value.to_bool().into());
+    }
+    if let Some(integer) = Integer::from_value(value) {
+        return Ok(integer.to_i64()?.into());
+    }
+    if let Some(float) = magnus::Float::from_value(value) {
+        return Ok(float.to_f64().into());
+    }
+    if let Some(symbol) = Symbol::from_value(value) {
+        return Ok(symbol.name()?.into_owned().into());
+    }
+    if let Some(array) = RArray::from_value(value) {
+        let items = array.to_vec::<Value>()?;
+        return items.into_iter().map(|item| config_to_json(ruby, item)).collect();
+    }
+    if let Some(hash) = RHash::from_value(value) {
+        let mut map = serde_json::Map::new();
+        hash.foreach(|key: Value, value: Value| {
+            let key: String = key.funcall("to_s", ())?;
+            map.insert(key, config_to_json(ruby, value)?);
+            Ok(ForEach::Continue)
+        })?;
+        return Ok(map.into());
     }
+    Ok(String::try_convert(value)?.into())
+}
 
-    fn build(ruby: &Ruby, rb_self: &Self) -> Result<LanguageDetectorWrapper, Error> {
-        let mut builder = take_builder(ruby, rb_self)?;
-        Ok(LanguageDetectorWrapper(builder.build()))
-    }
+fn config_from_json(ruby: &Ruby, value: &serde_json::Value) -> Result<Value, Error> {
+    Ok(match value {
+        serde_json::Value::Null => ruby.qnil().as_value(),
+        serde_json::Value::Bool(flag) => flag.into_value_with(ruby),
+        serde_json::Value::Number(number) => match number.as_i64() {
+            Some(integer) => integer.into_value_with(ruby),
+            None => number.as_f64().unwrap_or_default().into_value_with(ruby),
+        },
+        serde_json::Value::String(string) => string.as_str().into_value_with(ruby),
+        serde_json::Value::Array(items) => {
+            let array = ruby.ary_new();
+            for item in items {
+                array.push(config_from_json(ruby, item)?)?;
+            }
+            array.as_value()
+        }
+        serde_json::Value::Object(map) => {
+            let hash = ruby.hash_new();
+            for (key, value) in map {
+                hash.aset(key.as_str(), config_from_json(ruby, value)?)?;
+            }
+            hash.as_value()
+        }
+    })
 }
 
-impl LanguageDetectorWrapper {
-    fn unload_language_models(&self) {
-        self.0.unload_language_models();
-    }
+fn config_value<T: TryConvert>(ruby: &Ruby, config: RHash, key: &str) -> Result<Option<T>, Error> {
+    let value = match config.get(ruby.to_symbol(key)) {
+        Some(value) => Some(value),
+        None => config.get(key),
+    };
+    value
+        .filter(|value| !value.is_nil())
+        .map(T::try_convert)
+        .transpose()
+}
 
-    fn detect_language(&self, text: String) -> Option<String> {
-        self.0.detect_language_of(text).map(|lang| lang.to_string())
-    }
+fn config_from_hash(
+    ruby: &Ruby,
+    config: RHash,
+) -> Result<(HashSet<Language>, DetectorOptions), Error> {
+    let languages = match config_value::<Vec<String>>(ruby, config, "languages")? {
+        Some(languages) => parse_languages(ruby, languages)?.into_iter().collect(),
+        None => Language::all(),
+    };
 
-    fn detect_languages_in_parallel(&self, texts: Vec<String>) -> Vec<Option<String>> {
-        self.0
-            .detect_languages_in_parallel_of(&texts)
-            .into_iter()
-            .map(|lang| lang.map(|l| l.to_string()))
-            .collect()
+    let mut options = DetectorOptions::default();
+    if let Some(distance) = config_value(ruby, config, "minimum_relative_distance")? {
+        validate_minimum_relative_distance(ruby, distance)?;
+        options.minimum_relative_distance = distance;
     }
-
-    fn detect_multiple_languages(&self, text: String) -> Vec<(String, usize, usize)> {
-        self.0
-            .detect_multiple_languages_of(text)
-            .into_iter()
-            .map(detection_result_to_tuple)
-            .collect()
+    if let Some(enabled) = config_value(ruby, config, "low_accuracy_mode")? {
+        options.low_accuracy_mode = enabled;
     }
-
-    fn detect_multiple_languages_in_parallel(
-        &self,
-        texts: Vec<String>,
-    ) -> Vec<Vec<(String, usize, usize)>> {
-        self.0
-            .detect_multiple_languages_in_parallel_of(&texts)
-            .into_iter()
-            .map(|results| results.into_iter().map(detection_result_to_tuple).collect())
-            .collect()
+    if let Some(enabled) = config_value(ruby, config, "preloaded_language_models")? {
+        options.preloaded_language_models = enabled;
     }
-
-    fn compute_language_confidence_values(&self, text: String) -> Vec<(String, f64)> {
-        self.0
-            .compute_language_confidence_values(text)
-            .into_iter()
-            .map(|(language, confidence)| (language.to_string(), confidence))
-            .collect()
+    let high = config_value(ruby, config, "high_confidence_threshold")?
+        .unwrap_or(options.high_confidence_threshold);
+    let medium = config_value(ruby, config, "medium_confidence_threshold")?
+        .unwrap_or(options.medium_confidence_threshold);
+    validate_confidence_thresholds(ruby, high, medium)?;
+    options.high_confidence_threshold = high;
+    options.medium_confidence_threshold = medium;
+    if let Some(policy) = config_value(ruby, config, "invalid_utf8")? {
+        options.invalid_utf8 = InvalidUtf8Policy::parse(ruby, policy)?;
     }
-
-    fn compute_language_confidence_values_in_parallel(
-        &self,
-        texts: Vec<String>,
-    ) -> Vec<Vec<(String, f64)>> {
-        self.0
-            .compute_language_confidence_values_in_parallel(&texts)
-            .into_iter()
-            .map(|values| {
-                values
-                    .into_iter()
-                    .map(|(language, confidence)| (language.to_string(), confidence))
-                    .collect()
-            })
-            .collect()
+    if let Some(size) = config_value(ruby, config, "result_cache_size")? {
+        options.result_cache_size = size;
     }
-
-    fn compute_language_confidence(
-        ruby: &Ruby,
-        rb_self: &Self,
-        text: String,
-        language_value: Value,
-    ) -> Result<f64, Error> {
-        let language = parse_language_value(ruby, language_value)?;
-        Ok(rb_self.0.compute_language_confidence(text, language))
+    if let Some(threshold) = config_value(ruby, config, "hybrid_threshold")? {
+        validate_hybrid_threshold(ruby, threshold)?;
+        options.hybrid_threshold = Some(threshold);
     }
-
-    fn compute_language_confidence_in_parallel(
-        ruby: &Ruby,
-        rb_self: &Self,
-        texts: Vec<String>,
-        language_value: Value,
-    ) -> Result<Vec<f64>, Error> {
-        let language = parse_language_value(ruby, language_value)?;
-        Ok(rb_self
-            .0
-            .compute_language_confidence_in_parallel(&texts, language))
+    if let Some(format) = config_value(ruby, config, "language_format")? {
+        options.language_format = LanguageFormat::parse(ruby, format)?;
+    }
+    if let Some(locale) = config_value(ruby, config, "display_locale")? {
+        options.display_locale = DisplayLocale::parse(ruby, locale)?;
+    }
+    if let Some(merges) = config_value(ruby, config, "language_merges")? {
+        options.language_merges = parse_language_merges(ruby, merges)?;
     }
+    if let Some(calibration) = config_value(ruby, config, "calibration")? {
+        options.calibration = Calibration::parse(ruby, calibration)?;
+    }
+    if let Some(mode) = config_value(ruby, config, "preprocess")? {
+        options.preprocessing.markup = Markup::parse(ruby, mode)?;
+    }
+    if let Some(enabled) = config_value(ruby, config, "strip_urls")? {
+        options.preprocessing.strip_urls = enabled;
+    }
+    if let Some(enabled) = config_value(ruby, config, "strip_social_tokens")? {
+        options.preprocessing.strip_social_tokens = enabled;
+    }
+    if let Some(enabled) = config_value(ruby, config, "strip_junk")? {
+        options.preprocessing.strip_junk = enabled;
+    }
+    if let Some(letters) = config_value(ruby, config, "minimum_letters")? {
+        options.preprocessing.minimum_letters = letters;
+    }
+    if let Some(min_length) = config_value(ruby, config, "minimum_text_length")? {
+        options.preprocessing.minimum_text_length = min_length;
+    }
+    if let Some(max_length) = config_value(ruby, config, "max_text_length")? {
+        validate_max_text_length(ruby, Some(max_length))?;
+        options.preprocessing.max_text_length = Some(max_length);
+    }
+    if let Some(strategy) = config_value(ruby, config, "sampling")? {
+        options.preprocessing.sampling = SamplingStrategy::parse(ruby, strategy)?;
+    }
+
+    Ok((languages, options))
+}
+
+fn lock_ignoring_poison<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
 }
 
-fn detection_result_to_tuple(result: DetectionResult) -> (String, usize, usize) {
-    (result.language().to_string(), result.start_index(), result.end_index())
+// For GC mark callbacks, which must never block.
+fn try_lock_ignoring_poison<T>(mutex: &Mutex<T>) -> Option<MutexGuard<'_, T>> {
+    match mutex.try_lock() {
+        Ok(guard) => Some(guard),
+        Err(TryLockError::Poisoned(poisoned)) => Some(poisoned.into_inner()),
+        Err(TryLockError::WouldBlock) => None,
+    }
 }
 
-fn take_builder(
+fn lock_builder<'a>(
     ruby: &Ruby,
-    wrapper: &LanguageDetectorBuilderWrapper,
-) -> Result<LanguageDetectorBuilder, Error> {
-    wrapper.0.borrow_mut().take().ok_or_else(|| {
-        Error::new(
+    wrapper: &'a LanguageDetectorBuilderWrapper,
+) -> Result<MutexGuard<'a, BuilderState>, Error> {
+    match wrapper.0.try_lock() {
+        Ok(state) => Ok(state),
+        Err(TryLockError::Poisoned(poisoned)) => Ok(poisoned.into_inner()),
+        Err(TryLockError::WouldBlock) => Err(Error::new(
             ruby.exception_runtime_error(),
-            "language detector builder has already been consumed",
-        )
-    })
+            "language detector builder is being configured by another thread",
+        )),
+    }
+}
+
+fn resolve_language_name(name: &str) -> Option<Language> {
+    let exact = Language::from_str(name)
+        .ok()
+        .filter(|language| language.to_string() == name);
+    if exact.is_some() || STRICT_LANGUAGE_NAMES.load(Ordering::Relaxed) {
+        return exact;
+    }
+
+    let normalized = name.trim().to_lowercase();
+    let canonical = LANGUAGE_ALIASES
+        .iter()
+        .find(|(alias, _)| *alias == normalized)
+        .map_or(normalized.as_str(), |(_, canonical)| canonical);
+
+    Language::all()
+        .into_iter()
+        .find(|language| language.to_string().eq_ignore_ascii_case(canonical))
 }
 
 fn parse_language_value(ruby: &Ruby, value: Value) -> Result<Language, Error> {
     let name: String = value.funcall("to_s", ())?;
-    Language::from_str(&name).map_err(|_| {
-        Error::new(
-            ruby.exception_arg_error(),
-            format!("unknown language: {name}"),
-        )
+    resolve_language_name(&name).ok_or_else(|| {
+        unknown_language_error(ruby, format!("unknown language: {name}"))
     })
 }
 
+fn default_language_groups() -> Vec<(String, Vec<Language>)> {
+    LANGUAGE_GROUPS
+        .iter()
+        .map(|(group, members)| {
+            let members = members.iter().filter_map(|name| resolve_language_name(name));
+            (group.to_string(), members.collect())
+        })
+        .collect()
+}
+
+fn parse_language_groups(
+    ruby: &Ruby,
+    groups: RHash,
+) -> Result<Vec<(String, Vec<Language>)>, Error> {
+    let mut parsed = Vec::with_capacity(groups.len());
+    groups.foreach(|group: Value, members: Vec<String>| {
+        let group: String = group.funcall("to_s", ())?;
+        parsed.push((group, parse_languages(ruby, members)?));
+        Ok(ForEach::Continue)
+    })?;
+    Ok(parsed)
+}
+
+fn language_groups(ruby: &Ruby) -> Result<RHash, Error> {
+    let groups = ruby.hash_new();
+    for (group, members) in default_language_groups() {
+        let members: Vec<String> = members.iter().map(|language| language.to_string()).collect();
+        groups.aset(group, members)?;
+    }
+    Ok(groups)
+}
+
+// Sources are sorted so that `to_config_h` round-trips regardless of insertion order.
+fn parse_language_merges(ruby: &Ruby, mapping: RHash) -> Result<Vec<(Language, String)>, Error> {
+    let mut merges = Vec::with_capacity(mapping.len());
+    mapping.foreach(|source: Value, target: Value| {
+        let target: String = target.funcall("to_s", ())?;
+        merges.push((parse_language_value(ruby, source)?, target));
+        Ok(ForEach::Continue)
+    })?;
+    merges.sort_by_key(|(source, _)| *source);
+    merges.dedup_by_key(|(source, _)| *source);
+    Ok(merges)
+}
+
 fn parse_languages(ruby: &Ruby, values: Vec<String>) -> Result<Vec<Language>, Error> {
     if values.is_empty() {
         return Err(Error::new(
@@ -179,11 +5470,8 @@ fn parse_languages(ruby: &Ruby, values: Vec<String>) -> Result<Vec<Language>, Er
     values
         .into_iter()
         .map(|value| {
-            Language::from_str(&value).map_err(|_| {
-                Error::new(
-                    ruby.exception_arg_error(),
-                    format!("unknown language: {value}"),
-                )
+            resolve_language_name(&value).ok_or_else(|| {
+                unknown_language_error(ruby, format!("unknown language: {value}"))
             })
         })
         .collect()
@@ -204,10 +5492,7 @@ fn parse_iso_codes_639_1(
         .into_iter()
         .map(|value| {
             IsoCode639_1::from_str(&value).map_err(|_| {
-                Error::new(
-                    ruby.exception_arg_error(),
-                    format!("unknown ISO 639-1 code: {value}"),
-                )
+                unknown_language_error(ruby, format!("unknown ISO 639-1 code: {value}"))
             })
         })
         .collect()
@@ -228,92 +5513,695 @@ fn parse_iso_codes_639_3(
         .into_iter()
         .map(|value| {
             IsoCode639_3::from_str(&value).map_err(|_| {
-                Error::new(
-                    ruby.exception_arg_error(),
-                    format!("unknown ISO 639-3 code: {value}"),
-                )
+                unknown_language_error(ruby, format!("unknown ISO 639-3 code: {value}"))
             })
         })
         .collect()
 }
 
-fn builder_from_all_languages() -> LanguageDetectorBuilderWrapper {
-    LanguageDetectorBuilderWrapper::new(LanguageDetectorBuilder::from_all_languages())
+fn parse_language_identifiers(ruby: &Ruby, values: Vec<String>) -> Result<Vec<Language>, Error> {
+    if values.is_empty() {
+        return Err(Error::new(
+            ruby.exception_arg_error(),
+            "language identifiers list must not be empty",
+        ));
+    }
+
+    values
+        .into_iter()
+        .map(|value| {
+            resolve_language_name(&value)
+                .or_else(|| {
+                    IsoCode639_1::from_str(&value)
+                        .ok()
+                        .map(|code| Language::from_iso_code_639_1(&code))
+                })
+                .or_else(|| {
+                    IsoCode639_3::from_str(&value)
+                        .ok()
+                        .map(|code| Language::from_iso_code_639_3(&code))
+                })
+                .ok_or_else(|| {
+                    unknown_language_error(ruby, format!("unknown language identifier: {value}"))
+                })
+        })
+        .collect()
+}
+
+fn builder_from_all_languages() -> Result<Obj<LanguageDetectorBuilderWrapper>, Error> {
+    wrap_as_receiver(LanguageDetectorBuilderWrapper::new(Language::all()))
+}
+
+fn builder_from_all_spoken_languages() -> Result<Obj<LanguageDetectorBuilderWrapper>, Error> {
+    wrap_as_receiver(LanguageDetectorBuilderWrapper::new(Language::all_spoken_ones()))
+}
+
+fn builder_from_all_languages_with_arabic_script(
+) -> Result<Obj<LanguageDetectorBuilderWrapper>, Error> {
+    wrap_as_receiver(LanguageDetectorBuilderWrapper::new(Language::all_with_arabic_script()))
+}
+
+fn builder_from_all_languages_with_cyrillic_script(
+) -> Result<Obj<LanguageDetectorBuilderWrapper>, Error> {
+    wrap_as_receiver(LanguageDetectorBuilderWrapper::new(Language::all_with_cyrillic_script()))
+}
+
+fn builder_from_all_languages_with_devanagari_script(
+) -> Result<Obj<LanguageDetectorBuilderWrapper>, Error> {
+    wrap_as_receiver(LanguageDetectorBuilderWrapper::new(Language::all_with_devanagari_script()))
+}
+
+fn builder_from_all_languages_with_latin_script(
+) -> Result<Obj<LanguageDetectorBuilderWrapper>, Error> {
+    wrap_as_receiver(LanguageDetectorBuilderWrapper::new(Language::all_with_latin_script()))
+}
+
+fn builder_from_all_languages_with_single_unique_script(
+) -> Result<Obj<LanguageDetectorBuilderWrapper>, Error> {
+    wrap_as_receiver(LanguageDetectorBuilderWrapper::new(Language::all_with_single_unique_script()))
+}
+
+fn builder_from_languages(
+    ruby: &Ruby,
+    languages: Vec<String>,
+) -> Result<Obj<LanguageDetectorBuilderWrapper>, Error> {
+    let languages = parse_languages(ruby, languages)?;
+    wrap_as_receiver(LanguageDetectorBuilderWrapper::new(languages.into_iter().collect()))
+}
+
+fn builder_from_all_languages_without(
+    ruby: &Ruby,
+    languages: Vec<String>,
+) -> Result<Obj<LanguageDetectorBuilderWrapper>, Error> {
+    let excluded = parse_languages(ruby, languages)?;
+    let mut languages = Language::all();
+    languages.retain(|language| !excluded.contains(language));
+    wrap_as_receiver(LanguageDetectorBuilderWrapper::new(languages))
+}
+
+fn builder_from_iso_codes_639_1(
+    ruby: &Ruby,
+    iso_codes: Vec<String>,
+) -> Result<Obj<LanguageDetectorBuilderWrapper>, Error> {
+    let iso_codes = parse_iso_codes_639_1(ruby, iso_codes)?;
+    wrap_as_receiver(LanguageDetectorBuilderWrapper::new(
+        iso_codes.iter().map(Language::from_iso_code_639_1).collect(),
+    ))
+}
+
+fn builder_from_iso_codes_639_3(
+    ruby: &Ruby,
+    iso_codes: Vec<String>,
+) -> Result<Obj<LanguageDetectorBuilderWrapper>, Error> {
+    let iso_codes = parse_iso_codes_639_3(ruby, iso_codes)?;
+    wrap_as_receiver(LanguageDetectorBuilderWrapper::new(
+        iso_codes.iter().map(Language::from_iso_code_639_3).collect(),
+    ))
+}
+
+fn builder_from_identifiers(
+    ruby: &Ruby,
+    identifiers: Vec<String>,
+) -> Result<Obj<LanguageDetectorBuilderWrapper>, Error> {
+    let languages = parse_language_identifiers(ruby, identifiers)?;
+    wrap_as_receiver(LanguageDetectorBuilderWrapper::new(languages.into_iter().collect()))
+}
+
+fn builder_from_config_file(
+    ruby: &Ruby,
+    path: String,
+) -> Result<Obj<LanguageDetectorBuilderWrapper>, Error> {
+    let contents = std::fs::read_to_string(&path).map_err(|err| {
+        Error::new(
+            ruby.exception_io_error(),
+            format!("could not read config file {path}: {err}"),
+        )
+    })?;
+
+    let is_json = Path::new(&path)
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("json"));
+    let parsed = if is_json {
+        serde_json::from_str::<ConfigFile>(&contents).map_err(|err| err.to_string())
+    } else {
+        serde_yaml::from_str::<ConfigFile>(&contents).map_err(|err| err.to_string())
+    };
+    let config = parsed.map_err(|err| {
+        Error::new(
+            ruby.exception_arg_error(),
+            format!("invalid config file {path}: {err}"),
+        )
+    })?;
+
+    let mut languages = match config.languages {
+        Some(languages) => parse_language_identifiers(ruby, languages)?.into_iter().collect(),
+        None => Language::all(),
+    };
+    if !config.exclude.is_empty() {
+        let excluded = parse_language_identifiers(ruby, config.exclude)?;
+        languages.retain(|language| !excluded.contains(language));
+    }
+
+    let mut options = DetectorOptions::default();
+    if let Some(distance) = config.minimum_relative_distance {
+        validate_minimum_relative_distance(ruby, distance)?;
+        options.minimum_relative_distance = distance;
+    }
+    options.low_accuracy_mode = match config.accuracy_mode.as_deref() {
+        None | Some("high") => false,
+        Some("low") => true,
+        Some(other) => {
+            return Err(Error::new(
+                ruby.exception_arg_error(),
+                format!("invalid accuracy_mode in {path}: {other} (expected high or low)"),
+            ))
+        }
+    };
+    options.preloaded_language_models = config.preload;
+
+    wrap_as_receiver(LanguageDetectorBuilderWrapper(Mutex::new(BuilderState {
+        languages,
+        options,
+    })))
+}
+
+// Builds the native detector first and then runs `initialize` with the same arguments, so
+// subclasses can decorate instances while `new` keeps accepting the detector's keywords.
+fn detector_new(ruby: &Ruby, args: &[Value]) -> Result<Obj<LanguageDetectorWrapper>, Error> {
+    let parsed = scan_args::<(), (), (), (), RHash, ()>(args)?;
+    let state = parse_detector_kwargs(ruby, parsed.keywords)?;
+    let detector = LanguageDetectorWrapper::new(ruby, state.languages, state.options)?;
+    new_as_receiver(detector, args)
+}
+
+fn parse_detector_kwargs(ruby: &Ruby, keywords: RHash) -> Result<BuilderState, Error> {
+    type Optional = (
+        Option<Vec<String>>,
+        Option<f64>,
+        Option<bool>,
+        Option<bool>,
+        Option<Value>,
+        Option<usize>,
+        Option<f64>,
+        Option<Value>,
+        Option<Value>,
+        Option<RHash>,
+        Option<Value>,
+    );
+    let kwargs = get_kwargs::<_, (), Optional, RHash>(
+        keywords,
+        &[],
+        &[
+            "languages",
+            "minimum_relative_distance",
+            "low_accuracy",
+            "preload",
+            "invalid_utf8",
+            "result_cache",
+            "hybrid_threshold",
+            "language_format",
+            "display_locale",
+            "merge_languages",
+            "calibration",
+        ],
+    )?;
+    let (
+        languages,
+        distance,
+        low_accuracy,
+        preload,
+        invalid_utf8,
+        result_cache,
+        hybrid,
+        language_format,
+        display_locale,
+        merges,
+        calibration,
+    ) = kwargs.optional;
+
+    let languages = match languages {
+        Some(languages) => parse_languages(ruby, languages)?.into_iter().collect(),
+        None => Language::all(),
+    };
+    let mut options = DetectorOptions::default();
+    if let Some(distance) = distance {
+        validate_minimum_relative_distance(ruby, distance)?;
+        options.minimum_relative_distance = distance;
+    }
+    options.low_accuracy_mode = low_accuracy.unwrap_or(false);
+    options.preloaded_language_models = preload.unwrap_or(false);
+    if let Some(policy) = invalid_utf8 {
+        options.invalid_utf8 = InvalidUtf8Policy::parse(ruby, policy)?;
+    }
+    options.result_cache_size = result_cache.unwrap_or(0);
+    if let Some(threshold) = hybrid {
+        validate_hybrid_threshold(ruby, threshold)?;
+        options.hybrid_threshold = Some(threshold);
+    }
+    if let Some(format) = language_format {
+        options.language_format = LanguageFormat::parse(ruby, format)?;
+    }
+    if let Some(locale) = display_locale {
+        options.display_locale = DisplayLocale::parse(ruby, locale)?;
+    }
+    if let Some(merges) = merges {
+        options.language_merges = parse_language_merges(ruby, merges)?;
+    }
+    if let Some(calibration) = calibration {
+        options.calibration = Calibration::parse(ruby, calibration)?;
+    }
+    options.preprocessing = parse_preprocessing_kwargs(ruby, kwargs.splat)?;
+
+    Ok(BuilderState { languages, options })
+}
+
+fn parse_preprocessing_kwargs(ruby: &Ruby, keywords: RHash) -> Result<Preprocessing, Error> {
+    type Optional = (
+        Option<Value>,
+        Option<Value>,
+        Option<bool>,
+        Option<bool>,
+        Option<bool>,
+        Option<usize>,
+        Option<usize>,
+        Option<usize>,
+        Option<Value>,
+    );
+    let kwargs = get_kwargs::<_, (), Optional, ()>(
+        keywords,
+        &[],
+        &[
+            "preprocessor",
+            "preprocess",
+            "strip_urls",
+            "strip_social_tokens",
+            "strip_junk",
+            "minimum_letters",
+            "minimum_text_length",
+            "max_text_length",
+            "sampling",
+        ],
+    )?;
+    let (
+        custom,
+        markup,
+        strip_urls,
+        strip_social_tokens,
+        strip_junk,
+        minimum_letters,
+        minimum_text_length,
+        max_text_length,
+        sampling,
+    ) = kwargs.optional;
+    validate_max_text_length(ruby, max_text_length)?;
+    Ok(Preprocessing {
+        custom: custom
+            .map(|custom| parse_preprocessor(ruby, custom))
+            .transpose()?,
+        markup: markup.map_or(Ok(Markup::None), |mode| Markup::parse(ruby, mode))?,
+        strip_urls: strip_urls.unwrap_or(false),
+        strip_social_tokens: strip_social_tokens.unwrap_or(false),
+        strip_junk: strip_junk.unwrap_or(false),
+        minimum_letters: minimum_letters.unwrap_or(0),
+        minimum_text_length: minimum_text_length.unwrap_or(0),
+        max_text_length,
+        sampling: sampling
+            .map(|strategy| SamplingStrategy::parse(ruby, strategy))
+            .transpose()?
+            .unwrap_or_default(),
+    })
+}
+
+fn validate_max_text_length(ruby: &Ruby, max_length: Option<usize>) -> Result<(), Error> {
+    if max_length == Some(0) {
+        return Err(Error::new(
+            ruby.exception_arg_error(),
+            "max_text_length must be at least 1",
+        ));
+    }
+    Ok(())
+}
+
+fn parse_preprocessor(ruby: &Ruby, preprocessor: Value) -> Result<Opaque<Value>, Error> {
+    if !preprocessor.respond_to("call", false)? {
+        return Err(Error::new(
+            ruby.exception_type_error(),
+            "preprocessor must respond to call",
+        ));
+    }
+    Ok(Opaque::from(preprocessor))
+}
+
+// Keeps the preprocessor of a profile (or, under nil, the default detector) marked for as
+// long as `state` is the one registered under `key`.
+fn retain_preprocessor(ruby: &Ruby, key: Value, state: &BuilderState) -> Result<(), Error> {
+    let preprocessors = ruby.get_inner(&STATE_PREPROCESSORS);
+    match state.options.preprocessing.custom {
+        Some(custom) => preprocessors.aset(key, ruby.get_inner(custom))?,
+        None => {
+            preprocessors.delete::<_, Value>(key)?;
+        }
+    }
+    Ok(())
+}
+
+fn register_profile(ruby: &Ruby, args: &[Value]) -> Result<(), Error> {
+    let args = scan_args::<(Value,), (), (), (), RHash, ()>(args)?;
+    let (name,) = args.required;
+    let name: String = name.funcall("to_s", ())?;
+    let state = parse_detector_kwargs(ruby, args.keywords)?;
+
+    retain_preprocessor(ruby, name.as_str().into_value_with(ruby), &state)?;
+    lock_ignoring_poison(&PROFILES).insert(name.clone(), state);
+    ruby.get_inner(&PROFILE_DETECTORS).delete::<_, Value>(name)?;
+    Ok(())
+}
+
+fn profile(ruby: &Ruby, name: Value) -> Result<Value, Error> {
+    let name: String = name.funcall("to_s", ())?;
+    let detectors = ruby.get_inner(&PROFILE_DETECTORS);
+    if let Some(detector) = detectors.get(name.as_str()) {
+        return Ok(detector);
+    }
+
+    let state = lock_ignoring_poison(&PROFILES)
+        .get(&name)
+        .cloned()
+        .ok_or_else(|| {
+            Error::new(
+                ruby.exception_arg_error(),
+                format!("unknown detector profile: {name}"),
+            )
+        })?;
+    let detector = Obj::wrap(LanguageDetectorWrapper::new(ruby, state.languages, state.options)?);
+    let detector = detector.as_value();
+    detectors.aset(name, detector)?;
+    Ok(detector)
+}
+
+fn profile_names() -> Vec<String> {
+    lock_ignoring_poison(&PROFILES).keys().cloned().collect()
+}
+
+fn set_strict_language_names(strict: bool) -> bool {
+    STRICT_LANGUAGE_NAMES.store(strict, Ordering::Relaxed);
+    strict
+}
+
+fn is_strict_language_names() -> bool {
+    STRICT_LANGUAGE_NAMES.load(Ordering::Relaxed)
+}
+
+// `nil` goes back to rayon's global pool. Detections already running finish on the pool
+// they started on, which shuts down once they are done.
+fn set_max_threads(ruby: &Ruby, max_threads: Option<usize>) -> Result<Option<usize>, Error> {
+    let pool = match max_threads {
+        None => None,
+        Some(0) => {
+            return Err(Error::new(
+                ruby.exception_arg_error(),
+                "max_threads must be at least 1 (or nil to use rayon's global pool)",
+            ))
+        }
+        Some(threads) => rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .thread_name(|index| format!("lingua-rs-rb-{index}"))
+            .build()
+            .map(Arc::new)
+            .map(Some)
+            .map_err(|err| {
+                Error::new(
+                    ruby.exception_runtime_error(),
+                    format!("failed to start {threads} detection threads: {err}"),
+                )
+            })?,
+    };
+    *lock_ignoring_poison(&THREAD_POOL) = pool;
+    Ok(max_threads)
+}
+
+fn max_threads() -> Option<usize> {
+    thread_pool().map(|pool| pool.current_num_threads())
+}
+
+fn set_serial_batch_threshold(threshold: usize) -> usize {
+    SERIAL_BATCH_THRESHOLD.store(threshold, Ordering::Relaxed);
+    threshold
+}
+
+fn serial_batch_threshold() -> usize {
+    SERIAL_BATCH_THRESHOLD.load(Ordering::Relaxed)
+}
+
+fn configure(ruby: &Ruby) -> Result<(), Error> {
+    let state = lock_ignoring_poison(&DEFAULT_BUILDER_STATE)
+        .clone()
+        .unwrap_or_else(|| BuilderState {
+            languages: Language::all(),
+            options: DetectorOptions::default(),
+        });
+    let builder = Obj::wrap(LanguageDetectorBuilderWrapper(Mutex::new(state)));
+    ruby.yield_value::<_, Value>(builder)?;
+
+    let state = lock_builder(ruby, &builder)?.clone();
+    retain_preprocessor(ruby, ruby.qnil().as_value(), &state)?;
+    *lock_ignoring_poison(&DEFAULT_BUILDER_STATE) = Some(state);
+    *lock_ignoring_poison(&DEFAULT_DETECTOR) = None;
+    Ok(())
+}
+
+fn default_detector(ruby: &Ruby) -> Result<Arc<LanguageDetectorWrapper>, Error> {
+    let mut detector = lock_ignoring_poison(&DEFAULT_DETECTOR);
+    if let Some(detector) = &*detector {
+        return Ok(Arc::clone(detector));
+    }
+
+    let state = lock_ignoring_poison(&DEFAULT_BUILDER_STATE)
+        .clone()
+        .unwrap_or_else(|| BuilderState {
+            languages: Language::all(),
+            options: DetectorOptions::default(),
+        });
+    let built = Arc::new(LanguageDetectorWrapper::new(ruby, state.languages, state.options)?);
+    *detector = Some(Arc::clone(&built));
+    Ok(built)
+}
+
+fn model_bytes_per_language(low_accuracy_mode: bool) -> usize {
+    if low_accuracy_mode {
+        LOW_ACCURACY_MODEL_BYTES
+    } else {
+        HIGH_ACCURACY_MODEL_BYTES
+    }
+}
+
+fn model_cache_stats(ruby: &Ruby) -> Result<RHash, Error> {
+    let cache = lock_ignoring_poison(&MODEL_CACHE);
+    let resident: Vec<String> = cache.resident.keys().map(|l| l.to_string()).collect();
+    let shared: Vec<String> = cache
+        .holders
+        .iter()
+        .filter(|(_, &count)| count > 1)
+        .map(|(language, _)| language.to_string())
+        .collect();
+
+    let stats = ruby.hash_new();
+    stats.aset(ruby.to_symbol("resident_languages"), resident)?;
+    stats.aset(ruby.to_symbol("shared_languages"), shared)?;
+    stats.aset(ruby.to_symbol("hits"), cache.hits)?;
+    stats.aset(ruby.to_symbol("misses"), cache.misses)?;
+    stats.aset(ruby.to_symbol("evictions"), cache.evictions)?;
+    stats.aset(ruby.to_symbol("estimated_bytes"), cache.estimated_bytes())?;
+    Ok(stats)
+}
+
+fn estimated_model_memory(ruby: &Ruby, args: &[Value]) -> Result<usize, Error> {
+    let args = scan_args::<(Vec<String>,), (), (), (), RHash, ()>(args)?;
+    let (languages,) = args.required;
+    let kwargs =
+        get_kwargs::<_, (), (Option<bool>,), ()>(args.keywords, &[], &["low_accuracy"])?;
+    let (low_accuracy,) = kwargs.optional;
+
+    let languages: HashSet<Language> = parse_languages(ruby, languages)?.into_iter().collect();
+    Ok(languages.len() * model_bytes_per_language(low_accuracy.unwrap_or(false)))
+}
+
+fn detect(ruby: &Ruby, text: Value) -> Result<Value, Error> {
+    let detector = default_detector(ruby)?;
+    let text = detector.read_text(ruby, text)?;
+    detector.instrument(ruby, "detect", Some(text), || {
+        let Some(text) = detector.decode_text(ruby, &text)? else {
+            return Ok(None);
+        };
+        let labeler = detector.labeler(&text);
+        let language = detector.detect_language_of(ruby, text, None)?;
+        Ok(language.map(|language| labeler.label(language)))
+    })
+}
+
+fn script_of(text: String) -> Option<&'static str> {
+    dominant_script(&text).map(Script::full_name)
+}
+
+// Languages lingua detects in more than one script, with the subtags their tags may carry.
+const SCRIPT_SUBTAGGED_LANGUAGES: &[(&str, &[&str])] =
+    &[("sr", &["Cyrl", "Latn"]), ("zh", &["Hans", "Hant"])];
+
+// Characters commonly written differently in Simplified and Traditional Chinese, paired
+// position by position.
+const SIMPLIFIED_HAN: &str = "们这个来说为会国时学对发经没见长门问间东车马鸟语话请书买卖钱电开关头体点线边还过进远选难";
+const TRADITIONAL_HAN: &str = "們這個來說為會國時學對發經沒見長門問間東車馬鳥語話請書買賣錢電開關頭體點線邊還過進遠選難";
+
+fn bcp47_tag(language: Language, script: Option<&str>) -> String {
+    let base = language.iso_code_639_1().to_string();
+    let subtag = SCRIPT_SUBTAGGED_LANGUAGES
+        .iter()
+        .find(|(code, _)| *code == base)
+        .and_then(|(_, scripts)| scripts.iter().find(|subtag| Some(**subtag) == script));
+    match subtag {
+        Some(subtag) => format!("{base}-{subtag}"),
+        None => base,
+    }
+}
+
+fn bcp47_script_subtag(text: &str) -> Option<&'static str> {
+    match dominant_script(text)? {
+        Script::Cyrillic => Some("Cyrl"),
+        Script::Latin => Some("Latn"),
+        Script::Han => {
+            let simplified = text.chars().filter(|&c| SIMPLIFIED_HAN.contains(c)).count();
+            let traditional = text.chars().filter(|&c| TRADITIONAL_HAN.contains(c)).count();
+            match simplified.cmp(&traditional) {
+                std::cmp::Ordering::Greater => Some("Hans"),
+                std::cmp::Ordering::Less => Some("Hant"),
+                std::cmp::Ordering::Equal => None,
+            }
+        }
+        _ => None,
+    }
 }
 
-fn builder_from_all_spoken_languages() -> LanguageDetectorBuilderWrapper {
-    LanguageDetectorBuilderWrapper::new(LanguageDetectorBuilder::from_all_spoken_languages())
+fn display_name(ruby: &Ruby, args: &[Value]) -> Result<String, Error> {
+    let args = scan_args::<(Value,), (), (), (), RHash, ()>(args)?;
+    let (language,) = args.required;
+    let kwargs = get_kwargs::<_, (), (Option<Value>,), ()>(args.keywords, &[], &["locale"])?;
+    let language = parse_language_value(ruby, language)?;
+    let locale = match kwargs.optional.0 {
+        Some(locale) => DisplayLocale::parse(ruby, locale)?,
+        None => DisplayLocale::default(),
+    };
+    Ok(display_name_in(language, locale))
 }
 
-fn builder_from_all_languages_with_arabic_script() -> LanguageDetectorBuilderWrapper {
-    LanguageDetectorBuilderWrapper::new(
-        LanguageDetectorBuilder::from_all_languages_with_arabic_script(),
-    )
+fn display_name_in(language: Language, locale: DisplayLocale) -> String {
+    let code = language.iso_code_639_1().to_string();
+    DISPLAY_NAMES
+        .iter()
+        .find(|(entry, _)| *entry == code)
+        .map_or_else(|| language.to_string(), |(_, names)| names[locale.0].to_owned())
 }
 
-fn builder_from_all_languages_with_cyrillic_script() -> LanguageDetectorBuilderWrapper {
-    LanguageDetectorBuilderWrapper::new(
-        LanguageDetectorBuilder::from_all_languages_with_cyrillic_script(),
-    )
+fn text_direction(language: Language) -> &'static str {
+    if RTL_LANGUAGES.contains(&language.to_string().as_str()) {
+        "rtl"
+    } else {
+        "ltr"
+    }
 }
 
-fn builder_from_all_languages_with_devanagari_script() -> LanguageDetectorBuilderWrapper {
-    LanguageDetectorBuilderWrapper::new(
-        LanguageDetectorBuilder::from_all_languages_with_devanagari_script(),
-    )
+// The ISO 15924 code of the dominant script, using the combined codes for Japanese and
+// Korean text that mixes Han with kana or Hangul.
+fn script_code(text: &str) -> Option<&'static str> {
+    let script = dominant_script(text)?;
+    let contains = |other: Script| text.chars().any(|c| c.script() == other);
+    Some(match script {
+        Script::Han | Script::Hiragana | Script::Katakana
+            if contains(Script::Hiragana) || contains(Script::Katakana) =>
+        {
+            "Jpan"
+        }
+        Script::Han | Script::Hangul if contains(Script::Han) && contains(Script::Hangul) => {
+            "Kore"
+        }
+        script => script.short_name(),
+    })
 }
 
-fn builder_from_all_languages_with_latin_script() -> LanguageDetectorBuilderWrapper {
-    LanguageDetectorBuilderWrapper::new(
-        LanguageDetectorBuilder::from_all_languages_with_latin_script(),
-    )
+// The script covering the most characters; punctuation, digits and combining marks
+// (Common and Inherited) do not count towards any script.
+fn dominant_script(text: &str) -> Option<Script> {
+    let mut counts: Vec<(Script, usize)> = Vec::new();
+    for script in text.chars().map(|c| c.script()) {
+        if matches!(script, Script::Common | Script::Inherited | Script::Unknown) {
+            continue;
+        }
+        match counts.iter_mut().find(|(counted, _)| *counted == script) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((script, 1)),
+        }
+    }
+    counts
+        .into_iter()
+        .max_by_key(|&(script, count)| (count, std::cmp::Reverse(script.full_name())))
+        .map(|(script, _)| script)
 }
 
-fn builder_from_all_languages_with_single_unique_script() -> LanguageDetectorBuilderWrapper {
-    let languages: Vec<Language> = Language::all_with_single_unique_script().into_iter().collect();
-    LanguageDetectorBuilderWrapper::new(LanguageDetectorBuilder::from_languages(&languages))
+fn on_detection(ruby: &Ruby) -> Result<Value, Error> {
+    let hook = ruby.block_proc()?.as_value();
+    ruby.get_inner(&DETECTION_HOOKS).push(hook)?;
+    Ok(hook)
 }
 
-fn builder_from_languages(
-    ruby: &Ruby,
-    languages: Vec<String>,
-) -> Result<LanguageDetectorBuilderWrapper, Error> {
-    let languages = parse_languages(ruby, languages)?;
-    Ok(LanguageDetectorBuilderWrapper::new(
-        LanguageDetectorBuilder::from_languages(&languages),
-    ))
+fn set_tracer(ruby: &Ruby, tracer: Value) -> Result<Value, Error> {
+    let slot = ruby.get_inner(&TRACER);
+    slot.funcall::<_, _, Value>("clear", ())?;
+    if !tracer.is_nil() {
+        slot.push(tracer)?;
+    }
+    Ok(tracer)
 }
 
-fn builder_from_all_languages_without(
-    ruby: &Ruby,
-    languages: Vec<String>,
-) -> Result<LanguageDetectorBuilderWrapper, Error> {
-    let languages = parse_languages(ruby, languages)?;
-    Ok(LanguageDetectorBuilderWrapper::new(
-        LanguageDetectorBuilder::from_all_languages_without(&languages),
-    ))
+fn tracer(ruby: &Ruby) -> Result<Value, Error> {
+    ruby.get_inner(&TRACER).entry(0)
 }
 
-fn builder_from_iso_codes_639_1(
+// Follows the OpenTelemetry Ruby API (`start_span` / `finish` with nanosecond timestamps),
+// so an OpenTelemetry tracer can be passed as is; any object with that shape works.
+fn record_span(
     ruby: &Ruby,
-    iso_codes: Vec<String>,
-) -> Result<LanguageDetectorBuilderWrapper, Error> {
-    let iso_codes = parse_iso_codes_639_1(ruby, iso_codes)?;
-    Ok(LanguageDetectorBuilderWrapper::new(
-        LanguageDetectorBuilder::from_iso_codes_639_1(&iso_codes),
-    ))
+    tracer: Value,
+    method: &str,
+    attributes: RHash,
+    start_timestamp: u64,
+    duration: Duration,
+) -> Result<(), Error> {
+    let end_timestamp = start_timestamp.saturating_add(duration.as_nanos() as u64);
+    let options = ruby.hash_new();
+    options.aset(ruby.to_symbol("attributes"), attributes)?;
+    options.aset(ruby.to_symbol("start_timestamp"), start_timestamp)?;
+    let span: Value = tracer.funcall(
+        "start_span",
+        (format!("lingua_rs_rb.{method}"), KwArgs(options)),
+    )?;
+    let options = ruby.hash_new();
+    options.aset(ruby.to_symbol("end_timestamp"), end_timestamp)?;
+    span.funcall::<_, _, Value>("finish", (KwArgs(options),))?;
+    Ok(())
 }
 
-fn builder_from_iso_codes_639_3(
-    ruby: &Ruby,
-    iso_codes: Vec<String>,
-) -> Result<LanguageDetectorBuilderWrapper, Error> {
-    let iso_codes = parse_iso_codes_639_3(ruby, iso_codes)?;
-    Ok(LanguageDetectorBuilderWrapper::new(
-        LanguageDetectorBuilder::from_iso_codes_639_3(&iso_codes),
-    ))
+fn epoch_nanos() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_nanos() as u64)
+}
+
+fn remove_detection_hook(ruby: &Ruby, hook: Value) -> Result<bool, Error> {
+    let removed: Value = ruby.get_inner(&DETECTION_HOOKS).funcall("delete", (hook,))?;
+    Ok(!removed.is_nil())
 }
 
+// lingua gates each `Language` variant behind its Cargo feature, so these lists only ever
+// contain the languages compiled into this build (see LINGUA_RS_RB_LANGUAGES in extconf.rb).
 fn languages() -> Vec<String> {
     let mut langs: Vec<String> = Language::all().into_iter().map(|l| l.to_string()).collect();
     langs.sort();
@@ -374,10 +6262,147 @@ fn languages_with_single_unique_script() -> Vec<String> {
     langs
 }
 
+// Scripts each language is written in. lingua only groups languages by the four scripts
+// above, so this table covers the rest; languages missing from it are written in Latin.
+// Names are matched rather than variants so the table compiles with any language subset.
+fn language_scripts(language: Language) -> &'static [Script] {
+    match language.to_string().as_str() {
+        "Arabic" | "Persian" | "Urdu" => &[Script::Arabic],
+        "Armenian" => &[Script::Armenian],
+        "Belarusian" | "Bulgarian" | "Kazakh" | "Macedonian" | "Mongolian" | "Russian"
+        | "Serbian" | "Ukrainian" => &[Script::Cyrillic],
+        "Bengali" => &[Script::Bengali],
+        "Chinese" => &[Script::Han],
+        "Georgian" => &[Script::Georgian],
+        "Greek" => &[Script::Greek],
+        "Gujarati" => &[Script::Gujarati],
+        "Hebrew" => &[Script::Hebrew],
+        "Hindi" | "Marathi" => &[Script::Devanagari],
+        "Japanese" => &[Script::Han, Script::Hiragana, Script::Katakana],
+        "Korean" => &[Script::Hangul],
+        "Punjabi" => &[Script::Gurmukhi],
+        "Tamil" => &[Script::Tamil],
+        "Telugu" => &[Script::Telugu],
+        "Thai" => &[Script::Thai],
+        _ => &[Script::Latin],
+    }
+}
+
+fn languages_with_script(script: Script) -> Vec<String> {
+    let mut langs: Vec<String> = Language::all()
+        .into_iter()
+        .filter(|language| language_scripts(*language).contains(&script))
+        .map(|l| l.to_string())
+        .collect();
+    langs.sort();
+    langs
+}
+
+fn languages_with_armenian_script() -> Vec<String> {
+    languages_with_script(Script::Armenian)
+}
+
+fn languages_with_bengali_script() -> Vec<String> {
+    languages_with_script(Script::Bengali)
+}
+
+fn languages_with_georgian_script() -> Vec<String> {
+    languages_with_script(Script::Georgian)
+}
+
+fn languages_with_greek_script() -> Vec<String> {
+    languages_with_script(Script::Greek)
+}
+
+fn languages_with_gujarati_script() -> Vec<String> {
+    languages_with_script(Script::Gujarati)
+}
+
+fn languages_with_gurmukhi_script() -> Vec<String> {
+    languages_with_script(Script::Gurmukhi)
+}
+
+fn languages_with_han_script() -> Vec<String> {
+    languages_with_script(Script::Han)
+}
+
+fn languages_with_hangul_script() -> Vec<String> {
+    languages_with_script(Script::Hangul)
+}
+
+fn languages_with_hebrew_script() -> Vec<String> {
+    languages_with_script(Script::Hebrew)
+}
+
+fn languages_with_hiragana_script() -> Vec<String> {
+    languages_with_script(Script::Hiragana)
+}
+
+fn languages_with_katakana_script() -> Vec<String> {
+    languages_with_script(Script::Katakana)
+}
+
+fn languages_with_tamil_script() -> Vec<String> {
+    languages_with_script(Script::Tamil)
+}
+
+fn languages_with_telugu_script() -> Vec<String> {
+    languages_with_script(Script::Telugu)
+}
+
+fn languages_with_thai_script() -> Vec<String> {
+    languages_with_script(Script::Thai)
+}
+
 #[magnus::init]
 fn init(ruby: &Ruby) -> Result<(), Error> {
     let module = ruby.define_module("LinguaRsRb")?;
 
+    let error = module.define_error("Error", ruby.exception_standard_error())?;
+    module.define_error("UnknownLanguageError", error)?;
+    module.define_error("DetectionError", error)?;
+    module.define_error("InternalError", error)?;
+    module.define_error("TimeoutError", error)?;
+    module.define_error("NoLanguageDetectedError", error)?;
+
+    module.define_singleton_method(
+        "strict_language_names=",
+        function!(set_strict_language_names, 1),
+    )?;
+    module.define_singleton_method(
+        "strict_language_names?",
+        function!(is_strict_language_names, 0),
+    )?;
+    module.define_singleton_method("max_threads=", function!(set_max_threads, 1))?;
+    module.define_singleton_method("max_threads", function!(max_threads, 0))?;
+    module.define_singleton_method(
+        "serial_batch_threshold=",
+        function!(set_serial_batch_threshold, 1),
+    )?;
+    module.define_singleton_method(
+        "serial_batch_threshold",
+        function!(serial_batch_threshold, 0),
+    )?;
+    module.define_singleton_method("on_detection", function!(on_detection, 0))?;
+    module.define_singleton_method("tracer=", function!(set_tracer, 1))?;
+    module.define_singleton_method("tracer", function!(tracer, 0))?;
+    module.define_singleton_method(
+        "remove_detection_hook",
+        function!(remove_detection_hook, 1),
+    )?;
+    module.define_singleton_method("configure", function!(configure, 0))?;
+    module.define_singleton_method("detect", function!(detect, 1))?;
+    module.define_singleton_method("script_of", function!(script_of, 1))?;
+    module.define_singleton_method("language_groups", function!(language_groups, 0))?;
+    module.define_singleton_method("display_name", function!(display_name, -1))?;
+    module.define_singleton_method("register_profile", function!(register_profile, -1))?;
+    module.define_singleton_method("profile", function!(profile, 1))?;
+    module.define_singleton_method("profile_names", function!(profile_names, 0))?;
+    module.define_singleton_method("model_cache_stats", function!(model_cache_stats, 0))?;
+    module.define_singleton_method(
+        "estimated_model_memory",
+        function!(estimated_model_memory, -1),
+    )?;
     module.define_singleton_method("languages", function!(languages, 0))?;
     module.define_singleton_method("spoken_languages", function!(spoken_languages, 0))?;
     module.define_singleton_method(
@@ -400,8 +6425,69 @@ fn init(ruby: &Ruby) -> Result<(), Error> {
         "languages_with_single_unique_script",
         function!(languages_with_single_unique_script, 0),
     )?;
+    module.define_singleton_method(
+        "languages_with_armenian_script",
+        function!(languages_with_armenian_script, 0),
+    )?;
+    module.define_singleton_method(
+        "languages_with_bengali_script",
+        function!(languages_with_bengali_script, 0),
+    )?;
+    module.define_singleton_method(
+        "languages_with_georgian_script",
+        function!(languages_with_georgian_script, 0),
+    )?;
+    module.define_singleton_method(
+        "languages_with_greek_script",
+        function!(languages_with_greek_script, 0),
+    )?;
+    module.define_singleton_method(
+        "languages_with_gujarati_script",
+        function!(languages_with_gujarati_script, 0),
+    )?;
+    module.define_singleton_method(
+        "languages_with_gurmukhi_script",
+        function!(languages_with_gurmukhi_script, 0),
+    )?;
+    module.define_singleton_method(
+        "languages_with_han_script",
+        function!(languages_with_han_script, 0),
+    )?;
+    module.define_singleton_method(
+        "languages_with_hangul_script",
+        function!(languages_with_hangul_script, 0),
+    )?;
+    module.define_singleton_method(
+        "languages_with_hebrew_script",
+        function!(languages_with_hebrew_script, 0),
+    )?;
+    module.define_singleton_method(
+        "languages_with_hiragana_script",
+        function!(languages_with_hiragana_script, 0),
+    )?;
+    module.define_singleton_method(
+        "languages_with_katakana_script",
+        function!(languages_with_katakana_script, 0),
+    )?;
+    module.define_singleton_method(
+        "languages_with_tamil_script",
+        function!(languages_with_tamil_script, 0),
+    )?;
+    module.define_singleton_method(
+        "languages_with_telugu_script",
+        function!(languages_with_telugu_script, 0),
+    )?;
+    module.define_singleton_method(
+        "languages_with_thai_script",
+        function!(languages_with_thai_script, 0),
+    )?;
 
     let builder_class = module.define_class("LanguageDetectorBuilder", ruby.class_object())?;
+    builder_class.define_alloc_func::<LanguageDetectorBuilderWrapper>();
+    builder_class.define_method(
+        "initialize_copy",
+        method!(LanguageDetectorBuilderWrapper::initialize_copy, 1),
+    )?;
     builder_class.define_singleton_method("from_all_languages", function!(builder_from_all_languages, 0))?;
     builder_class.define_singleton_method(
         "from_all_spoken_languages",
@@ -440,48 +6526,319 @@ fn init(ruby: &Ruby) -> Result<(), Error> {
         "from_iso_codes_639_3",
         function!(builder_from_iso_codes_639_3, 1),
     )?;
+    builder_class.define_singleton_method(
+        "from_config_file",
+        function!(builder_from_config_file, 1),
+    )?;
+    builder_class.define_singleton_method(
+        "from_identifiers",
+        function!(builder_from_identifiers, 1),
+    )?;
     builder_class.define_method(
         "with_minimum_relative_distance",
         method!(LanguageDetectorBuilderWrapper::with_minimum_relative_distance, 1),
     )?;
+    builder_class.define_method(
+        "with_invalid_utf8_policy",
+        method!(LanguageDetectorBuilderWrapper::with_invalid_utf8_policy, 1),
+    )?;
+    builder_class.define_method(
+        "with_result_cache",
+        method!(LanguageDetectorBuilderWrapper::with_result_cache, 1),
+    )?;
+    builder_class.define_method(
+        "with_confidence_thresholds",
+        method!(LanguageDetectorBuilderWrapper::with_confidence_thresholds, 2),
+    )?;
     builder_class.define_method(
         "with_preloaded_language_models",
-        method!(LanguageDetectorBuilderWrapper::with_preloaded_language_models, 0),
+        method!(LanguageDetectorBuilderWrapper::with_preloaded_language_models, -1),
+    )?;
+    builder_class.define_method(
+        "with_preprocessing",
+        method!(LanguageDetectorBuilderWrapper::with_preprocessing, 1),
+    )?;
+    builder_class.define_method(
+        "with_preprocessor",
+        method!(LanguageDetectorBuilderWrapper::with_preprocessor, -1),
+    )?;
+    builder_class.define_method(
+        "with_minimum_text_length",
+        method!(LanguageDetectorBuilderWrapper::with_minimum_text_length, 1),
+    )?;
+    builder_class.define_method(
+        "with_max_text_length",
+        method!(LanguageDetectorBuilderWrapper::with_max_text_length, 1),
+    )?;
+    builder_class.define_method(
+        "with_sampling_strategy",
+        method!(LanguageDetectorBuilderWrapper::with_sampling_strategy, 1),
+    )?;
+    builder_class.define_method(
+        "with_url_stripping",
+        method!(LanguageDetectorBuilderWrapper::with_url_stripping, -1),
+    )?;
+    builder_class.define_method(
+        "with_social_token_stripping",
+        method!(LanguageDetectorBuilderWrapper::with_social_token_stripping, -1),
+    )?;
+    builder_class.define_method(
+        "with_junk_stripping",
+        method!(LanguageDetectorBuilderWrapper::with_junk_stripping, -1),
+    )?;
+    builder_class.define_method(
+        "with_minimum_letters",
+        method!(LanguageDetectorBuilderWrapper::with_minimum_letters, 1),
+    )?;
+    builder_class.define_method(
+        "with_language_format",
+        method!(LanguageDetectorBuilderWrapper::with_language_format, 1),
+    )?;
+    builder_class.define_method(
+        "with_display_names",
+        method!(LanguageDetectorBuilderWrapper::with_display_names, -1),
+    )?;
+    builder_class.define_method(
+        "with_language_merges",
+        method!(LanguageDetectorBuilderWrapper::with_language_merges, 1),
+    )?;
+    builder_class.define_method(
+        "with_confidence_calibration",
+        method!(LanguageDetectorBuilderWrapper::with_confidence_calibration, 1),
+    )?;
+    builder_class.define_method(
+        "with_hybrid_accuracy_mode",
+        method!(LanguageDetectorBuilderWrapper::with_hybrid_accuracy_mode, -1),
     )?;
     builder_class.define_method(
         "with_low_accuracy_mode",
-        method!(LanguageDetectorBuilderWrapper::with_low_accuracy_mode, 0),
+        method!(LanguageDetectorBuilderWrapper::with_low_accuracy_mode, -1),
+    )?;
+    builder_class.define_method(
+        "languages",
+        method!(LanguageDetectorBuilderWrapper::languages, 0),
+    )?;
+    builder_class.define_method(
+        "minimum_relative_distance",
+        method!(LanguageDetectorBuilderWrapper::minimum_relative_distance, 0),
+    )?;
+    builder_class.define_method(
+        "low_accuracy_mode?",
+        method!(LanguageDetectorBuilderWrapper::is_low_accuracy_mode, 0),
+    )?;
+    builder_class.define_method("inspect", method!(LanguageDetectorBuilderWrapper::inspect, 0))?;
+    builder_class.define_method(
+        "preloaded_language_models?",
+        method!(LanguageDetectorBuilderWrapper::is_preloaded_language_models, 0),
     )?;
     builder_class.define_method("build", method!(LanguageDetectorBuilderWrapper::build, 0))?;
 
     let detector_class = module.define_class("LanguageDetector", ruby.class_object())?;
+    // Detectors only exist with their native data attached, so `allocate` (and with it
+    // `dup`/`clone`) is undefined rather than producing an unusable instance.
+    unsafe { rb_sys::rb_undef_alloc_func(detector_class.as_raw()) };
+    detector_class.define_singleton_method("new", function!(detector_new, -1))?;
+    detector_class.define_private_method(
+        "initialize",
+        method!(initialize_wrapped::<LanguageDetectorWrapper>, -1),
+    )?;
+    detector_class.define_singleton_method("load", function!(LanguageDetectorWrapper::load, 1))?;
+    detector_class.define_method(
+        "to_config_h",
+        method!(LanguageDetectorWrapper::to_config_h, 0),
+    )?;
+    detector_class.define_method("==", method!(LanguageDetectorWrapper::equals, 1))?;
+    detector_class.define_method("eql?", method!(LanguageDetectorWrapper::equals, 1))?;
+    detector_class.define_method("hash", method!(LanguageDetectorWrapper::hash, 0))?;
+    detector_class.define_method("_dump", method!(LanguageDetectorWrapper::marshal_dump, 1))?;
+    detector_class.define_singleton_method(
+        "_load",
+        function!(LanguageDetectorWrapper::marshal_load, 1),
+    )?;
+    detector_class.define_method("languages", method!(LanguageDetectorWrapper::languages, 0))?;
+    detector_class.define_method(
+        "minimum_relative_distance",
+        method!(LanguageDetectorWrapper::minimum_relative_distance, 0),
+    )?;
+    detector_class.define_method(
+        "low_accuracy_mode?",
+        method!(LanguageDetectorWrapper::is_low_accuracy_mode, 0),
+    )?;
+    detector_class.define_method("inspect", method!(LanguageDetectorWrapper::inspect, 0))?;
+    detector_class.define_method(
+        "hybrid_threshold",
+        method!(LanguageDetectorWrapper::hybrid_threshold, 0),
+    )?;
+    detector_class.define_method("preloaded?", method!(LanguageDetectorWrapper::is_preloaded, 0))?;
+    detector_class.define_method(
+        "invalid_utf8_policy",
+        method!(LanguageDetectorWrapper::invalid_utf8_policy, 0),
+    )?;
+    detector_class.define_method(
+        "result_cache_size",
+        method!(LanguageDetectorWrapper::result_cache_size, 0),
+    )?;
+    detector_class.define_method(
+        "clear_result_cache",
+        method!(LanguageDetectorWrapper::clear_result_cache, 0),
+    )?;
+    detector_class.define_method("stats", method!(LanguageDetectorWrapper::stats, 0))?;
+    detector_class.define_method(
+        "reset_stats",
+        method!(LanguageDetectorWrapper::reset_stats, 0),
+    )?;
     detector_class.define_method(
         "unload_language_models",
         method!(LanguageDetectorWrapper::unload_language_models, 0),
     )?;
+    detector_class.define_method("dump", method!(LanguageDetectorWrapper::dump, 1))?;
+    detector_class.define_singleton_method(
+        "restore",
+        function!(LanguageDetectorWrapper::restore, 1),
+    )?;
+    detector_class.define_method(
+        "unload_models_when_idle",
+        method!(LanguageDetectorWrapper::unload_models_when_idle, 1),
+    )?;
+    detector_class.define_method(
+        "unload_models_on_gc",
+        method!(LanguageDetectorWrapper::unload_models_on_gc, 1),
+    )?;
+    detector_class.define_method(
+        "loaded_languages",
+        method!(LanguageDetectorWrapper::loaded_languages, 0),
+    )?;
+    detector_class.define_method(
+        "loaded_language_count",
+        method!(LanguageDetectorWrapper::loaded_language_count, 0),
+    )?;
+    detector_class.define_method(
+        "model_memory_bytes",
+        method!(LanguageDetectorWrapper::model_memory_bytes, 0),
+    )?;
+    detector_class.define_method(
+        "preload_models",
+        method!(LanguageDetectorWrapper::preload_models, 1),
+    )?;
+    detector_class.define_method(
+        "preload_models_async",
+        method!(LanguageDetectorWrapper::preload_models_async, -1),
+    )?;
+    detector_class.define_method(
+        "prefork_warmup!",
+        method!(LanguageDetectorWrapper::prefork_warmup, 0),
+    )?;
+    detector_class.define_method(
+        "on_fallback",
+        method!(LanguageDetectorWrapper::on_fallback, -1),
+    )?;
+    detector_class.define_method(
+        "detect_arrow",
+        method!(LanguageDetectorWrapper::detect_arrow, -1),
+    )?;
+    detector_class.define_method(
+        "detect_file",
+        method!(LanguageDetectorWrapper::detect_file, -1),
+    )?;
+    detector_class.define_method(
+        "write_json_lines",
+        method!(LanguageDetectorWrapper::write_json_lines, -1),
+    )?;
+    detector_class.define_method(
+        "detect_directory",
+        method!(LanguageDetectorWrapper::detect_directory, -1),
+    )?;
+    detector_class.define_method("benchmark", method!(LanguageDetectorWrapper::benchmark, -1))?;
+
+    // The detection methods below only touch Sync native state, so they can be called from
+    // any Ractor once the detector has been made shareable. Hooks, the tracer and detector
+    // callbacks stay with the main Ractor; see `instrument` and `ractor_callable`.
+    unsafe { rb_sys::rb_ext_ractor_safe(true) };
     detector_class.define_method(
         "detect_language",
-        method!(LanguageDetectorWrapper::detect_language, 1),
+        method!(LanguageDetectorWrapper::detect_language, -1),
+    )?;
+    detector_class.define_method(
+        "detect_language!",
+        method!(LanguageDetectorWrapper::detect_language_or_raise, -1),
+    )?;
+    detector_class.define_method(
+        "detect_language_with_confidence",
+        method!(LanguageDetectorWrapper::detect_language_with_confidence, -1),
+    )?;
+    detector_class.define_method(
+        "detect_top_languages",
+        method!(LanguageDetectorWrapper::detect_top_languages, 2),
+    )?;
+    detector_class.define_method(
+        "explain_detection",
+        method!(LanguageDetectorWrapper::explain_detection, -1),
+    )?;
+    detector_class.define_method(
+        "group_confidence_values",
+        method!(LanguageDetectorWrapper::group_confidence_values, -1),
+    )?;
+    detector_class.define_method("analyze", method!(LanguageDetectorWrapper::analyze, 1))?;
+    detector_class.define_method(
+        "confidence_gap",
+        method!(LanguageDetectorWrapper::confidence_gap, 1),
+    )?;
+    detector_class.define_method(
+        "confidence_gaps_in_parallel",
+        method!(LanguageDetectorWrapper::confidence_gaps_in_parallel, -1),
+    )?;
+    detector_class.define_method("reliable?", method!(LanguageDetectorWrapper::is_reliable, -1))?;
+    detector_class.define_method(
+        "confidence_bucket",
+        method!(LanguageDetectorWrapper::confidence_bucket, 1),
     )?;
     detector_class.define_method(
         "detect_languages_in_parallel",
-        method!(LanguageDetectorWrapper::detect_languages_in_parallel, 1),
+        method!(LanguageDetectorWrapper::detect_languages_in_parallel, -1),
+    )?;
+    detector_class.define_method(
+        "detect_languages_keyed",
+        method!(LanguageDetectorWrapper::detect_languages_keyed, -1),
+    )?;
+    detector_class.define_method(
+        "language_distribution",
+        method!(LanguageDetectorWrapper::language_distribution, -1),
+    )?;
+    detector_class.define_method(
+        "detect_each",
+        method!(LanguageDetectorWrapper::detect_each, -1),
     )?;
     detector_class.define_method(
         "detect_multiple_languages",
-        method!(LanguageDetectorWrapper::detect_multiple_languages, 1),
+        method!(LanguageDetectorWrapper::detect_multiple_languages, -1),
+    )?;
+    detector_class.define_method(
+        "language_composition",
+        method!(LanguageDetectorWrapper::language_composition, 1),
+    )?;
+    detector_class.define_method(
+        "detect_sentences",
+        method!(LanguageDetectorWrapper::detect_sentences, 1),
+    )?;
+    detector_class.define_method(
+        "detect_lines",
+        method!(LanguageDetectorWrapper::detect_lines, 1),
+    )?;
+    detector_class.define_method(
+        "detect_words",
+        method!(LanguageDetectorWrapper::detect_words, -1),
     )?;
     detector_class.define_method(
         "detect_multiple_languages_in_parallel",
-        method!(LanguageDetectorWrapper::detect_multiple_languages_in_parallel, 1),
+        method!(LanguageDetectorWrapper::detect_multiple_languages_in_parallel, -1),
     )?;
     detector_class.define_method(
         "compute_language_confidence_values",
-        method!(LanguageDetectorWrapper::compute_language_confidence_values, 1),
+        method!(LanguageDetectorWrapper::compute_language_confidence_values, -1),
     )?;
     detector_class.define_method(
         "compute_language_confidence_values_in_parallel",
-        method!(LanguageDetectorWrapper::compute_language_confidence_values_in_parallel, 1),
+        method!(LanguageDetectorWrapper::compute_language_confidence_values_in_parallel, -1),
     )?;
     detector_class.define_method(
         "compute_language_confidence",
@@ -489,8 +6846,96 @@ fn init(ruby: &Ruby) -> Result<(), Error> {
     )?;
     detector_class.define_method(
         "compute_language_confidence_in_parallel",
-        method!(LanguageDetectorWrapper::compute_language_confidence_in_parallel, 2),
+        method!(LanguageDetectorWrapper::compute_language_confidence_in_parallel, -1),
+    )?;
+    detector_class.define_method(
+        "compute_language_confidence_pairs",
+        method!(LanguageDetectorWrapper::compute_language_confidence_pairs, 1),
+    )?;
+    unsafe { rb_sys::rb_ext_ractor_safe(false) };
+
+    let handle_class = module.define_class("ModelLoadHandle", ruby.class_object())?;
+    handle_class.define_method("ready?", method!(ModelLoadHandle::is_ready, 0))?;
+    handle_class.define_method("wait", method!(ModelLoadHandle::wait, -1))?;
+
+    let confidence_class = module.define_class("LanguageConfidence", ruby.class_object())?;
+    confidence_class.include_module(ruby.module_comparable())?;
+    confidence_class.define_singleton_method("new", function!(LanguageConfidence::rb_new, 2))?;
+    confidence_class.define_private_method(
+        "initialize",
+        method!(initialize_wrapped::<LanguageConfidence>, -1),
     )?;
+    confidence_class.define_method("language", method!(LanguageConfidence::language, 0))?;
+    confidence_class.define_method("confidence", method!(LanguageConfidence::confidence, 0))?;
+    confidence_class.define_method("<=>", method!(LanguageConfidence::compare, 1))?;
+    confidence_class.define_method("==", method!(LanguageConfidence::equals, 1))?;
+    confidence_class.define_method("eql?", method!(LanguageConfidence::is_eql, 1))?;
+    confidence_class.define_method("hash", method!(LanguageConfidence::hash, 0))?;
+    confidence_class.define_method("[]", method!(LanguageConfidence::element, 1))?;
+    confidence_class.define_method("first", method!(LanguageConfidence::language, 0))?;
+    confidence_class.define_method("last", method!(LanguageConfidence::last, -1))?;
+    confidence_class.define_method("script", method!(LanguageConfidence::script, 0))?;
+    confidence_class.define_method("direction", method!(LanguageConfidence::direction, 0))?;
+    confidence_class.define_method("gap", method!(LanguageConfidence::gap, 0))?;
+    confidence_class.define_method("to_a", method!(LanguageConfidence::to_a, 0))?;
+    confidence_class.define_method("to_ary", method!(LanguageConfidence::to_a, 0))?;
+    confidence_class.define_method("deconstruct", method!(LanguageConfidence::to_a, 0))?;
+    confidence_class.define_method(
+        "deconstruct_keys",
+        method!(LanguageConfidence::deconstruct_keys, 1),
+    )?;
+    confidence_class.define_method("to_h", method!(LanguageConfidence::to_h, 0))?;
+    confidence_class.define_method("to_json", method!(LanguageConfidence::to_json, -1))?;
+    confidence_class.define_method("inspect", method!(LanguageConfidence::inspect, 0))?;
+
+    let analysis_class = module.define_class("DetectionAnalysis", ruby.class_object())?;
+    analysis_class.define_method("language", method!(DetectionAnalysis::language, 0))?;
+    analysis_class.define_method("confidence", method!(DetectionAnalysis::confidence, 0))?;
+    analysis_class.define_method("reliable?", method!(DetectionAnalysis::is_reliable, 0))?;
+    analysis_class.define_method("script", method!(DetectionAnalysis::script, 0))?;
+    analysis_class.define_method("runner_up", method!(DetectionAnalysis::runner_up, 0))?;
+    analysis_class.define_method("to_h", method!(DetectionAnalysis::to_h, 0))?;
+    analysis_class.define_method("to_json", method!(DetectionAnalysis::to_json, -1))?;
+
+    let pool_class = module.define_class("DetectorPool", ruby.class_object())?;
+    pool_class.define_singleton_method("new", function!(DetectorPool::new, -1))?;
+    pool_class.define_private_method(
+        "initialize",
+        method!(initialize_wrapped::<DetectorPool>, -1),
+    )?;
+    pool_class.define_method("size", method!(DetectorPool::size, 0))?;
+    pool_class.define_method("available", method!(DetectorPool::available, 0))?;
+    pool_class.define_method("checkout", method!(DetectorPool::checkout, -1))?;
+    pool_class.define_method("checkin", method!(DetectorPool::checkin, 1))?;
+    pool_class.define_method("with", method!(DetectorPool::with, -1))?;
+
+    let evaluator_class = module.define_class("Evaluator", ruby.class_object())?;
+    evaluator_class.define_singleton_method("new", function!(Evaluator::new, 1))?;
+    evaluator_class.define_private_method(
+        "initialize",
+        method!(initialize_wrapped::<Evaluator>, -1),
+    )?;
+    evaluator_class.define_method("detector", method!(Evaluator::detector, 0))?;
+    evaluator_class.define_method("evaluate", method!(Evaluator::evaluate, -1))?;
+
+    let report_class = module.define_class("EvaluationReport", ruby.class_object())?;
+    report_class.define_method("size", method!(EvaluationReport::size, 0))?;
+    report_class.define_method("accuracy", method!(EvaluationReport::accuracy, 0))?;
+    report_class.define_method("precision", method!(EvaluationReport::precision, 1))?;
+    report_class.define_method("recall", method!(EvaluationReport::recall, 1))?;
+    report_class.define_method("per_language", method!(EvaluationReport::per_language, 0))?;
+    report_class.define_method("confusion_matrix", method!(EvaluationReport::confusion_matrix, 0))?;
+    report_class.define_method("to_h", method!(EvaluationReport::to_h, 0))?;
+    report_class.define_method("to_json", method!(EvaluationReport::to_json, -1))?;
+
+    let matrix_class = module.define_class("ConfusionMatrix", ruby.class_object())?;
+    matrix_class.define_method("labels", method!(ConfusionMatrix::labels, 0))?;
+    matrix_class.define_method("[]", method!(ConfusionMatrix::count, 2))?;
+    matrix_class.define_method("total", method!(ConfusionMatrix::total, 0))?;
+    matrix_class.define_method("to_a", method!(ConfusionMatrix::to_a, 0))?;
+    matrix_class.define_method("undetected", method!(ConfusionMatrix::undetected, 0))?;
+    matrix_class.define_method("to_h", method!(ConfusionMatrix::to_h, 0))?;
+    matrix_class.define_method("to_json", method!(ConfusionMatrix::to_json, -1))?;
 
     Ok(())
 }
@@ -4,6 +4,5 @@ require_relative "lingua_rs_rb/version"
 require_relative "lingua_rs_rb/lingua_rs_rb"
 
 module LinguaRsRb
-  class Error < StandardError; end
   # Your code goes here...
 end
@@ -0,0 +1,149 @@
+# frozen_string_literal: true
+
+require "optparse"
+require_relative "../lingua_rs_rb"
+
+module LinguaRsRb
+  # Command line entry point behind `exe/lingua_rs_rb`. Every command runs on the native
+  # detector; this class only parses arguments and formats output.
+  class CLI
+    BATCH_SIZE = 1_000
+
+    def self.start(argv, stdin: $stdin, stdout: $stdout, stderr: $stderr)
+      new(stdin: stdin, stdout: stdout, stderr: stderr).run(argv)
+    end
+
+    def initialize(stdin:, stdout:, stderr:)
+      @stdin = stdin
+      @stdout = stdout
+      @stderr = stderr
+    end
+
+    def run(argv)
+      argv = argv.dup
+      case (command = argv.shift)
+      when "detect" then detect(argv)
+      when "accuracy-report" then accuracy_report(argv)
+      when nil, "-h", "--help"
+        @stdout.puts usage
+        command.nil? ? 1 : 0
+      when "-v", "--version"
+        @stdout.puts VERSION
+        0
+      else
+        fail_with("unknown command: #{command}")
+      end
+    rescue OptionParser::ParseError, LinguaRsRb::Error, ArgumentError, SystemCallError => e
+      fail_with(e.message)
+    end
+
+    private
+
+    def usage
+      <<~USAGE
+        Usage: lingua_rs_rb <command> [options]
+
+        Commands:
+          detect [FILE...]          Detect the language of each line of FILE, or of stdin for - or no FILE
+          accuracy-report FILE      Print per-language precision and recall for a labeled TSV file
+      USAGE
+    end
+
+    def detect(argv)
+      options = { confidence: true }
+      parser = detector_options(OptionParser.new("Usage: lingua_rs_rb detect [options] [FILE...]"), options)
+      parser.on("--[no-]confidence", "Print the confidence next to each language (default: on)") do |value|
+        options[:confidence] = value
+      end
+      files = parser.parse(argv)
+      detector = build_detector(options)
+
+      each_input(files.empty? ? ["-"] : files) do |input|
+        input.each_line.each_slice(BATCH_SIZE) do |lines|
+          keyed = lines.each_with_index.to_h { |line, index| [index, line.chomp] }
+          detector.detect_languages_keyed(keyed, confidence: options[:confidence]).each_value do |result|
+            @stdout.puts format_detection(result)
+          end
+        end
+      end
+      0
+    end
+
+    # Each input line is a text, a tab and its expected language; lines without a tab are
+    # skipped. Expected languages are compared with the detector's labels as written.
+    def accuracy_report(argv)
+      options = {}
+      parser = detector_options(OptionParser.new("Usage: lingua_rs_rb accuracy-report [options] FILE"), options)
+      files = parser.parse(argv)
+      return fail_with("accuracy-report takes exactly one FILE") unless files.size == 1
+
+      evaluator = Evaluator.new(build_detector(options))
+      samples = []
+      each_input(files) do |input|
+        input.each_line do |line|
+          text, tab, expected = line.chomp.rpartition("\t")
+          samples << [text, expected] unless tab.empty?
+        end
+      end
+      @stdout.puts format_report(evaluator.evaluate(samples))
+      0
+    end
+
+    def format_report(report)
+      scores = report.per_language
+      width = [*scores.keys.map(&:length), "language".length].max
+      rows = scores.map do |language, score|
+        format("%-*s  %9.4f  %6.4f  %7d", width, language, score[:precision], score[:recall], score[:support])
+      end
+      [format("%-*s  %9s  %6s  %7s", width, "language", "precision", "recall", "support"), *rows,
+       format("accuracy: %.4f (%d samples)", report.accuracy, report.size)].join("\n")
+    end
+
+    def detector_options(parser, options)
+      parser.on("-l", "--languages LIST", Array, "Languages to consider, as names or ISO 639 codes") do |list|
+        options[:languages] = list
+      end
+      parser.on("--low-accuracy", "Use the low accuracy mode") { options[:low_accuracy] = true }
+      parser.on("--minimum-relative-distance DISTANCE", Float, "Minimum relative distance (0.0 to 0.99)") do |value|
+        options[:minimum_relative_distance] = value
+      end
+    end
+
+    def build_detector(options)
+      languages = options[:languages]
+      builder =
+        if languages.nil?
+          LanguageDetectorBuilder.from_all_languages
+        else
+          LanguageDetectorBuilder.from_identifiers(languages)
+        end
+      builder = builder.with_low_accuracy_mode if options[:low_accuracy]
+      distance = options[:minimum_relative_distance]
+      builder = builder.with_minimum_relative_distance(distance) if distance
+      builder.build
+    end
+
+    def each_input(files, &block)
+      files.each do |file|
+        if file == "-"
+          yield @stdin
+        else
+          File.open(file, "r:UTF-8", &block)
+        end
+      end
+    end
+
+    def format_detection(result)
+      case result
+      when nil then "-"
+      when Array then format("%<language>s\t%<confidence>.4f", language: result[0], confidence: result[1])
+      else result
+      end
+    end
+
+    def fail_with(message)
+      @stderr.puts "lingua_rs_rb: #{message}"
+      1
+    end
+  end
+end
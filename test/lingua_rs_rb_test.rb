@@ -1,6 +1,7 @@
 # frozen_string_literal: true
 
 require_relative "test_helper"
+require "tmpdir"
 
 class LinguaRsRbTest < Minitest::Test
   def test_languages_list_exposes_english
@@ -33,6 +34,107 @@ class LinguaRsRbTest < Minitest::Test
     assert_equal "Greek", greek
   end
 
+  def test_single_language_detector_without_letters
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English])
+
+    assert_equal "English", detector.detect_language("This is an English sentence.")
+    assert_nil detector.detect_language("12345 !!!")
+    assert_nil detector.detect_language_with_confidence("12345 !!!")
+    assert_nil detector.analyze("12345 !!!").language
+    refute detector.reliable?("12345 !!!")
+    assert_equal [[1, nil, 0.0]], detector.detect_lines("12345 !!!")
+  end
+
+  def test_detect_language_restricted_per_call
+    detector = LinguaRsRb::LanguageDetectorBuilder
+               .from_all_languages
+               .with_minimum_relative_distance(0.0)
+               .build
+
+    text = "Hola, ¿cómo estás? Este texto está en español."
+    assert_equal "Spanish", detector.detect_language(text)
+    refute_equal "Spanish", detector.detect_language(text, only: %w[English German French])
+    assert_raises(LinguaRsRb::UnknownLanguageError) { detector.detect_language(text, only: ["Klingon"]) }
+    assert_nil detector.detect_language("12345", only: ["English"])
+    assert_nil detector.detect_language("12345 !!!", only: %w[English German])
+
+    narrow = LinguaRsRb::LanguageDetector.new(languages: %w[English German])
+    assert_equal "German", narrow.detect_language("Guten Morgen, wie geht es dir?", only: ["German"])
+    error = assert_raises(ArgumentError) { narrow.detect_language(text, only: ["Japanese"]) }
+    assert_includes error.message, "Japanese"
+  end
+
+  def test_detect_language_with_confidence
+    detector = LinguaRsRb::LanguageDetectorBuilder
+               .from_all_languages
+               .with_minimum_relative_distance(0.0)
+               .build
+
+    language, confidence = detector.detect_language_with_confidence("This is a test sentence written in English.")
+    assert_equal "English", language
+    assert_kind_of Float, confidence
+    assert_nil detector.detect_language_with_confidence("12345 !!!")
+  end
+
+  def test_detect_top_languages
+    detector = LinguaRsRb::LanguageDetectorBuilder
+               .from_all_languages
+               .build
+
+    top = detector.detect_top_languages("Bonjour tout le monde, comment allez-vous?", 3)
+    assert_equal 3, top.size
+    assert_equal "French", top.first.first
+    assert_equal top.map(&:last).sort.reverse, top.map(&:last)
+  end
+
+  def test_reliability_uses_top_two_gap
+    detector = LinguaRsRb::LanguageDetectorBuilder
+               .from_languages(%w[English German])
+               .with_minimum_relative_distance(0.25)
+               .build
+
+    text = "This is a long and clearly English sentence about the weather."
+    gap = detector.confidence_gap(text)
+    assert_kind_of Float, gap
+    assert gap.between?(0.0, 1.0)
+    assert detector.reliable?(text)
+    refute detector.reliable?(text, threshold: 1.0)
+    assert detector.reliable?(text, threshold: gap / 2)
+    assert_raises(ArgumentError) { detector.reliable?(text, 0.5) }
+  end
+
+  def test_reliability_threshold_defaults_to_the_minimum_relative_distance
+    default = LinguaRsRb::LanguageDetector.new(languages: %w[English German])
+    strict = LinguaRsRb::LanguageDetector.new(languages: %w[English German], minimum_relative_distance: 0.9)
+    text = "The house"
+    gap = default.confidence_gap(text)
+
+    assert_equal 0.0, default.to_config_h[:minimum_relative_distance]
+    assert_operator gap, :>, 0.0
+    assert default.reliable?(text)
+    assert_equal gap >= 0.9, strict.reliable?(text)
+    assert_equal strict.reliable?(text), default.reliable?(text, threshold: 0.9)
+    assert strict.reliable?(text, threshold: 0.0)
+  end
+
+  def test_confidence_bucket_uses_builder_thresholds
+    detector = LinguaRsRb::LanguageDetectorBuilder
+               .from_languages(%w[English German])
+               .with_confidence_thresholds(0.9, 0.5)
+               .build
+
+    bucket = detector.confidence_bucket("This is a long and clearly English sentence about the weather.")
+    assert_includes %i[high medium low], bucket
+    assert_equal :low, detector.confidence_bucket("12345")
+  end
+
+  def test_confidence_thresholds_validation
+    builder = LinguaRsRb::LanguageDetectorBuilder.from_all_languages
+    assert_raises(ArgumentError) do
+      builder.with_confidence_thresholds(0.3, 0.6)
+    end
+  end
+
   def test_confidence_values_return_pairs
     detector = LinguaRsRb::LanguageDetectorBuilder
                .from_all_languages
@@ -41,8 +143,8 @@ class LinguaRsRbTest < Minitest::Test
     values = detector.compute_language_confidence_values("Bonjour tout le monde")
     assert_kind_of Array, values
     refute_empty values
-    assert_kind_of Array, values.first
-    assert_equal 2, values.first.size
+    assert_kind_of LinguaRsRb::LanguageConfidence, values.first
+    assert_equal 2, values.first.to_a.size
   end
 
   def test_confidence_values_in_parallel_return_pairs
@@ -56,8 +158,34 @@ class LinguaRsRbTest < Minitest::Test
     assert_kind_of Array, values
     assert_equal texts.size, values.size
     refute_empty values.first
-    assert_kind_of Array, values.first.first
-    assert_equal 2, values.first.first.size
+    assert_kind_of LinguaRsRb::LanguageConfidence, values.first.first
+    assert_equal 2, values.first.first.to_a.size
+  end
+
+  def test_confidence_values_top_k_truncates_results
+    detector = LinguaRsRb::LanguageDetectorBuilder
+               .from_all_languages
+               .build
+
+    values = detector.compute_language_confidence_values("Bonjour tout le monde", top_k: 3)
+    assert_equal 3, values.size
+
+    texts = ["Bonjour tout le monde", "Hola, ¿qué tal?"]
+    batches = detector.compute_language_confidence_values_in_parallel(texts, top_k: 2)
+    batches.each { |batch| assert_equal 2, batch.size }
+  end
+
+  def test_confidence_values_min_confidence_filters_results
+    detector = LinguaRsRb::LanguageDetectorBuilder
+               .from_all_languages
+               .build
+
+    values = detector.compute_language_confidence_values("Bonjour tout le monde", min_confidence: 0.05)
+    refute_empty values
+    values.each { |(_, confidence)| assert confidence >= 0.05 }
+
+    batches = detector.compute_language_confidence_values_in_parallel(["Hola, ¿qué tal?"], min_confidence: 0.05)
+    batches.first.each { |(_, confidence)| assert confidence >= 0.05 }
   end
 
   def test_confidence_for_language_and_parallel
@@ -78,6 +206,1850 @@ class LinguaRsRbTest < Minitest::Test
     confidences.each { |value| assert_kind_of Float, value }
   end
 
+  def test_confidence_for_text_language_pairs
+    detector = LinguaRsRb::LanguageDetectorBuilder
+               .from_all_languages
+               .build
+
+    pairs = [
+      ["This is a short English sentence.", "English"],
+      ["Hola, ¿cómo estás?", "Spanish"],
+      ["Hola, ¿cómo estás?", "English"]
+    ]
+    confidences = detector.compute_language_confidence_pairs(pairs)
+
+    assert_equal pairs.size, confidences.size
+    confidences.each { |value| assert_kind_of Float, value }
+    assert_in_delta detector.compute_language_confidence(*pairs.first), confidences.first, 1e-9
+    assert confidences[1] > confidences[2]
+  end
+
+  def test_detector_languages_introspection
+    detector = LinguaRsRb::LanguageDetectorBuilder
+               .from_languages(%w[German English French])
+               .build
+    assert_equal %w[English French German], detector.languages
+
+    detector = LinguaRsRb::LanguageDetectorBuilder
+               .from_iso_codes_639_1(%w[en es])
+               .build
+    assert_equal %w[English Spanish], detector.languages
+
+    all = LinguaRsRb::LanguageDetectorBuilder.from_all_languages_without(["English"]).build
+    refute_includes all.languages, "English"
+    assert_equal LinguaRsRb.languages.size - 1, all.languages.size
+  end
+
+  def test_detector_option_getters
+    detector = LinguaRsRb::LanguageDetectorBuilder
+               .from_languages(%w[English German])
+               .build
+    assert_in_delta 0.0, detector.minimum_relative_distance
+    refute detector.low_accuracy_mode?
+    refute detector.preloaded?
+
+    detector = LinguaRsRb::LanguageDetectorBuilder
+               .from_languages(%w[English German])
+               .with_minimum_relative_distance(0.3)
+               .with_low_accuracy_mode
+               .with_preloaded_language_models
+               .build
+    assert_in_delta 0.3, detector.minimum_relative_distance
+    assert detector.low_accuracy_mode?
+    assert detector.preloaded?
+  end
+
+  def test_builder_introspection
+    builder = LinguaRsRb::LanguageDetectorBuilder
+              .from_languages(%w[French English])
+              .with_minimum_relative_distance(0.2)
+              .with_low_accuracy_mode
+
+    assert_equal %w[English French], builder.languages
+    assert_in_delta 0.2, builder.minimum_relative_distance
+    assert builder.low_accuracy_mode?
+    refute builder.preloaded_language_models?
+  end
+
+  def test_builder_is_reusable_after_build
+    builder = LinguaRsRb::LanguageDetectorBuilder.from_languages(%w[English German])
+    first = builder.build
+    second = builder.with_low_accuracy_mode.build
+
+    refute first.low_accuracy_mode?
+    assert second.low_accuracy_mode?
+    assert_equal first.languages, second.languages
+  end
+
+  def test_builder_dup_forks_configuration
+    base = LinguaRsRb::LanguageDetectorBuilder
+           .from_languages(%w[English German])
+           .with_minimum_relative_distance(0.1)
+    low = base.dup.with_low_accuracy_mode
+    high = base.clone
+
+    assert low.low_accuracy_mode?
+    refute high.low_accuracy_mode?
+    refute base.low_accuracy_mode?
+    assert_in_delta 0.1, low.minimum_relative_distance
+    assert_equal base.languages, high.build.languages
+  end
+
+  def test_builder_can_be_configured_from_multiple_threads
+    builder = LinguaRsRb::LanguageDetectorBuilder.from_languages(%w[English German])
+    threads = 4.times.map do |i|
+      Thread.new do
+        50.times { builder.with_minimum_relative_distance(i / 10.0).languages }
+      end
+    end
+    threads.each(&:join)
+
+    assert_includes [0.0, 0.1, 0.2, 0.3], builder.minimum_relative_distance
+  end
+
+  def test_detector_new_with_keyword_arguments
+    detector = LinguaRsRb::LanguageDetector.new(
+      languages: %w[English German],
+      minimum_relative_distance: 0.2,
+      low_accuracy: true
+    )
+
+    assert_equal %w[English German], detector.languages
+    assert_in_delta 0.2, detector.minimum_relative_distance
+    assert detector.low_accuracy_mode?
+    refute detector.preloaded?
+    assert_equal "German", detector.detect_language("Guten Morgen, wie geht es dir heute?")
+
+    assert_equal LinguaRsRb.languages, LinguaRsRb::LanguageDetector.new.languages
+    assert_raises(ArgumentError) { LinguaRsRb::LanguageDetector.new(minimum_relative_distance: 2.0) }
+  end
+
+  def test_builder_from_mixed_identifiers
+    builder = LinguaRsRb::LanguageDetectorBuilder.from_identifiers(%w[en deu French])
+    assert_equal %w[English French German], builder.languages
+
+    error = assert_raises(LinguaRsRb::UnknownLanguageError) do
+      LinguaRsRb::LanguageDetectorBuilder.from_identifiers(%w[en xx])
+    end
+    assert_includes error.message, "xx"
+  end
+
+  def test_language_names_are_case_insensitive_and_accept_aliases
+    builder = LinguaRsRb::LanguageDetectorBuilder.from_languages(%w[english GERMAN Farsi Mandarin])
+    assert_equal %w[Chinese English German Persian], builder.languages
+
+    begin
+      LinguaRsRb.strict_language_names = true
+      assert LinguaRsRb.strict_language_names?
+      assert_raises(LinguaRsRb::UnknownLanguageError) do
+        LinguaRsRb::LanguageDetectorBuilder.from_languages(%w[english German])
+      end
+    ensure
+      LinguaRsRb.strict_language_names = false
+    end
+  end
+
+  def test_builder_boolean_toggles
+    builder = LinguaRsRb::LanguageDetectorBuilder
+              .from_languages(%w[English German])
+              .with_low_accuracy_mode
+              .with_preloaded_language_models(false)
+    assert builder.low_accuracy_mode?
+    refute builder.preloaded_language_models?
+
+    builder.with_low_accuracy_mode(false).with_preloaded_language_models(true)
+    refute builder.low_accuracy_mode?
+    assert builder.preloaded_language_models?
+
+    builder.with_low_accuracy_mode(nil)
+    refute builder.low_accuracy_mode?
+  end
+
+  def test_detector_config_round_trip
+    detector = LinguaRsRb::LanguageDetectorBuilder
+               .from_languages(%w[English German])
+               .with_minimum_relative_distance(0.15)
+               .with_low_accuracy_mode
+               .build
+
+    config = detector.to_config_h
+    assert_equal %w[English German], config[:languages]
+    assert_in_delta 0.15, config[:minimum_relative_distance]
+    assert config[:low_accuracy_mode]
+    refute config[:preloaded_language_models]
+
+    restored = LinguaRsRb::LanguageDetector.load(config)
+    assert_equal config, restored.to_config_h
+
+    from_strings = LinguaRsRb::LanguageDetector.load(config.transform_keys(&:to_s))
+    assert_equal config, from_strings.to_config_h
+  end
+
+  def test_builder_from_config_file
+    Dir.mktmpdir do |dir|
+      yaml = File.join(dir, "lingua.yml")
+      File.write(yaml, <<~YAML)
+        languages: [en, deu, French, Spanish]
+        exclude: [Spanish]
+        minimum_relative_distance: 0.1
+        accuracy_mode: low
+      YAML
+      builder = LinguaRsRb::LanguageDetectorBuilder.from_config_file(yaml)
+      assert_equal %w[English French German], builder.languages
+      assert_in_delta 0.1, builder.minimum_relative_distance
+      assert builder.low_accuracy_mode?
+
+      json = File.join(dir, "lingua.json")
+      File.write(json, '{"exclude": ["Latin"], "preload": true}')
+      builder = LinguaRsRb::LanguageDetectorBuilder.from_config_file(json)
+      refute_includes builder.languages, "Latin"
+      assert builder.preloaded_language_models?
+
+      File.write(yaml, "accuracy_mode: medium\n")
+      assert_raises(ArgumentError) { LinguaRsRb::LanguageDetectorBuilder.from_config_file(yaml) }
+    end
+  end
+
+  def test_module_level_default_detector
+    assert_equal "French", LinguaRsRb.detect("Bonjour tout le monde, comment allez-vous?")
+
+    LinguaRsRb.configure do |builder|
+      assert_kind_of LinguaRsRb::LanguageDetectorBuilder, builder
+      builder.with_low_accuracy_mode
+    end
+    results = 4.times.map { Thread.new { LinguaRsRb.detect("Guten Morgen, wie geht es dir?") } }.map(&:value)
+    assert_equal ["German"] * 4, results
+  ensure
+    LinguaRsRb.configure { |builder| builder.with_low_accuracy_mode(false) }
+  end
+
+  def test_named_detector_profiles
+    LinguaRsRb.register_profile(:european, languages: %w[English German French], low_accuracy: true)
+
+    detector = LinguaRsRb.profile(:european)
+    assert_same detector, LinguaRsRb.profile("european")
+    assert_equal %w[English French German], detector.languages
+    assert detector.low_accuracy_mode?
+    assert_includes LinguaRsRb.profile_names, "european"
+
+    LinguaRsRb.register_profile(:european, languages: %w[English German])
+    refute_same detector, LinguaRsRb.profile(:european)
+    assert_equal %w[English German], LinguaRsRb.profile(:european).languages
+
+    assert_raises(ArgumentError) { LinguaRsRb.profile(:missing) }
+  end
+
+  def test_detector_pool_checkout_and_checkin
+    pool = LinguaRsRb::DetectorPool.new(size: 2, timeout: 0.05, languages: %w[English German])
+    assert_equal 2, pool.size
+
+    result = pool.with { |detector| detector.detect_language("Guten Morgen, wie geht es dir?") }
+    assert_equal "German", result
+    assert_equal 2, pool.available
+
+    first = pool.checkout
+    second = pool.checkout
+    assert_equal 0, pool.available
+    error = assert_raises(LinguaRsRb::TimeoutError) { pool.checkout }
+    assert_includes error.message, "pool (size 2)"
+
+    pool.checkin(first)
+    assert_raises(ArgumentError) { pool.checkin(first) }
+    pool.checkin(second)
+    assert_equal 2, pool.available
+  end
+
+  def test_detector_pool_rejects_invalid_timeouts
+    pool = LinguaRsRb::DetectorPool.new(size: 1, languages: %w[English German])
+    [-1, Float::NAN, Float::INFINITY, 1e300].each do |timeout|
+      assert_raises(ArgumentError) { pool.checkout(timeout: timeout) }
+      assert_raises(ArgumentError) { pool.with(timeout: timeout) { flunk } }
+      assert_raises(ArgumentError) { LinguaRsRb::DetectorPool.new(size: 1, timeout: timeout) }
+    end
+    assert_equal 1, pool.available
+  end
+
+  def test_detector_is_ractor_shareable
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English German])
+    Ractor.make_shareable(detector)
+    assert Ractor.shareable?(detector)
+
+    assert_equal "German", in_ractor(detector) { |shared| shared.detect_language("Guten Morgen, wie geht es dir?") }
+    error = assert_raises(Ractor::RemoteError) { in_ractor(detector) { |shared| shared.dump(File::NULL) } }
+    assert_kind_of Ractor::UnsafeError, error.cause
+  end
+
+  def test_fallback_outside_the_main_ractor_must_be_shareable
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English German])
+    detector.on_fallback { "unknown" }
+    Ractor.make_shareable(detector)
+    assert_equal "unknown", detector.detect_language("")
+    error = assert_raises(Ractor::RemoteError) { in_ractor(detector) { |shared| shared.detect_language("") } }
+    assert_kind_of Ractor::UnsafeError, error.cause
+
+    shareable = LinguaRsRb::LanguageDetector.new(languages: %w[English German])
+    shareable.on_fallback(&Ractor.make_shareable(nil.instance_eval { proc { "unknown" } }))
+    Ractor.make_shareable(shareable)
+    assert_equal "unknown", in_ractor(shareable) { |shared| shared.detect_language("") }
+  end
+
+  def test_preprocessor_outside_the_main_ractor_must_be_shareable
+    languages = %w[English German]
+    strip = ->(text) { text.strip }
+    detector = Ractor.make_shareable(LinguaRsRb::LanguageDetector.new(languages: languages, preprocessor: strip))
+    error = assert_raises(Ractor::RemoteError) do
+      in_ractor(detector) { |shared| shared.detect_language(" Guten Morgen ") }
+    end
+    assert_kind_of Ractor::UnsafeError, error.cause
+
+    strip = Ractor.make_shareable(nil.instance_eval { ->(text) { text.strip } })
+    detector = Ractor.make_shareable(LinguaRsRb::LanguageDetector.new(languages: languages, preprocessor: strip))
+    assert_equal "German", in_ractor(detector) { |shared| shared.detect_language(" Guten Morgen ") }
+  end
+
+  def in_ractor(*args, &block)
+    previous = Warning[:experimental]
+    Warning[:experimental] = false
+    Ractor.new(*args, &block).take
+  ensure
+    Warning[:experimental] = previous
+  end
+
+  def test_wrapped_objects_survive_gc_compaction
+    skip "GC compaction is not supported on this platform" unless GC.respond_to?(:verify_compaction_references)
+
+    builder = LinguaRsRb::LanguageDetectorBuilder.from_languages(%w[English German])
+    detector = builder.build
+    pool = LinguaRsRb::DetectorPool.new(size: 2, languages: %w[English German])
+
+    GC.verify_compaction_references(expand_heap: true, toward: :empty)
+
+    assert_equal %w[English German], builder.languages
+    assert_equal "German", detector.detect_language("Guten Morgen, wie geht es dir?")
+    assert_equal "English", pool.with { |pooled| pooled.detect_language("Good morning, how are you today?") }
+  end
+
+  def test_prefork_warmup
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English German], low_accuracy: true)
+    assert_same detector, detector.prefork_warmup!
+    assert_equal %w[English German], detector.loaded_languages
+    assert_equal "German", detector.detect_language("Guten Morgen, wie geht es dir?")
+  end
+
+  def test_loaded_languages_tracks_eager_loads
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English German])
+    assert_empty detector.loaded_languages
+    assert_equal 0, detector.loaded_language_count
+
+    preloaded = LinguaRsRb::LanguageDetector.new(languages: %w[English German], preload: true)
+    assert_equal %w[English German], preloaded.loaded_languages
+    assert_equal 2, preloaded.loaded_language_count
+
+    preloaded.unload_language_models
+    assert_empty preloaded.loaded_languages
+  end
+
+  def test_idle_model_unloading
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English German], low_accuracy: true)
+    detector.prefork_warmup!
+    detector.unload_models_when_idle(0.05)
+    sleep 0.3
+    assert_empty detector.loaded_languages
+    assert_equal "German", detector.detect_language("Guten Morgen, wie geht es dir?")
+
+    detector.unload_models_when_idle(nil)
+    assert_raises(ArgumentError) { detector.unload_models_when_idle(0) }
+  end
+
+  def test_gc_model_unloading
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English German], low_accuracy: true)
+    detector.prefork_warmup!
+    detector.unload_models_on_gc(true)
+    GC.start(full_mark: true)
+    assert_empty detector.loaded_languages
+    detector.unload_models_on_gc(false)
+  end
+
+  def test_selective_model_preloading
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English German Spanish French])
+    assert_same detector, detector.preload_models(%w[English Spanish])
+    assert_equal %w[English Spanish], detector.loaded_languages
+
+    assert_raises(LinguaRsRb::UnknownLanguageError) { detector.preload_models(["Japanese"]) }
+  end
+
+  def test_background_model_loading
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English German French])
+    handle = detector.preload_models_async(%w[English German])
+    assert_kind_of LinguaRsRb::ModelLoadHandle, handle
+
+    assert handle.wait
+    assert handle.ready?
+    assert_equal %w[English German], detector.loaded_languages
+    assert handle.wait(0)
+    assert handle.wait(1e12)
+    [-1, Float::NAN, Float::INFINITY, 1e300].each do |timeout|
+      assert_raises(ArgumentError) { handle.wait(timeout) }
+    end
+  end
+
+  def test_detector_snapshot_round_trip
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English German French], low_accuracy: true)
+    detector.preload_models(%w[English German])
+
+    Dir.mktmpdir do |dir|
+      path = File.join(dir, "detector.snapshot")
+      detector.dump(path)
+      restored = LinguaRsRb::LanguageDetector.restore(path)
+
+      assert_equal detector.to_config_h, restored.to_config_h
+      assert_equal %w[English German], restored.loaded_languages
+      assert_equal "German", restored.detect_language("Guten Morgen, wie geht es dir?")
+
+      File.write(path, "not a snapshot")
+      assert_raises(ArgumentError) { LinguaRsRb::LanguageDetector.restore(path) }
+    end
+  end
+
+  def test_detector_snapshot_keeps_every_option
+    require "json"
+    detector = LinguaRsRb::LanguageDetector.new(
+      languages: %w[English German Bokmal Nynorsk], invalid_utf8: :scrub, result_cache: 16,
+      hybrid_threshold: 0.5, language_format: :bcp47, merge_languages: { "Bokmal" => "Norwegian" },
+      calibration: 1.5, preprocess: :html, strip_urls: true, strip_junk: true, minimum_letters: 3,
+      minimum_text_length: 4, max_text_length: 200, sampling: :stratified
+    )
+
+    Dir.mktmpdir do |dir|
+      path = File.join(dir, "detector.snapshot")
+      detector.dump(path)
+      restored = LinguaRsRb::LanguageDetector.restore(path)
+
+      assert_equal detector.to_config_h, restored.to_config_h
+      assert_equal :scrub, restored.invalid_utf8_policy
+      assert_equal 16, restored.result_cache_size
+
+      legacy = { format_version: 1, languages: %w[English German], minimum_relative_distance: 0.2,
+                 low_accuracy_mode: false, high_confidence_threshold: 0.8, medium_confidence_threshold: 0.5,
+                 loaded_languages: [] }
+      File.write(path, JSON.generate(legacy))
+      assert_in_delta 0.2, LinguaRsRb::LanguageDetector.restore(path).minimum_relative_distance
+    end
+    assert_raises(TypeError) { LinguaRsRb::LanguageDetector.new(preprocessor: lambda(&:strip)).dump(File::NULL) }
+  end
+
+  def test_shared_model_cache
+    first = LinguaRsRb::LanguageDetector.new(languages: %w[Yoruba Zulu], preload: true)
+    hits = LinguaRsRb.model_cache_stats[:hits]
+    second = LinguaRsRb::LanguageDetector.new(languages: %w[Yoruba Zulu], preload: true)
+
+    stats = LinguaRsRb.model_cache_stats
+    assert_equal hits + 2, stats[:hits]
+    assert_includes stats[:shared_languages], "Yoruba"
+    assert stats[:estimated_bytes].positive?
+
+    second.unload_language_models
+    assert_includes LinguaRsRb.model_cache_stats[:resident_languages], "Zulu"
+    first.unload_language_models
+    refute_includes LinguaRsRb.model_cache_stats[:resident_languages], "Zulu"
+  end
+
+  def test_model_memory_estimates
+    high = LinguaRsRb.estimated_model_memory(%w[English German])
+    low = LinguaRsRb.estimated_model_memory(%w[English German], low_accuracy: true)
+    assert high.positive?
+    assert low < high
+    assert_equal high * 2, LinguaRsRb.estimated_model_memory(%w[English German French Spanish])
+
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English German])
+    assert_equal 0, detector.model_memory_bytes
+    detector.prefork_warmup!
+    assert_equal high, detector.model_memory_bytes
+  end
+
+  def test_error_class_hierarchy
+    assert_operator LinguaRsRb::Error, :<, StandardError
+    assert_operator LinguaRsRb::UnknownLanguageError, :<, LinguaRsRb::Error
+    # Builders are reusable, so there is no error for a consumed one.
+    refute LinguaRsRb.const_defined?(:BuilderConsumedError)
+    assert_operator LinguaRsRb::DetectionError, :<, LinguaRsRb::Error
+
+    error = assert_raises(LinguaRsRb::Error) { LinguaRsRb::LanguageDetectorBuilder.from_languages(["Klingon"]) }
+    assert_includes error.message, "Klingon"
+  end
+
+  def test_lingua_panics_surface_as_internal_error
+    assert_operator LinguaRsRb::InternalError, :<, LinguaRsRb::Error
+
+    builder = LinguaRsRb::LanguageDetectorBuilder.from_languages(["English"])
+    begin
+      builder.build
+    rescue LinguaRsRb::InternalError => e
+      assert_includes e.message, "lingua panicked"
+    end
+  end
+
+  def test_building_without_languages_raises_argument_error
+    builder = LinguaRsRb::LanguageDetectorBuilder.from_all_languages_without(LinguaRsRb.languages)
+    error = assert_raises(ArgumentError) { builder.build }
+    assert_includes error.message, "at least one language"
+    assert_raises(ArgumentError) { builder.build }
+  end
+
+  def test_invalid_utf8_policies
+    broken = "Guten Morgen, wie geht es dir? \xFF".b
+
+    strict = LinguaRsRb::LanguageDetector.new(languages: %w[English German])
+    assert_equal :raise, strict.invalid_utf8_policy
+    assert_raises(EncodingError) { strict.detect_language(broken) }
+
+    scrubbing = LinguaRsRb::LanguageDetector.new(languages: %w[English German], invalid_utf8: :scrub)
+    assert_equal "German", scrubbing.detect_language(broken)
+
+    skipping = LinguaRsRb::LanguageDetector.new(languages: %w[English German], invalid_utf8: :skip)
+    assert_nil skipping.detect_language(broken)
+    assert_equal [nil, "English"], skipping.detect_languages_in_parallel([broken, "Hello, how are you today?"])
+
+    assert_raises(ArgumentError) { LinguaRsRb::LanguageDetector.new(invalid_utf8: :ignore) }
+  end
+
+  def test_non_utf8_input_is_transcoded
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English Japanese Russian])
+
+    japanese = "今日はとても良い天気ですね。散歩に行きましょう。".encode("Shift_JIS")
+    assert_equal "Japanese", detector.detect_language(japanese)
+
+    russian = "Привет, как дела? Сегодня очень хорошая погода.".encode("Windows-1251")
+    assert_equal "Russian", detector.detect_language(russian)
+
+    broken = "\x82\xA0\xFF".dup.force_encoding("Shift_JIS")
+    assert_raises(EncodingError) { detector.detect_language(broken) }
+  end
+
+  def test_large_texts_are_read_in_place
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English German], low_accuracy: true)
+    text = ("Guten Morgen, wie geht es dir? " * 5_000).freeze
+
+    assert_equal "German", detector.detect_language(text)
+    assert_equal %w[German German], detector.detect_languages_in_parallel([text, text])
+    assert_equal ("Guten Morgen, wie geht es dir? " * 5_000), text
+  end
+
+  def test_parallel_methods_accept_lazy_enumerables
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English German], low_accuracy: true)
+    lazy = %w[Hello Guten].cycle.lazy.map { |word| word == "Hello" ? "Hello, how are you?" : "Guten Tag, Freunde!" }
+    assert_equal %w[English German English], detector.detect_languages_in_parallel(lazy.take(3))
+
+    generated = Enumerator.new do |yielder|
+      1_200.times { yielder << "Guten Morgen, wie geht es dir?" }
+    end
+    assert_equal ["German"] * 1_200, detector.detect_languages_in_parallel(generated)
+    assert_equal 1_200, detector.compute_language_confidence_in_parallel(generated, "German").size
+  end
+
+  def test_detect_each_yields_results_incrementally
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English German], low_accuracy: true)
+    texts = ["Hello, how are you today?", "Guten Morgen, wie geht es dir?"]
+
+    yielded = []
+    assert_same detector, detector.detect_each(texts) { |index, language| yielded << [index, language] }
+    assert_equal [[0, "English"], [1, "German"]], yielded
+
+    assert_equal [[0, "English"], [1, "German"]], detector.detect_each(texts).to_a
+  end
+
+  def test_batch_chunk_size
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English German], low_accuracy: true)
+    texts = ["Hello, how are you today?", "Guten Morgen, wie geht es dir?"] * 5
+
+    assert_equal %w[English German] * 5, detector.detect_languages_in_parallel(texts, chunk_size: 3)
+
+    chunks = []
+    detector.detect_each(texts, chunk_size: 4) { |index, _| chunks << index }
+    assert_equal (0...10).to_a, chunks
+
+    values = detector.compute_language_confidence_values_in_parallel(texts, top_k: 1, chunk_size: 2)
+    assert_equal [1] * 10, values.map(&:size)
+
+    assert_raises(ArgumentError) { detector.detect_languages_in_parallel(texts, chunk_size: 0) }
+    assert_raises(ArgumentError) { detector.detect_languages_in_parallel(texts, chunk: 2) }
+  end
+
+  def test_batch_detection_is_interruptible
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English German], low_accuracy: true)
+    texts = Enumerator.new { |yielder| 200_000.times { yielder << "Guten Morgen, wie geht es dir?" } }
+
+    worker = Thread.new { detector.detect_languages_in_parallel(texts, chunk_size: 50) }
+    sleep 0.2
+    worker.raise(Interrupt)
+    assert_raises(Interrupt) { worker.join }
+
+    worker = Thread.new { detector.detect_languages_in_parallel(texts, chunk_size: 50, partial: true) }
+    sleep 0.2
+    worker.raise(Interrupt)
+    partial = worker.value
+    assert_operator partial.size, :<, 200_000
+    assert_equal ["German"], partial.uniq
+  end
+
+  def test_detection_timeouts
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English German], low_accuracy: true)
+    texts = Enumerator.new { |yielder| 200_000.times { yielder << "Guten Morgen, wie geht es dir?" } }
+
+    assert_raises(LinguaRsRb::TimeoutError) { detector.detect_languages_in_parallel(texts, timeout: 0.05) }
+    partial = detector.detect_languages_in_parallel(texts, timeout: 0.05, partial: true)
+    assert_operator partial.size, :<, 200_000
+    assert_equal ["German"], partial.uniq
+
+    assert_equal "German", detector.detect_language("Guten Morgen, wie geht es dir?", timeout: 5.0)
+    values = detector.compute_language_confidence_values("Guten Morgen", top_k: 1, timeout: 5.0)
+    assert_equal "German", values.first.first
+    assert_raises(ArgumentError) { detector.detect_language("Guten Morgen", timeout: 0) }
+  end
+
+  class CountingScheduler
+    attr_reader :sleeps
+
+    def initialize
+      @sleeps = 0
+    end
+
+    def fiber(&block)
+      Fiber.new(blocking: false, &block).tap(&:resume)
+    end
+
+    def kernel_sleep(_duration = nil)
+      @sleeps += 1
+    end
+
+    def block(_blocker, _timeout = nil) = nil
+    def unblock(_blocker, _fiber) = nil
+    def io_wait(_io, _events, _timeout) = nil
+    def close = nil
+  end
+
+  def test_detection_yields_to_the_fiber_scheduler
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English German])
+    text = "Guten Morgen, wie geht es dir? " * 2_000
+    scheduler = CountingScheduler.new
+
+    results = Thread.new do
+      Fiber.set_scheduler(scheduler)
+      detected = nil
+      Fiber.schedule { detected = [detector.detect_language(text), detector.detect_languages_in_parallel([text])] }
+      detected
+    end.value
+
+    assert_equal ["German", ["German"]], results
+    assert_operator scheduler.sleeps, :>, 0
+    assert_equal "German", detector.detect_language(text)
+  end
+
+  def test_max_threads
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English German], low_accuracy: true)
+    assert_nil LinguaRsRb.max_threads
+
+    LinguaRsRb.max_threads = 2
+    assert_equal 2, LinguaRsRb.max_threads
+    assert_equal %w[German English], detector.detect_languages_in_parallel(["Guten Morgen", "Good morning"])
+    assert_equal "German", detector.detect_language("Guten Morgen, wie geht es dir?", timeout: 5.0)
+    assert_raises(ArgumentError) { LinguaRsRb.max_threads = 0 }
+  ensure
+    LinguaRsRb.max_threads = nil
+  end
+
+  def test_serial_batch_threshold
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English German], low_accuracy: true)
+    texts = ["Guten Morgen", "Good morning", "Guten Tag"]
+    assert_equal 4, LinguaRsRb.serial_batch_threshold
+
+    [0, 2, 10].each do |threshold|
+      LinguaRsRb.serial_batch_threshold = threshold
+      assert_equal %w[German English German], detector.detect_languages_in_parallel(texts)
+      assert_equal 3, detector.compute_language_confidence_pairs(texts.zip(%w[German English German])).size
+    end
+  ensure
+    LinguaRsRb.serial_batch_threshold = 4
+  end
+
+  def test_batches_with_repeated_texts
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English German], low_accuracy: true)
+    texts = ["Guten Morgen", "Good morning", "Guten Morgen", "", "Good morning", "Guten Morgen"]
+
+    assert_equal texts.map { |text| detector.detect_language(text) }, detector.detect_languages_in_parallel(texts)
+    values = detector.compute_language_confidence_values_in_parallel(texts)
+    assert_equal values[0], values[2]
+    assert_equal values[1], values[4]
+    assert_equal values[0], values[5]
+  end
+
+  def test_result_cache
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English German], low_accuracy: true, result_cache: 2)
+    assert_equal 2, detector.result_cache_size
+    assert_equal 2, detector.to_config_h[:result_cache_size]
+
+    3.times do
+      assert_equal "German", detector.detect_language("Guten Morgen")
+      assert_equal "English", detector.detect_language("Good morning")
+      batch = detector.detect_languages_in_parallel(["Guten Tag", "Good day", "Guten Tag"])
+      assert_equal %w[German English German], batch
+    end
+    assert_same detector, detector.clear_result_cache
+    assert_equal "German", detector.detect_language("Guten Morgen")
+
+    built = LinguaRsRb::LanguageDetectorBuilder.from_languages(%w[English German]).with_result_cache(10).build
+    assert_equal 10, built.result_cache_size
+    assert_equal 0, LinguaRsRb::LanguageDetector.new(languages: %w[English German]).result_cache_size
+  end
+
+  def test_detector_stats
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English German], low_accuracy: true, result_cache: 10)
+    detector.detect_language("Guten Morgen")
+    detector.detect_language("Guten Morgen")
+    detector.detect_languages_in_parallel(["Good morning", "Guten Tag"])
+    detector.compute_language_confidence_values("Good evening")
+
+    stats = detector.stats
+    assert_equal 5, stats[:detections]
+    assert_equal 1, stats[:cache_hits]
+    assert_equal({ "German" => 3, "English" => 1 }, stats[:languages])
+    assert_equal 0, stats[:undetermined]
+    assert_operator stats[:native_calls], :>=, 3
+    assert_operator stats[:native_time], :>, 0
+    assert_in_delta stats[:native_time] / stats[:native_calls], stats[:average_native_time]
+
+    assert_same detector, detector.reset_stats
+    assert_equal 0, detector.stats[:detections]
+    assert_empty detector.stats[:languages]
+  end
+
+  def test_detection_hooks
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English German], low_accuracy: true)
+    events = []
+    hook = LinguaRsRb.on_detection { |event| events << event }
+
+    detector.detect_language("Guten Morgen")
+    detector.detect_languages_in_parallel(["Guten Morgen", "Good morning"])
+
+    single, batch = events
+    assert_equal :detect_language, single[:method]
+    assert_equal 12, single[:text_length]
+    assert_nil single[:batch_size]
+    assert_equal "German", single[:result]
+    assert_operator single[:duration], :>=, 0
+    assert_equal :detect_languages_in_parallel, batch[:method]
+    assert_equal 2, batch[:batch_size]
+    assert_equal %w[German English], batch[:result]
+  ensure
+    assert LinguaRsRb.remove_detection_hook(hook)
+    detector.detect_language("Guten Morgen")
+    assert_equal 2, events.size
+  end
+
+  def test_detection_hooks_stay_on_the_main_ractor
+    detector = Ractor.make_shareable(LinguaRsRb::LanguageDetector.new(languages: %w[English German]))
+    events = []
+    hook = LinguaRsRb.on_detection { |event| events << event }
+
+    assert_equal "German", in_ractor(detector) { |shared| shared.detect_language("Guten Morgen") }
+    assert_empty events
+    detector.detect_language("Guten Morgen")
+    assert_equal 1, events.size
+  ensure
+    LinguaRsRb.remove_detection_hook(hook)
+  end
+
+  class RecordingTracer
+    Span = Struct.new(:name, :attributes, :start_timestamp, :end_timestamp) do
+      def finish(end_timestamp:)
+        self.end_timestamp = end_timestamp
+      end
+    end
+
+    attr_reader :spans
+
+    def initialize
+      @spans = []
+    end
+
+    def start_span(name, attributes:, start_timestamp:)
+      Span.new(name, attributes, start_timestamp).tap { |span| @spans << span }
+    end
+  end
+
+  def test_tracer_receives_detection_spans
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English German], low_accuracy: true)
+    tracer = RecordingTracer.new
+    LinguaRsRb.tracer = tracer
+    assert_same tracer, LinguaRsRb.tracer
+
+    detector.detect_language("Guten Morgen")
+    detector.detect_languages_in_parallel(["Guten Morgen", "Good morning", "Hallo"])
+
+    single, batch = tracer.spans
+    assert_equal "lingua_rs_rb.detect_language", single.name
+    assert_equal %w[English German], single.attributes["lingua_rs_rb.languages"]
+    assert_equal 12, single.attributes["lingua_rs_rb.text_length"]
+    assert_operator single.end_timestamp, :>=, single.start_timestamp
+    assert_equal "lingua_rs_rb.detect_languages_in_parallel", batch.name
+    assert_equal 3, batch.attributes["lingua_rs_rb.batch_size"]
+  ensure
+    LinguaRsRb.tracer = nil
+    assert_nil LinguaRsRb.tracer
+  end
+
+  def test_tracer_is_not_called_outside_the_main_ractor
+    detector = Ractor.make_shareable(LinguaRsRb::LanguageDetector.new(languages: %w[English German]))
+    tracer = RecordingTracer.new
+    LinguaRsRb.tracer = tracer
+
+    assert_equal "German", in_ractor(detector) { |shared| shared.detect_language("Guten Morgen") }
+    assert_empty tracer.spans
+    detector.detect_language("Guten Morgen")
+    assert_equal 1, tracer.spans.size
+  ensure
+    LinguaRsRb.tracer = nil
+  end
+
+  def test_benchmark_report
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English German], low_accuracy: true)
+    report = detector.benchmark(["Guten Morgen", "Good morning", "Guten Tag"], iterations: 2)
+
+    assert_equal 3, report[:texts]
+    assert_equal 2, report[:iterations]
+    %i[single parallel].each do |path|
+      assert_operator report[path][:texts_per_second], :>, 0
+      assert_operator report[path][:p99], :>=, report[path][:p50]
+      assert_operator report[path][:total_time], :>, 0
+    end
+    assert_raises(ArgumentError) { detector.benchmark(["Hallo"], iterations: 0) }
+  end
+
+  def test_batch_progress_callback
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English German], low_accuracy: true)
+    texts = ["Guten Morgen"] * 10
+
+    calls = []
+    progress = ->(done, total) { calls << [done, total] }
+    detector.detect_languages_in_parallel(texts, progress: progress, progress_every: 4)
+    assert_equal [[4, 10], [8, 10], [10, 10]], calls
+
+    calls = []
+    unsized = Enumerator.new { |yielder| texts.each { |text| yielder << text } }
+    detector.detect_languages_in_parallel(unsized, chunk_size: 5, progress: progress)
+    assert_equal [[5, nil], [10, nil]], calls
+
+    assert_raises(ArgumentError) { detector.detect_languages_in_parallel(texts, progress: proc {}, progress_every: 0) }
+  end
+
+  def test_url_and_social_token_stripping
+    text = "@maria_home https://example.com/news?id=1 mail@example.com Das ist ein Haus #follow #like"
+    plain = LinguaRsRb::LanguageDetector.new(languages: %w[English German])
+    stripping = LinguaRsRb::LanguageDetector.new(languages: %w[English German],
+                                                 strip_urls: true, strip_social_tokens: true)
+
+    only_noise = stripping.compute_language_confidence_values("@someone https://example.com #tbt")
+    assert(only_noise.all? { |_, confidence| confidence.zero? })
+    refute(plain.compute_language_confidence_values("@someone https://example.com #tbt").all? { |_, c| c.zero? })
+    assert_equal "German", stripping.detect_language(text)
+    assert_equal true, stripping.to_config_h[:strip_urls]
+
+    built = LinguaRsRb::LanguageDetectorBuilder.from_languages(%w[English German])
+                                              .with_url_stripping
+                                              .with_social_token_stripping(false)
+                                              .build
+    assert built.to_config_h[:strip_urls]
+    refute built.to_config_h[:strip_social_tokens]
+  end
+
+  def test_url_and_hashtag_stripping_keeps_byte_offsets
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English German],
+                                                strip_urls: true, strip_social_tokens: true)
+    text = "#Grüße https://münchen.de/straße Das ist ein sehr schönes Haus mit einem großen Garten. " \
+           "The weather is lovely today and we are going outside."
+
+    spans = detector.detect_multiple_languages(text)
+    assert_equal %w[German English], spans.map(&:first)
+    assert_equal text.bytesize, spans.last[2]
+    assert_includes text.byteslice(spans.last[1]...spans.last[2]), "The weather is lovely"
+  end
+
+  def test_html_preprocessing
+    page = "<html><head><style>body { color: red; }</style><script>var the = 'and the';</script></head>" \
+           "<body><p>Das ist ein sch&ouml;nes Haus &amp; ein gro&szlig;er Garten</p></body></html>"
+    plain = LinguaRsRb::LanguageDetector.new(languages: %w[English German])
+    html = LinguaRsRb::LanguageDetector.new(languages: %w[English German], preprocess: :html)
+
+    assert_equal "German", html.detect_language(page)
+    assert_equal :html, html.to_config_h[:preprocess]
+    assert_nil plain.to_config_h[:preprocess]
+    assert(html.compute_language_confidence_values("<div><br/></div>").all? { |_, confidence| confidence.zero? })
+    assert_equal :html, LinguaRsRb::LanguageDetector.load(html.to_config_h).to_config_h[:preprocess]
+
+    built = LinguaRsRb::LanguageDetectorBuilder.from_languages(%w[English German]).with_preprocessing(:html).build
+    assert_equal :html, built.to_config_h[:preprocess]
+    assert_raises(ArgumentError) { LinguaRsRb::LanguageDetector.new(preprocess: :pdf) }
+  end
+
+  def test_html_entities_keep_byte_offsets
+    html = LinguaRsRb::LanguageDetector.new(languages: %w[English German], preprocess: :html)
+    page = "<p>Das ist ein sch&ouml;nes Haus &amp; ein gro&szlig;er Garten mit vielen B&auml;umen.</p>" \
+           "<p>The weather is lovely today and we are going outside.</p>"
+
+    spans = html.detect_multiple_languages(page)
+    assert_equal %w[German English], spans.map(&:first)
+    assert_includes page.byteslice(spans.last[1]...spans.last[2]), "The weather is lovely"
+    assert_operator spans.last[2], :<=, page.bytesize
+  end
+
+  def test_markdown_stripping_keeps_byte_offsets
+    markdown = LinguaRsRb::LanguageDetector.new(languages: %w[English German], preprocess: :markdown)
+    post = "Das ist ein sehr schönes [Haus](https://de.wikipedia.org/wiki/Gebäude_für_Müller) mit einem " \
+           "großen Garten. The weather is lovely today and we are going outside."
+
+    spans = markdown.detect_multiple_languages(post)
+    assert_equal %w[German English], spans.map(&:first)
+    assert_equal post.bytesize, spans.last[2]
+    assert_includes post.byteslice(spans.last[1]...spans.last[2]), "The weather is lovely"
+  end
+
+  def test_markdown_preprocessing
+    post = <<~MARKDOWN
+      ## Frage zum Import
+
+      Ich habe ein **Problem** mit dem [Paket](https://example.com/the-package) und `require "json"`:
+
+      ```ruby
+      def the_method(and_the_other)
+        return the_value if the_value && and_the_other
+      end
+      ```
+
+      > Weiß jemand, warum das nicht funktioniert?
+    MARKDOWN
+    markdown = LinguaRsRb::LanguageDetector.new(languages: %w[English German], preprocess: :markdown)
+
+    assert_equal "German", markdown.detect_language(post)
+    assert_equal :markdown, markdown.to_config_h[:preprocess]
+    only_code = "```\nputs the_value\n```\n`and the`"
+    assert(markdown.compute_language_confidence_values(only_code).all? { |_, confidence| confidence.zero? })
+  end
+
+  def test_junk_stripping_and_minimum_letters
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English German], strip_junk: true, minimum_letters: 5)
+
+    assert_nil detector.detect_language("\u{1F602}\u{1F602} 12345 !!!")
+    assert_equal [], detector.compute_language_confidence_values("\u{1F602} Hey?!")
+    assert_equal [nil, "German"], detector.detect_languages_in_parallel(["\u{1F44D}\u{1F3FD} 42", "Das ist ein Haus"])
+    assert_equal 5, detector.to_config_h[:minimum_letters]
+
+    built = LinguaRsRb::LanguageDetectorBuilder.from_languages(%w[English German])
+                                              .with_junk_stripping
+                                              .with_minimum_letters(3)
+                                              .build
+    assert built.to_config_h[:strip_junk]
+    assert_equal "German", built.detect_language("\u{1F602}\u{1F602} Das ist ein Haus!!! 12345")
+  end
+
+  def test_junk_stripping_keeps_byte_offsets
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English German], strip_junk: true)
+    text = "\u{1F602}\u{1F602} Das ist ein sehr schönes Haus mit einem großen Garten. \u{1F44D}\u{1F3FD} " \
+           "The weather is lovely today and we are going outside."
+
+    spans = detector.detect_multiple_languages(text)
+    assert_equal %w[German English], spans.map(&:first)
+    assert_equal text.bytesize, spans.last[2]
+    assert_includes text.byteslice(spans.last[1]...spans.last[2]), "The weather is lovely"
+  end
+
+  def test_custom_preprocessor
+    seen = []
+    unquote = lambda do |text|
+      seen << text
+      text.gsub(/^>.*$/, "")
+    end
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English German], preprocessor: unquote, strip_urls: true)
+    reply = "> The house is very big and the garden is green\nDas ist ein Haus https://example.com"
+
+    assert_equal "German", detector.detect_language(reply)
+    assert_equal [reply], seen
+    assert_equal %w[German German], detector.detect_languages_in_parallel([reply, reply])
+    refute detector.to_config_h.key?(:preprocessor)
+
+    built = LinguaRsRb::LanguageDetectorBuilder.from_languages(%w[English German])
+                                              .with_preprocessor { |text| text.sub("REDACTED", "") }
+                                              .build
+    assert_equal "German", built.detect_language("REDACTED Das ist ein Haus")
+    assert_raises(TypeError) { LinguaRsRb::LanguageDetectorBuilder.from_all_languages.with_preprocessor(42) }
+    numeric = LinguaRsRb::LanguageDetector.new(languages: %w[English German], preprocessor: ->(_) { 1 })
+    assert_raises(TypeError) { numeric.detect_language("Das ist ein Haus") }
+  end
+
+  def test_max_text_length_sampling
+    document = "#{"Das ist ein Haus. " * 20}#{"The house is very big. " * 200}"
+    head = LinguaRsRb::LanguageDetector.new(languages: %w[English German], max_text_length: 200)
+    stratified = LinguaRsRb::LanguageDetector.new(languages: %w[English German], max_text_length: 200,
+                                                  sampling: :stratified)
+    sentences = LinguaRsRb::LanguageDetector.new(languages: %w[English German], max_text_length: 200,
+                                                 sampling: :random_sentences)
+
+    assert_equal "German", head.detect_language(document)
+    assert_equal "English", head.detect_language("The house is very big.")
+    assert_equal "English", stratified.detect_language(document)
+    assert_equal sentences.detect_language(document), sentences.detect_language(document)
+    assert_equal({ max_text_length: 200, sampling: :stratified },
+                 stratified.to_config_h.slice(:max_text_length, :sampling))
+
+    built = LinguaRsRb::LanguageDetectorBuilder.from_languages(%w[English German])
+                                              .with_max_text_length(100)
+                                              .with_sampling_strategy(:random_sentences)
+                                              .build
+    assert_equal :random_sentences, built.to_config_h[:sampling]
+    assert_raises(ArgumentError) { LinguaRsRb::LanguageDetector.new(max_text_length: 0) }
+    assert_raises(ArgumentError) { LinguaRsRb::LanguageDetector.new(sampling: :tail) }
+  end
+
+  def test_hybrid_accuracy_mode
+    languages = %w[English German Dutch]
+    hybrid = LinguaRsRb::LanguageDetector.new(languages: languages, hybrid_threshold: 0.2)
+    high = LinguaRsRb::LanguageDetector.new(languages: languages)
+    texts = ["Das ist ein Haus", "The house is very big", "Het huis is groot", "Haus", "huis"]
+
+    assert_equal texts.map { |text| high.detect_language(text) }, hybrid.detect_languages_in_parallel(texts)
+    assert_equal high.detect_language("huis"), hybrid.detect_language("huis")
+    assert_in_delta 0.2, hybrid.hybrid_threshold
+    assert_nil high.hybrid_threshold
+    assert_in_delta 0.2, LinguaRsRb::LanguageDetector.load(hybrid.to_config_h).hybrid_threshold
+
+    builder = LinguaRsRb::LanguageDetectorBuilder.from_languages(languages)
+    assert_in_delta 0.1, builder.with_hybrid_accuracy_mode.build.hybrid_threshold
+    assert_raises(ArgumentError) { builder.with_hybrid_accuracy_mode(2.0) }
+  end
+
+  def test_minimum_text_length
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English German], minimum_text_length: 10)
+
+    assert_nil detector.detect_language("  Haus  ")
+    assert_equal [nil, "German", nil], detector.detect_languages_in_parallel(["Haus", "Das ist ein Haus", "house"])
+    assert_equal [], detector.compute_language_confidence_values("Haus")
+    assert_equal 10, detector.to_config_h[:minimum_text_length]
+
+    built = LinguaRsRb::LanguageDetectorBuilder.from_languages(%w[English German]).with_minimum_text_length(3).build
+    assert_equal "German", built.detect_language("Haus")
+  end
+
+  def test_detect_sentences
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English German French])
+    text = "Das ist ein schönes Haus. The weather is lovely today!  Je suis très content.\n"
+
+    sentences = detector.detect_sentences(text)
+    assert_equal %w[German English French], sentences.map(&:first)
+    assert_equal ["Das ist ein schönes Haus.", "The weather is lovely today!", "Je suis très content."],
+                 sentences.map { |_, start, finish| text.byteslice(start...finish) }
+    assert_equal [], detector.detect_sentences("   ")
+  end
+
+  def test_detect_lines
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English German])
+    log = "Das ist ein Haus\r\n\n   \nThe house is very big\n"
+
+    lines = detector.detect_lines(log)
+    assert_equal [[1, "German"], [4, "English"]], lines.map { |number, language, _| [number, language] }
+    assert(lines.all? { |_, _, confidence| confidence.between?(0.0, 1.0) })
+    assert_equal [], detector.detect_lines("\n\n")
+  end
+
+  def test_detect_words
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English German])
+    text = "Ich liebe meinen Hund, but the weather is terrible"
+
+    words = detector.detect_words(text)
+    assert_equal %w[Ich liebe meinen Hund but the weather is terrible], words.map(&:first)
+    assert_equal "Hund", text.byteslice(words[3][2]...words[3][3])
+    smoothed = detector.detect_words(text, window: 2)
+    assert_equal %w[German German English English], (smoothed.first(2) + smoothed.last(2)).map { |word| word[1] }
+    assert_equal [], detector.detect_words("  ,,  ")
+  end
+
+  def test_language_composition
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English German])
+    text = "Das ist ein sehr schönes Haus mit einem großen Garten. The weather is lovely today."
+
+    composition = detector.language_composition(text)
+    assert_equal %w[German English], composition.keys
+    assert_in_delta 1.0, composition.values.sum
+    assert_operator composition["German"], :>, composition["English"]
+    assert_equal({}, detector.language_composition(""))
+  end
+
+  def test_span_confidence
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English German])
+    text = "Das ist ein sehr schönes Haus mit einem großen Garten. The weather is lovely today."
+
+    spans = detector.detect_multiple_languages(text, confidence: true)
+    assert_equal detector.detect_multiple_languages(text), spans.map { |span| span.first(3) }
+    spans.each do |language, start, finish, confidence|
+      assert_in_delta detector.compute_language_confidence(text.byteslice(start...finish), language), confidence
+    end
+    batch = detector.detect_multiple_languages_in_parallel([text], confidence: true)
+    assert_equal [spans], batch
+  end
+
+  def test_script_of
+    assert_equal "Latin", LinguaRsRb.script_of("Hello, world! 123")
+    assert_equal "Cyrillic", LinguaRsRb.script_of("Привет, как дела? OK")
+    assert_equal "Han", LinguaRsRb.script_of("你好，世界")
+    assert_equal "Arabic", LinguaRsRb.script_of("مرحبا بالعالم")
+    assert_nil LinguaRsRb.script_of("123 !!! ...")
+  end
+
+  def test_script_codes_in_results
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English Russian Japanese])
+
+    assert_equal "Latn", detector.detect_language_with_confidence("The house is very big", script: true).last
+    assert_equal "Cyrl", detector.detect_language_with_confidence("Это очень большой дом", script: true).last
+    assert_equal "Jpan", detector.detect_language_with_confidence("これは大きな家です", script: true).last
+    assert_equal 2, detector.detect_language_with_confidence("The house is very big").to_a.size
+    spans = detector.detect_multiple_languages("The house is very big. Это очень большой дом.", script: true)
+    assert_equal [%w[English Latn], %w[Russian Cyrl]], spans.map { |span| [span.first, span.last] }
+    confident = detector.detect_multiple_languages("The house is very big", confidence: true, script: true)
+    assert_kind_of Float, confident.first[3]
+  end
+
+  def test_text_direction_in_results
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English Arabic Hebrew])
+
+    assert_equal :rtl, detector.detect_language_with_confidence("مرحبا بك في بيتنا الكبير", direction: true).last
+    assert_equal :ltr, detector.detect_language_with_confidence("The house is very big", direction: true).last
+    both = detector.detect_language_with_confidence("The house is very big", script: true, direction: true)
+    assert_equal ["English", "Latn", :ltr], [both.first, *both.last(2)]
+    spans = detector.detect_multiple_languages("The house is very big. הבית גדול מאוד ויפה.", direction: true)
+    assert_equal [%w[English ltr], %w[Hebrew rtl]], spans.map { |span| [span.first, span.last.to_s] }
+  end
+
+  def test_extra_result_fields_keep_the_language_confidence
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English Arabic])
+    text = "The house is very big"
+
+    result = detector.detect_language_with_confidence(text, script: true, direction: true, gap: true)
+    assert_kind_of LinguaRsRb::LanguageConfidence, result
+    assert_equal ["English", "Latn", :ltr], [result.language, result.script, result.direction]
+    assert_in_delta detector.confidence_gap(text), result.gap
+    assert_equal [result.language, result.confidence, "Latn", :ltr, result.gap], result.to_a
+    assert_equal({ language: "English", confidence: result.confidence, script: "Latn" },
+                 detector.detect_language_with_confidence(text, script: true).to_h)
+    plain = detector.detect_language_with_confidence(text)
+    assert_nil plain.script
+    assert_nil plain.gap
+    assert_equal plain.confidence, plain.last
+    assert_nil detector.detect_language_with_confidence("", script: true, gap: true)
+  end
+
+  def test_bcp47_language_format
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English Serbian Chinese], language_format: :bcp47)
+
+    assert_equal "en", detector.detect_language("The house is very big and the garden is green")
+    assert_equal "sr-Cyrl", detector.detect_language("Ово је веома велика кућа")
+    assert_equal "zh-Hans", detector.detect_language("这是我们的学校，我们每天都来这里学习")
+    assert_equal "zh-Hant", detector.detect_language("這是我們的學校，我們每天都來這裡學習")
+    assert_equal "en", detector.detect_languages_in_parallel(["The house is very big"]).first
+    assert_includes detector.compute_language_confidence_values("The house is very big").map(&:first), "sr-Latn"
+    assert_equal :bcp47, detector.to_config_h[:language_format]
+
+    built = LinguaRsRb::LanguageDetectorBuilder.from_languages(%w[English German]).with_language_format(:bcp47).build
+    assert_equal [["de", 0, 16]], built.detect_multiple_languages("Das ist ein Haus")
+  end
+
+  def test_display_names
+    assert_equal "Deutsch", LinguaRsRb.display_name("German", locale: "de")
+    assert_equal "allemand", LinguaRsRb.display_name("German", locale: "fr-CA")
+    assert_equal "German", LinguaRsRb.display_name("German")
+    assert_raises(ArgumentError) { LinguaRsRb.display_name("German", locale: "xx") }
+
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English German], language_format: :display_name,
+                                                display_locale: "es")
+    assert_equal "alemán", detector.detect_language("Das ist ein ganz normaler deutscher Satz.")
+    assert_equal "es", detector.to_config_h[:display_locale]
+
+    built = LinguaRsRb::LanguageDetectorBuilder.from_languages(%w[English German]).with_display_names("fr").build
+    assert_equal "anglais", built.detect_language("This is a perfectly ordinary English sentence.")
+  end
+
+  def test_language_merges
+    require "json"
+    require "stringio"
+    merges = { "Bokmal" => "Norwegian", "Nynorsk" => "Norwegian" }
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English Bokmal Nynorsk], merge_languages: merges)
+    text = "Jeg har ikke tid til å snakke med deg i dag, men kanskje i morgen."
+    assert_equal "Norwegian", detector.detect_language(text)
+
+    values = detector.compute_language_confidence_values(text)
+    assert_equal %w[Norwegian English], values.map(&:first)
+    assert_in_delta 1.0, values.sum(&:last), 0.001
+    assert_equal "Norwegian", detector.detect_language_with_confidence(text).first
+    assert_equal merges, detector.to_config_h[:language_merges]
+    norwegian = values.first.confidence
+    assert_in_delta norwegian, detector.compute_language_confidence(text, "Bokmal")
+    assert_in_delta norwegian, detector.compute_language_confidence(text, "Nynorsk")
+    assert_in_delta norwegian, detector.compute_language_confidence_in_parallel([text], "Norwegian").first
+    assert_in_delta norwegian, detector.compute_language_confidence_pairs([[text, "Nynorsk"]]).first
+    assert_equal "Norwegian", detector.detect_language(text, min_confidence: norwegian - 1e-9)
+    assert_in_delta norwegian, detector.detect_languages_keyed({ a: text }, confidence: true)[:a].last
+    io = StringIO.new
+    detector.write_json_lines([text], to: io)
+    assert_in_delta norwegian, JSON.parse(io.string)["confidence"]
+    Dir.mktmpdir do |dir|
+      tsv = File.join(dir, "comments.tsv")
+      File.write(tsv, "a\t#{text}\n")
+      assert_in_delta norwegian, detector.detect_file(tsv, text_column: 1, confidence: true).first.last
+    end
+    fallback = LinguaRsRb::LanguageDetector.new(languages: %w[English Bokmal Nynorsk], merge_languages: merges)
+    fallback.on_fallback(norwegian - 1e-9) { "unsure" }
+    assert_equal "Norwegian", fallback.detect_language(text)
+
+    built = LinguaRsRb::LanguageDetectorBuilder.from_languages(%w[English Bokmal Nynorsk])
+                                               .with_language_merges(Bokmal: "no")
+                                               .build
+    assert_equal "no", built.detect_language(text)
+    bokmal = built.compute_language_confidence_values(text).find { |value| value.language == "no" }
+    assert_in_delta bokmal.confidence, built.compute_language_confidence(text, "no")
+  end
+
+  def test_group_confidence_values
+    assert_includes LinguaRsRb.language_groups["Romance"], "Spanish"
+
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English German Spanish Portuguese Italian])
+    groups = detector.group_confidence_values("Hola")
+    assert_equal %w[Romance Germanic], groups.map(&:first)
+    assert_in_delta 1.0, groups.sum(&:last), 0.001
+
+    custom = detector.group_confidence_values("Hola", groups: { iberian: %w[Spanish Portuguese] })
+    assert_equal ["iberian"], custom.map(&:first)
+    assert_raises(LinguaRsRb::UnknownLanguageError) do
+      detector.group_confidence_values("Hola", groups: { bad: ["Klingon"] })
+    end
+  end
+
+  def test_on_fallback
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English German])
+    assert_same detector, detector.on_fallback { |text| "fallback:#{text}" }
+    assert_equal "fallback:12345", detector.detect_language("12345")
+    assert_equal "German", detector.detect_language("Das ist ein ganz normaler deutscher Satz.")
+
+    detector.on_fallback(1.0) { "unsure" }
+    assert_equal "unsure", detector.detect_language("Rat")
+    assert_raises(ArgumentError) { detector.on_fallback(1.5) { "unsure" } }
+  end
+
+  def test_preprocessors_live_as_long_as_their_owners
+    require "weakref"
+    discarded = Array.new(10) do
+      preprocessor = proc(&:downcase)
+      LinguaRsRb::LanguageDetector.new(languages: %w[English German], preprocessor: preprocessor)
+      LinguaRsRb::LanguageDetectorBuilder.from_languages(%w[English German]).with_preprocessor(preprocessor)
+      WeakRef.new(preprocessor)
+    end
+    german = "Guten Morgen, wie geht es dir?"
+    kept = LinguaRsRb::LanguageDetector.new(languages: %w[English German], preprocessor: proc { german })
+    builder = LinguaRsRb::LanguageDetectorBuilder.from_languages(%w[English German]).with_preprocessor { german }
+    LinguaRsRb.register_profile(:preprocessed, languages: %w[English German], preprocessor: proc { german })
+    GC.start
+    GC.compact if GC.respond_to?(:compact)
+
+    assert(discarded.any? { |preprocessor| !preprocessor.weakref_alive? })
+    assert_equal "German", kept.detect_language("The house is very big")
+    assert_equal "German", builder.build.detect_language("The house is very big")
+    assert_equal "German", LinguaRsRb.profile(:preprocessed).detect_language("The house is very big")
+  end
+
+  def test_replaced_fallback_blocks_are_collected
+    require "weakref"
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English German])
+    replaced = Array.new(10) do |index|
+      callback = proc { "fallback #{index}" }
+      detector.on_fallback(&callback)
+      WeakRef.new(callback)
+    end
+    GC.start
+    GC.compact if GC.respond_to?(:compact)
+
+    assert(replaced.any? { |callback| !callback.weakref_alive? })
+    assert_equal "fallback 9", detector.detect_language("12345")
+  end
+
+  def test_detect_language_bang
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English German])
+    assert_equal "German", detector.detect_language!("Das ist ein ganz normaler deutscher Satz.")
+
+    error = assert_raises(LinguaRsRb::NoLanguageDetectedError) { detector.detect_language!("12345") }
+    assert_equal "no language detected in text of 5 characters (top candidates: none)", error.message
+    assert_kind_of LinguaRsRb::Error, error
+
+    calls = 0
+    counted = LinguaRsRb::LanguageDetector.new(languages: %w[English German], preprocessor: lambda { |text|
+      calls += 1
+      text
+    })
+    assert_raises(LinguaRsRb::NoLanguageDetectedError) { counted.detect_language!("12345 !!!") }
+    assert_equal "German", counted.detect_language!("Das ist ein ganz normaler deutscher Satz.")
+    assert_equal 2, calls
+    assert_equal 2, counted.stats[:detections]
+    assert_equal "English", counted.detect_language!("Das ist ein ganz normaler deutscher Satz.", only: ["English"])
+    assert_equal 3, calls
+  end
+
+  def test_explain_detection
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English German Dutch])
+    explanation = detector.explain_detection("Het is een mooie dag en wij gaan naar het strand.", top: 3)
+    assert_equal "Dutch", explanation[:language]
+    assert_operator explanation[:ngrams].size, :<=, 3
+    refute_empty explanation[:ngrams]
+    margins = explanation[:ngrams].map(&:last)
+    assert_equal margins.sort.reverse, margins
+    assert(margins.all?(&:positive?))
+    assert_nil detector.explain_detection("12345")
+  end
+
+  def test_confidence_calibration
+    raw = LinguaRsRb::LanguageDetector.new(languages: %w[English German Spanish])
+    tempered = LinguaRsRb::LanguageDetector.new(languages: %w[English German Spanish], calibration: 3.0)
+    length_aware = LinguaRsRb::LanguageDetectorBuilder.from_languages(%w[English German Spanish])
+                                                      .with_confidence_calibration(:length_aware)
+                                                      .build
+
+    raw_top = raw.compute_language_confidence_values("Rat").first
+    tempered_top = tempered.compute_language_confidence_values("Rat").first
+    assert_equal raw_top.first, tempered_top.first
+    assert_operator tempered_top.last, :<, raw_top.last
+    assert_in_delta 1.0, tempered.compute_language_confidence_values("Rat").sum(&:last), 0.001
+    assert_operator length_aware.detect_language_with_confidence("Rat").last, :<, raw_top.last
+
+    assert_in_delta 3.0, tempered.to_config_h[:calibration]
+    assert_equal :length_aware, length_aware.to_config_h[:calibration]
+    assert_raises(ArgumentError) { LinguaRsRb::LanguageDetector.new(calibration: -1.0) }
+    assert_raises(ArgumentError) { LinguaRsRb::LanguageDetector.new(calibration: :platt) }
+  end
+
+  def test_single_language_confidences_are_calibrated
+    raw = LinguaRsRb::LanguageDetector.new(languages: %w[English German Spanish])
+    tempered = LinguaRsRb::LanguageDetector.new(languages: %w[English German Spanish], calibration: 3.0)
+    text = "Rat"
+    language = tempered.compute_language_confidence_values(text).first.language
+    expected = tempered.compute_language_confidence_values(text).first.confidence
+
+    assert_in_delta expected, tempered.compute_language_confidence(text, language)
+    assert_in_delta expected, tempered.compute_language_confidence_in_parallel([text], language).first
+    assert_in_delta expected, tempered.compute_language_confidence_pairs([[text, language]]).first
+    assert_operator expected, :<, raw.compute_language_confidence(text, language)
+
+    lines = "Das ist ein Satz.\nThis is a sentence."
+    expected = lines.lines(chomp: true).map { |line| tempered.detect_language_with_confidence(line).to_a }
+    assert_equal expected, tempered.detect_lines(lines).map { |_, label, confidence| [label, confidence] }
+  end
+
+  def test_detect_language_min_confidence
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English German Spanish])
+    sentence = "Das ist ein ganz normaler deutscher Satz."
+    assert_equal "German", detector.detect_language(sentence, min_confidence: 0.5)
+    assert_nil detector.detect_language("Rat", min_confidence: 1.0)
+    assert_nil detector.detect_language("Rat", only: %w[English German], min_confidence: 1.0)
+    assert_equal detector.detect_language("Rat"), detector.detect_language("Rat", min_confidence: 0.0)
+    assert_raises(ArgumentError) { detector.detect_language(sentence, min_confidence: 1.5) }
+  end
+
+  def test_confidence_gap_in_one_call
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English German])
+    text = "This is a long and clearly English sentence about the weather."
+    language, confidence, gap = detector.detect_language_with_confidence(text, gap: true)
+    assert_equal "English", language
+    assert_operator gap, :<=, confidence
+    assert_in_delta detector.confidence_gap(text), gap
+
+    gaps = detector.confidence_gaps_in_parallel([text, "Rat"])
+    assert_in_delta gap, gaps.first
+    assert_in_delta detector.confidence_gap("Rat"), gaps.last
+  end
+
+  def test_analyze
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English German Russian])
+    analysis = detector.analyze("Это совершенно обычное русское предложение.")
+    assert_instance_of LinguaRsRb::DetectionAnalysis, analysis
+    assert_equal "Russian", analysis.language
+    assert analysis.reliable?
+    assert_equal "Cyrl", analysis.script
+    runner_up, runner_up_confidence = analysis.runner_up
+    refute_equal "Russian", runner_up
+    assert_operator runner_up_confidence, :<=, analysis.confidence
+    assert_equal analysis.language, analysis.to_h[:language]
+
+    empty = detector.analyze("12345")
+    assert_nil empty.language
+    refute empty.reliable?
+    assert_nil empty.runner_up
+  end
+
+  def test_analyze_reliability_matches_reliable
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English German], minimum_relative_distance: 0.5,
+                                                calibration: 1000.0)
+    text = "This is a long and clearly English sentence about the weather."
+    analysis = detector.analyze(text)
+
+    assert_equal "English", analysis.language
+    assert_operator detector.confidence_gap(text), :<, 0.5
+    refute detector.reliable?(text)
+    refute analysis.reliable?
+  end
+
+  def test_detect_languages_keyed
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English German])
+    texts = {
+      42 => "This is a perfectly ordinary English sentence.",
+      "row-7" => "Das ist ein ganz normaler deutscher Satz.",
+      nil => "12345"
+    }
+    assert_equal({ 42 => "English", "row-7" => "German", nil => nil }, detector.detect_languages_keyed(texts))
+
+    with_confidence = detector.detect_languages_keyed(texts, confidence: true, chunk_size: 1)
+    assert_equal [42, "row-7", nil], with_confidence.keys
+    assert_equal "German", with_confidence["row-7"].first
+    assert_in_delta detector.compute_language_confidence(texts["row-7"], "German"), with_confidence["row-7"].last
+    assert_nil with_confidence[nil]
+  end
+
+  def test_detect_file
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English German])
+    Dir.mktmpdir do |dir|
+      input = File.join(dir, "comments.csv")
+      File.write(input, <<~CSV)
+        id,text
+        1,"This is a perfectly ordinary English sentence, really."
+        2,Das ist ein ganz normaler deutscher Satz.
+        3,12345
+      CSV
+      output = File.join(dir, "out.csv")
+      assert_equal 3, detector.detect_file(input, text_column: "text", headers: true, output: output, chunk_size: 2)
+      assert_equal ["id,text,language", "1,\"This is a perfectly ordinary English sentence, really.\",English",
+                    "2,Das ist ein ganz normaler deutscher Satz.,German", "3,12345,"],
+                   File.readlines(output, chomp: true)
+
+      tsv = File.join(dir, "comments.tsv")
+      File.write(tsv, "a\tDas ist ein ganz normaler deutscher Satz.\n")
+      assert_equal ["German"], detector.detect_file(tsv, text_column: 1)
+      assert_equal "German", detector.detect_file(tsv, text_column: 1, confidence: true).first.first
+
+      assert_raises(ArgumentError) { detector.detect_file(input, text_column: "body", headers: true) }
+      assert_raises(IOError) { detector.detect_file(File.join(dir, "missing.csv"), text_column: 0) }
+    end
+  end
+
+  def test_write_json_lines
+    require "json"
+    require "stringio"
+
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English German])
+    io = StringIO.new
+    texts = ["This is a perfectly ordinary English sentence.", "12345"]
+    assert_equal 2, detector.write_json_lines(texts, to: io, chunk_size: 1)
+    first, second = io.string.lines.map { |line| JSON.parse(line) }
+    assert_equal %w[id language confidence], first.keys
+    assert_equal [0, "English"], first.values_at("id", "language")
+    assert_in_delta detector.compute_language_confidence(texts.first, "English"), first["confidence"]
+    assert_equal({ "id" => 1, "language" => nil, "confidence" => nil }, second)
+
+    Dir.mktmpdir do |dir|
+      path = File.join(dir, "out.jsonl")
+      detector.write_json_lines({ "a-1" => "Das ist ein ganz normaler deutscher Satz." }, to: path)
+      assert_equal [%w[a-1 German]], File.readlines(path).map { |line| JSON.parse(line).values_at("id", "language") }
+    end
+    assert_raises(TypeError) { detector.write_json_lines(texts, to: 42) }
+  end
+
+  def test_detect_directory
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English German])
+    Dir.mktmpdir do |dir|
+      Dir.mkdir(File.join(dir, "nested"))
+      File.write(File.join(dir, "en.txt"), "This is a perfectly ordinary English sentence.")
+      File.write(File.join(dir, "nested", "de.txt"), "Das ist ein ganz normaler deutscher Satz.")
+      File.write(File.join(dir, "notes.md"), "Das ist ein ganz normaler deutscher Satz.")
+
+      results = detector.detect_directory(File.join(dir, "**", "*.txt"), chunk_size: 1)
+      assert_equal({ File.join(dir, "en.txt") => "English", File.join(dir, "nested", "de.txt") => "German" }, results)
+
+      with_confidence = detector.detect_directory(File.join(dir, "*.txt"), confidence: true)
+      assert_equal "English", with_confidence.fetch(File.join(dir, "en.txt")).first
+      assert_empty detector.detect_directory(File.join(dir, "*.csv"))
+    end
+    assert_raises(ArgumentError) { detector.detect_directory("[") }
+  end
+
+  def test_io_input
+    require "stringio"
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English German])
+    assert_equal "English", detector.detect_language(StringIO.new("This is a perfectly ordinary English sentence."))
+    german = StringIO.new("Das ist ein ganz normaler deutscher Satz.")
+    assert_equal "German", detector.detect_language_with_confidence(german).first
+
+    head = LinguaRsRb::LanguageDetector.new(languages: %w[English German], max_text_length: 40)
+    io = StringIO.new("This is a perfectly ordinary English sentence. #{"Das ist ein deutscher Satz. " * 10_000}")
+    assert_equal "English", head.detect_language(io)
+    assert_operator io.pos, :<, io.size
+
+    Dir.mktmpdir do |dir|
+      path = File.join(dir, "de.txt")
+      File.write(path, "Das ist ein ganz normaler deutscher Satz.")
+      File.open(path) { |file| assert_equal "German", detector.analyze(file).language }
+
+      latin1 = File.join(dir, "latin1.txt")
+      File.binwrite(latin1, "Die Straße vor dem schönen Haus ist für große Autos zu schmal.".encode("ISO-8859-1"))
+      File.open(latin1, "r:ISO-8859-1") { |file| assert_equal "German", detector.detect_language(file) }
+    end
+    assert_raises(TypeError) { detector.detect_language(42) }
+  end
+
+  def test_io_input_for_segmenting_methods
+    require "stringio"
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English German])
+    text = "This is a perfectly ordinary English sentence.\nDas ist ein ganz normaler deutscher Satz."
+
+    assert_equal detector.detect_sentences(text), detector.detect_sentences(StringIO.new(text))
+    assert_equal detector.detect_lines(text), detector.detect_lines(StringIO.new(text))
+    assert_equal detector.detect_words(text, window: 2), detector.detect_words(StringIO.new(text), window: 2)
+    assert_equal "English", LinguaRsRb.detect(StringIO.new("This is a perfectly ordinary English sentence."))
+    assert_raises(TypeError) { detector.detect_lines(42) }
+  end
+
+  def test_cli_detect
+    require "lingua_rs_rb/cli"
+    require "stringio"
+    stdin = StringIO.new(<<~TEXT)
+      This is a perfectly ordinary English sentence.
+      Das ist ein ganz normaler deutscher Satz.
+
+    TEXT
+    stdout = StringIO.new
+    status = LinguaRsRb::CLI.start(%w[detect --languages en,de -], stdin: stdin, stdout: stdout, stderr: StringIO.new)
+    assert_equal 0, status
+    lines = stdout.string.lines(chomp: true)
+    assert_equal %w[English German -], lines.map { |line| line.split("\t").first }
+    assert_match(/\AEnglish\t\d\.\d{4}\z/, lines.first)
+
+    Dir.mktmpdir do |dir|
+      path = File.join(dir, "texts.txt")
+      File.write(path, "Das ist ein ganz normaler deutscher Satz.\n")
+      stdout = StringIO.new
+      LinguaRsRb::CLI.start(["detect", "-l", "English,German", "--no-confidence", path], stdout: stdout)
+      assert_equal "German\n", stdout.string
+
+      stdout = StringIO.new
+      LinguaRsRb::CLI.start(["detect", "-l", "en,German", "--no-confidence", path], stdout: stdout)
+      assert_equal "German\n", stdout.string
+    end
+
+    stderr = StringIO.new
+    assert_equal 1, LinguaRsRb::CLI.start(%w[detect --languages xx], stdin: StringIO.new, stderr: stderr)
+    assert_match(/\Alingua_rs_rb: /, stderr.string)
+  end
+
+  def test_cli_accuracy_report
+    require "lingua_rs_rb/cli"
+    require "stringio"
+    Dir.mktmpdir do |dir|
+      path = File.join(dir, "labeled.tsv")
+      File.write(path, <<~TSV)
+        This is a perfectly ordinary English sentence.\tEnglish
+        Das ist ein ganz normaler deutscher Satz.\tGerman
+        Das Haus ist sehr groß und schön.\tEnglish
+        a line without a label
+      TSV
+      stdout = StringIO.new
+      assert_equal 0, LinguaRsRb::CLI.start(["accuracy-report", "-l", "en,de", path], stdout: stdout)
+      report = stdout.string.lines(chomp: true)
+      assert_equal %w[language precision recall support], report.first.split
+      assert_equal %w[English 1.0000 0.5000 2], report[1].split
+      assert_equal %w[German 0.5000 1.0000 1], report[2].split
+      assert_equal "accuracy: 0.6667 (3 samples)", report.last
+    end
+    assert_equal 1, LinguaRsRb::CLI.start(%w[accuracy-report], stderr: StringIO.new)
+  end
+
+  def test_evaluator
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English German])
+    evaluator = LinguaRsRb::Evaluator.new(detector)
+    assert_same detector, evaluator.detector
+    report = evaluator.evaluate({
+                                  "This is a perfectly ordinary English sentence." => "English",
+                                  "Das ist ein ganz normaler deutscher Satz." => "German",
+                                  "Das Haus ist sehr groß und schön." => :English,
+                                  "12345" => "German"
+                                }, chunk_size: 2)
+    assert_equal 4, report.size
+    assert_in_delta 0.5, report.accuracy
+    assert_in_delta 1.0, report.precision("English")
+    assert_in_delta 0.5, report.recall(:English)
+    assert_in_delta 0.5, report.precision("German")
+    assert_in_delta 0.0, report.precision("French")
+    assert_equal({ precision: 0.5, recall: 0.5, support: 2 }, report.per_language["German"])
+
+    matrix = report.confusion_matrix
+    assert_equal %w[English German], matrix.labels
+    assert_equal [[1, 1], [0, 1]], matrix.to_a
+    assert_equal 1, matrix["German", nil]
+    assert_equal 4, matrix.total
+    assert_equal [0, 1], matrix.undetected
+    assert_equal({ labels: %w[English German], counts: [[1, 1], [0, 1]], undetected: [0, 1] }, matrix.to_h)
+    assert_raises(TypeError) { LinguaRsRb::Evaluator.new("detector") }
+  end
+
+  def test_language_distribution
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English German])
+    texts = ["This is a perfectly ordinary English sentence.", "Das ist ein ganz normaler deutscher Satz.",
+             "The weather is lovely today.", "12345"]
+    distribution = detector.language_distribution(texts.each, chunk_size: 3)
+    assert_equal ["English", "German", nil], distribution.keys
+    assert_equal({ count: 2, percentage: 50.0 }, distribution["English"])
+    assert_equal({ count: 1, percentage: 25.0 }, distribution[nil])
+    assert_empty detector.language_distribution([])
+  end
+
+  def test_detect_arrow
+    begin
+      require "arrow"
+    rescue LoadError
+      skip "red-arrow is not installed"
+    end
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English German])
+    texts = Arrow::StringArray.new(["This is a perfectly ordinary English sentence.", nil,
+                                    "Das ist ein ganz normaler deutscher Satz.", "12345"])
+    languages = detector.detect_arrow(texts, chunk_size: 3)
+    assert_kind_of Arrow::StringArray, languages
+    assert_equal ["English", nil, "German", nil], languages.to_a
+
+    languages, confidences = detector.detect_arrow(texts.slice(2, 2), confidence: true)
+    assert_equal ["German", nil], languages.to_a
+    assert_kind_of Arrow::DoubleArray, confidences
+    assert_nil confidences[1]
+    assert_operator confidences[0], :>, 0.0
+    assert_raises(TypeError) { detector.detect_arrow(["not", "arrow"]) }
+  end
+
+  def test_result_objects_to_json
+    require "json"
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English German])
+    analysis = detector.analyze("This is a perfectly ordinary English sentence.")
+    assert_equal JSON.parse(JSON.generate(analysis.to_h)), JSON.parse(analysis.to_json)
+    assert_equal %w[language confidence reliable script runner_up], JSON.parse(analysis.to_json).keys
+    assert_equal({ "language" => nil }, JSON.parse(JSON.generate(detector.analyze("12345"))).slice("language"))
+
+    report = LinguaRsRb::Evaluator.new(detector).evaluate([["Das ist ein ganz normaler deutscher Satz.", "German"]])
+    assert_equal({ "size" => 1, "accuracy" => 1.0,
+                   "per_language" => { "German" => { "precision" => 1.0, "recall" => 1.0, "support" => 1 } } },
+                 JSON.parse(report.to_json))
+    assert_equal({ "labels" => ["German"], "counts" => [[1]], "undetected" => [0] },
+                 JSON.parse(JSON.generate({ matrix: report.confusion_matrix }))["matrix"])
+  end
+
+  def test_marshal_round_trip
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English German], minimum_relative_distance: 0.1,
+                                                  merge_languages: { German: "Deutsch" }, max_text_length: 200)
+    copy = Marshal.load(Marshal.dump(detector))
+    assert_kind_of LinguaRsRb::LanguageDetector, copy
+    refute_same detector, copy
+    assert_equal detector.to_config_h, copy.to_config_h
+    assert_equal "Deutsch", copy.detect_language("Das ist ein ganz normaler deutscher Satz.")
+    assert_equal [detector.to_config_h], Marshal.load(Marshal.dump([detector])).map(&:to_config_h)
+
+    custom = LinguaRsRb::LanguageDetector.new(languages: %w[English German], preprocessor: lambda(&:strip))
+    assert_raises(TypeError) { Marshal.dump(custom) }
+    detector.on_fallback { "English" }
+    assert_raises(TypeError) { Marshal.dump(detector) }
+  end
+
+  def test_language_confidence_objects
+    require "json"
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English German])
+    result = detector.detect_language_with_confidence("This is a perfectly ordinary English sentence.")
+    assert_kind_of LinguaRsRb::LanguageConfidence, result
+    assert_equal "English", result.language
+    assert_equal [result.language, result.confidence], result.to_a
+    assert_equal result.to_a, result
+    language, confidence = result
+    assert_equal ["English", result.confidence], [language, confidence]
+
+    case result
+    in { language: "English", confidence: Float => matched }
+      assert_in_delta result.confidence, matched
+    end
+    case result
+    in [String => name, Float]
+      assert_equal "English", name
+    end
+
+    values = detector.compute_language_confidence_values("Das ist ein ganz normaler deutscher Satz.")
+    assert_equal values.map(&:confidence).sort, values.sort.map(&:confidence)
+    assert_equal "German", values.max.language
+    assert_equal values.map(&:to_a).to_h, values.to_h
+    low = LinguaRsRb::LanguageConfidence.new("German", 0.25)
+    assert_operator LinguaRsRb::LanguageConfidence.new("English", 0.5), :>, low
+    assert_equal low.hash, LinguaRsRb::LanguageConfidence.new("German", 0.25).hash
+    assert low.eql?(LinguaRsRb::LanguageConfidence.new("German", 0.25))
+    refute low.eql?(["German", 0.25])
+    assert_equal '#<LinguaRsRb::LanguageConfidence language="German" confidence=0.25>', low.inspect
+    assert_equal({ "language" => "German", "confidence" => 0.25 }, JSON.parse(low.to_json))
+  end
+
+  def test_inspect_summarizes_configuration
+    builder = LinguaRsRb::LanguageDetectorBuilder
+              .from_languages(%w[English German French])
+              .with_minimum_relative_distance(0.25)
+              .with_low_accuracy_mode
+    assert_equal "#<LinguaRsRb::LanguageDetectorBuilder languages=3 accuracy=low minimum_relative_distance=0.25 " \
+                 "preload=false>", builder.inspect
+    assert_equal "#<LinguaRsRb::LanguageDetector languages=3 accuracy=low minimum_relative_distance=0.25 " \
+                 "preload=false>", builder.build.inspect
+
+    hybrid = LinguaRsRb::LanguageDetector.new(languages: %w[English German], hybrid_threshold: 0.1)
+    assert_match(/\A#<LinguaRsRb::LanguageDetector languages=2 accuracy=hybrid minimum_relative_distance=0\.0 /,
+                 hybrid.inspect)
+  end
+
+  def test_detector_equality_by_configuration
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English German], minimum_relative_distance: 0.1)
+    same = LinguaRsRb::LanguageDetectorBuilder
+           .from_languages(%w[German English])
+           .with_minimum_relative_distance(0.1)
+           .build
+    other = LinguaRsRb::LanguageDetector.new(languages: %w[English German], low_accuracy: true)
+    assert_equal detector, same
+    assert_equal detector.hash, same.hash
+    refute_equal detector, other
+    refute_equal detector, "detector"
+    assert_equal 2, [detector, same, other].uniq.size
+    assert_equal({ detector => :a, other => :b }, { same => :a, other => :b })
+    assert_equal detector, Marshal.load(Marshal.dump(detector))
+
+    strip = lambda(&:strip)
+    custom = LinguaRsRb::LanguageDetector.new(languages: %w[English German], preprocessor: strip)
+    assert_equal custom, LinguaRsRb::LanguageDetector.new(languages: %w[English German], preprocessor: strip)
+    refute_equal custom, LinguaRsRb::LanguageDetector.new(languages: %w[English German], preprocessor: lambda(&:strip))
+
+    fallback = proc { "English" }
+    guarded = LinguaRsRb::LanguageDetector.new(languages: %w[English German]).on_fallback(0.5, &fallback)
+    same_guard = LinguaRsRb::LanguageDetector.new(languages: %w[English German]).on_fallback(0.5, &fallback)
+    plain = LinguaRsRb::LanguageDetector.new(languages: %w[English German])
+    assert_equal guarded, same_guard
+    assert_equal guarded.hash, same_guard.hash
+    refute_equal guarded, plain
+    refute_equal guarded, LinguaRsRb::LanguageDetector.new(languages: %w[English German]).on_fallback(0.6, &fallback)
+    refute_equal guarded, LinguaRsRb::LanguageDetector.new(languages: %w[English German]).on_fallback(0.5) { "English" }
+  end
+
+  def test_frozen_detector_refuses_mutation
+    detector = LinguaRsRb::LanguageDetector.new(languages: %w[English German]).freeze
+    assert detector.frozen?
+    assert_equal "German", detector.detect_language("Guten Morgen, wie geht es dir?")
+    assert_raises(FrozenError) { detector.unload_language_models }
+    assert_raises(FrozenError) { detector.unload_models_when_idle(1.0) }
+    assert_raises(FrozenError) { detector.unload_models_on_gc(true) }
+    assert_raises(FrozenError) { detector.on_fallback(0.5) { "English" } }
+    detector.clear_result_cache
+    assert_equal "English", detector.detect_language("Good morning, how are you today?")
+    assert Ractor.shareable?(detector) if defined?(Ractor)
+  end
+
+  class TaggedDetector < LinguaRsRb::LanguageDetector
+    attr_reader :options
+
+    def initialize(**options)
+      super
+      @options = options
+    end
+
+    def detect_language(text)
+      super&.upcase
+    end
+  end
+
+  class StrictBuilder < LinguaRsRb::LanguageDetectorBuilder; end
+
+  class RankedConfidence < LinguaRsRb::LanguageConfidence
+    attr_reader :rank
+
+    def initialize(language, confidence)
+      super
+      @rank = (confidence * 10).round
+    end
+  end
+
+  class CountingPool < LinguaRsRb::DetectorPool
+    attr_reader :checkouts
+
+    def initialize(**options)
+      super
+      @checkouts = 0
+    end
+
+    def checkout(...)
+      @checkouts += 1
+      super
+    end
+  end
+
+  class TaggedEvaluator < LinguaRsRb::Evaluator
+    attr_reader :tag
+
+    def initialize(detector)
+      super
+      @tag = :tagged
+    end
+  end
+
+  def test_subclassing_wrapped_classes
+    detector = TaggedDetector.new(languages: %w[English German])
+    assert_instance_of TaggedDetector, detector
+    assert_equal({ languages: %w[English German] }, detector.options)
+    assert_equal "GERMAN", detector.detect_language("Guten Morgen, wie geht es dir?")
+    assert_instance_of TaggedDetector, TaggedDetector.load(detector.to_config_h)
+    assert_instance_of TaggedDetector, Marshal.load(Marshal.dump(detector))
+    assert_raises(TypeError) { TaggedDetector.allocate }
+
+    builder = StrictBuilder.from_languages(%w[English German])
+    assert_instance_of StrictBuilder, builder
+    assert_instance_of StrictBuilder, builder.with_low_accuracy_mode.dup
+
+    built = builder.build
+    built.extend(Module.new { def detect_language(text) = super || "unknown" })
+    assert_equal "unknown", built.detect_language("")
+  end
+
+  def test_subclassing_value_and_helper_classes
+    confidence = RankedConfidence.new("English", 0.82)
+    assert_instance_of RankedConfidence, confidence
+    assert_equal ["English", 0.82, 8], [confidence.language, confidence.confidence, confidence.rank]
+
+    pool = CountingPool.new(size: 1, languages: %w[English German])
+    assert_instance_of CountingPool, pool
+    assert_equal "German", pool.with { |detector| detector.detect_language("Guten Morgen, wie geht es dir?") }
+    detector = pool.checkout
+    pool.checkin(detector)
+    assert_equal 1, pool.checkouts
+
+    evaluator = TaggedEvaluator.new(detector)
+    assert_instance_of TaggedEvaluator, evaluator
+    assert_equal :tagged, evaluator.tag
+    assert_same detector, evaluator.detector
+  end
+
+  def test_languages_by_additional_scripts
+    assert_equal %w[Chinese Japanese], LinguaRsRb.languages_with_han_script
+    assert_equal %w[Korean], LinguaRsRb.languages_with_hangul_script
+    assert_equal %w[Greek], LinguaRsRb.languages_with_greek_script
+    assert_equal %w[Hebrew], LinguaRsRb.languages_with_hebrew_script
+    assert_equal %w[Japanese], LinguaRsRb.languages_with_katakana_script
+    assert_equal %w[Punjabi], LinguaRsRb.languages_with_gurmukhi_script
+    assert_equal %w[Thai], LinguaRsRb.languages_with_thai_script
+    refute_includes LinguaRsRb.languages_with_latin_script, "Chinese"
+  end
+
   def test_builder_distance_validation
     builder = LinguaRsRb::LanguageDetectorBuilder.from_all_languages
     assert_raises(ArgumentError) do